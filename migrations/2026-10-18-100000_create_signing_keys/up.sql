@@ -0,0 +1,15 @@
+-- JWT signing keys when JWT_KEY_SOURCE=database.
+-- status is 'active' (signs and verifies), 'verify' (only verifies) or 'retired'.
+CREATE TABLE signing_keys (
+    kid VARCHAR PRIMARY KEY,
+    algorithm VARCHAR NOT NULL,
+    private_key TEXT NOT NULL,
+    public_key TEXT,
+    status VARCHAR NOT NULL DEFAULT 'verify',
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+    activated_at TIMESTAMP,
+    retired_at TIMESTAMP
+);
+
+-- Only one key can sign at a time
+CREATE UNIQUE INDEX idx_signing_keys_active ON signing_keys (status) WHERE status = 'active';
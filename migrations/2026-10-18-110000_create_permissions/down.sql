@@ -0,0 +1,4 @@
+DROP TABLE role_permissions;
+DROP TABLE permissions;
+ALTER TABLE users DROP CONSTRAINT fk_users_role;
+DROP TABLE roles;
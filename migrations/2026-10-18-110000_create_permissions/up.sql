@@ -0,0 +1,35 @@
+-- Roles mirror the UserRole enum, the id is the value stored in users.role.
+CREATE TABLE roles (
+    id INTEGER PRIMARY KEY,
+    name VARCHAR NOT NULL UNIQUE
+);
+
+INSERT INTO roles (id, name) VALUES (0, 'Guest'), (1, 'User'), (2, 'Admin');
+
+ALTER TABLE users ADD CONSTRAINT fk_users_role FOREIGN KEY (role) REFERENCES roles (id);
+
+-- Named permissions such as 'users:read', embedded in the permissions claim of access tokens.
+CREATE TABLE permissions (
+    name VARCHAR PRIMARY KEY,
+    description TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+);
+
+CREATE TABLE role_permissions (
+    role_id INTEGER NOT NULL REFERENCES roles (id) ON DELETE CASCADE,
+    permission VARCHAR NOT NULL REFERENCES permissions (name) ON DELETE CASCADE ON UPDATE CASCADE,
+    PRIMARY KEY (role_id, permission)
+);
+
+INSERT INTO permissions (name, description) VALUES
+    ('users:read', 'Look up users'),
+    ('users:write', 'Create and update users'),
+    ('users:delete', 'Delete users');
+
+INSERT INTO role_permissions (role_id, permission) VALUES
+    (1, 'users:read'),
+    (1, 'users:write'),
+    (1, 'users:delete'),
+    (2, 'users:read'),
+    (2, 'users:write'),
+    (2, 'users:delete');
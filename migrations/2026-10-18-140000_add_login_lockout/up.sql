@@ -0,0 +1,3 @@
+-- Consecutive failed logins and the resulting lockout, reset by a successful login.
+ALTER TABLE users ADD COLUMN failed_login_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN locked_until TIMESTAMP;
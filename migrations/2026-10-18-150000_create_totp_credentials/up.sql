@@ -0,0 +1,22 @@
+-- TOTP second factor, confirmed_at is set once the user proved the authenticator works.
+CREATE TABLE totp_credentials (
+    user_id UUID PRIMARY KEY,
+    secret VARCHAR NOT NULL,
+    confirmed_at TIMESTAMP,
+    -- Codes can only be used once, codes from this time step or earlier are rejected
+    last_used_step BIGINT,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+-- Single use codes for when the authenticator is lost, only their SHA-256 hash is stored.
+CREATE TABLE mfa_recovery_codes (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL,
+    code_hash VARCHAR NOT NULL,
+    used_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_mfa_recovery_codes_user_id ON mfa_recovery_codes (user_id);
@@ -0,0 +1,30 @@
+-- Public keys of registered WebAuthn authenticators (passkeys).
+CREATE TABLE webauthn_credentials (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL,
+    -- base64url credential id chosen by the authenticator
+    credential_id VARCHAR NOT NULL UNIQUE,
+    -- DER encoded SubjectPublicKeyInfo
+    public_key BYTEA NOT NULL,
+    -- COSE algorithm identifier, -7 for ES256 and -257 for RS256
+    algorithm INTEGER NOT NULL,
+    sign_count BIGINT NOT NULL DEFAULT 0,
+    name VARCHAR,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+    last_used_at TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_webauthn_credentials_user_id ON webauthn_credentials (user_id);
+
+-- Outstanding registration and authentication challenges, deleted once used.
+CREATE TABLE webauthn_challenges (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    -- Empty for authentication with a discoverable credential
+    user_id UUID,
+    challenge_hash VARCHAR NOT NULL UNIQUE,
+    ceremony VARCHAR NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
@@ -0,0 +1,27 @@
+-- Accounts at external OpenID Connect providers linked to local users.
+CREATE TABLE user_identities (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL,
+    provider VARCHAR NOT NULL,
+    -- `sub` claim of the provider's ID tokens, stable for the account at that provider
+    subject VARCHAR NOT NULL,
+    email VARCHAR,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+    last_login_at TIMESTAMP,
+    UNIQUE (provider, subject),
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_user_identities_user_id ON user_identities (user_id);
+
+-- Authorization requests waiting for the provider's callback, deleted once used.
+CREATE TABLE oidc_login_states (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    state_hash VARCHAR NOT NULL UNIQUE,
+    provider VARCHAR NOT NULL,
+    nonce VARCHAR NOT NULL,
+    -- PKCE code verifier, sent with the authorization code
+    code_verifier VARCHAR NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+);
@@ -0,0 +1,6 @@
+ALTER TABLE refresh_tokens
+    DROP COLUMN scope,
+    DROP COLUMN client_id;
+DROP TABLE oauth_consents;
+DROP TABLE oauth_authorization_codes;
+DROP TABLE oauth_clients;
@@ -0,0 +1,51 @@
+-- Applications that use this service as their OAuth2 authorization server.
+CREATE TABLE oauth_clients (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    client_id VARCHAR NOT NULL UNIQUE,
+    -- SHA-256 of the client secret, empty for public clients that rely on PKCE alone
+    client_secret_hash VARCHAR,
+    name VARCHAR NOT NULL,
+    -- Space separated, like scopes in OAuth requests
+    redirect_uris VARCHAR NOT NULL DEFAULT '',
+    scopes VARCHAR NOT NULL DEFAULT '',
+    grant_types VARCHAR NOT NULL DEFAULT '',
+    -- First party clients are trusted and never ask users for consent
+    first_party BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp
+);
+
+-- Single use authorization codes, only their SHA-256 hash is stored.
+CREATE TABLE oauth_authorization_codes (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    code_hash VARCHAR NOT NULL UNIQUE,
+    client_id UUID NOT NULL,
+    user_id UUID NOT NULL,
+    redirect_uri VARCHAR NOT NULL,
+    scope VARCHAR NOT NULL,
+    code_challenge VARCHAR NOT NULL,
+    nonce VARCHAR,
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+    FOREIGN KEY (client_id) REFERENCES oauth_clients (id) ON DELETE CASCADE,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+-- Scopes a user allowed a client to access.
+CREATE TABLE oauth_consents (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL,
+    client_id UUID NOT NULL,
+    scopes VARCHAR NOT NULL DEFAULT '',
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+    updated_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+    UNIQUE (user_id, client_id),
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE,
+    FOREIGN KEY (client_id) REFERENCES oauth_clients (id) ON DELETE CASCADE
+);
+
+-- Refresh tokens issued to OAuth clients carry the client and the granted scope, those
+-- issued by /auth/login have neither.
+ALTER TABLE refresh_tokens
+    ADD COLUMN client_id UUID REFERENCES oauth_clients (id) ON DELETE CASCADE,
+    ADD COLUMN scope VARCHAR;
@@ -0,0 +1,17 @@
+-- One row per login. The session id doubles as the family id of the refresh tokens
+-- issued for it, and access tokens carry it in their sid claim.
+CREATE TABLE sessions (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL,
+    -- Browser and operating system derived from the user agent, e.g. Firefox on Linux
+    device VARCHAR,
+    user_agent VARCHAR,
+    ip_address VARCHAR,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+    last_seen_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+    revoked_at TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+);
+
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
+CREATE INDEX sessions_revoked_at_idx ON sessions (revoked_at);
@@ -0,0 +1,14 @@
+-- Audit trail of admins acting as other users. There are no foreign keys so the
+-- record outlives deleted accounts.
+CREATE TABLE impersonations (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    actor_id UUID NOT NULL,
+    user_id UUID NOT NULL,
+    reason VARCHAR NOT NULL,
+    -- jti of the issued token, so requests in the logs can be traced back to this record
+    token_id UUID NOT NULL UNIQUE,
+    created_at TIMESTAMP NOT NULL DEFAULT current_timestamp,
+    expires_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX impersonations_created_at_idx ON impersonations (created_at);
@@ -0,0 +1,5 @@
+DROP INDEX users_email_trgm_idx;
+DROP INDEX users_username_trgm_idx;
+DROP INDEX users_search_vector_idx;
+ALTER TABLE users DROP COLUMN search_vector;
+DROP EXTENSION IF EXISTS pg_trgm;
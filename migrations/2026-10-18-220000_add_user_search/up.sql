@@ -0,0 +1,13 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- Words of the username and email address, e.g. jane.doe@example.com becomes jane, doe,
+-- example and com. The simple configuration doesn't stem names.
+ALTER TABLE users ADD COLUMN search_vector TSVECTOR NOT NULL GENERATED ALWAYS AS (
+    to_tsvector('simple', username || ' ' || translate(email, '@.-_+', '     '))
+) STORED;
+
+CREATE INDEX users_search_vector_idx ON users USING GIN (search_vector);
+
+-- Trigram indexes find usernames and email addresses with typos
+CREATE INDEX users_username_trgm_idx ON users USING GIN (username gin_trgm_ops);
+CREATE INDEX users_email_trgm_idx ON users USING GIN (email gin_trgm_ops);
@@ -0,0 +1,2 @@
+-- Words of the username alone, for callers that may not search email addresses
+CREATE INDEX users_username_search_idx ON users USING GIN (to_tsvector('simple', username));
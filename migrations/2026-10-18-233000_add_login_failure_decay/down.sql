@@ -0,0 +1,2 @@
+DROP INDEX users_lower_email_idx;
+ALTER TABLE users DROP COLUMN last_failed_login_at;
@@ -0,0 +1,4 @@
+-- Failed logins are forgotten after a quiet period, counted from the last failure.
+ALTER TABLE users ADD COLUMN last_failed_login_at TIMESTAMP;
+-- Lockouts are looked up by the normalized email the login was attempted with
+CREATE INDEX users_lower_email_idx ON users (lower(email));
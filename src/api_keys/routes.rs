@@ -0,0 +1,130 @@
+use actix_web::{delete, get, post, web, HttpResponse, Responder};
+use log::error;
+use uuid::Uuid;
+
+use crate::api_keys::service::{
+    create_api_key, delete_api_key, find_api_keys_for_user, ApiKeyError,
+};
+use crate::authentication::guard::{roles, AllowImpersonation, RequireRole};
+use crate::common::model::AppError;
+use crate::database::model::api_keys::{ApiKeyResponse, CreateApiKeyRequest};
+use crate::database::{model::db::DbPool, tools::get_connection};
+use crate::users::service::find_user_by_id;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(find_api_keys_handler);
+    cfg.service(create_api_key_handler);
+    cfg.service(delete_api_key_handler);
+}
+
+fn user_id(sub: &str) -> Result<Uuid, AppError> {
+    Uuid::parse_str(sub).map_err(|_| AppError::UnauthorizedError("Invalid token".to_string()))
+}
+
+// Find the API keys of the current user
+#[utoipa::path(
+    path = "/api/api-keys",
+    responses(
+        (status = 200, description = "Successful response", body = Vec<ApiKeyResponse>),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Not available to API keys"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "findApiKeys"
+)]
+#[get("/api-keys")]
+async fn find_api_keys_handler(
+    pool: web::Data<DbPool>,
+    claims: AllowImpersonation<roles::Guest>,
+) -> Result<impl Responder, AppError> {
+    let owner_id = user_id(&claims.sub)?;
+    let mut conn = get_connection(pool);
+
+    match find_api_keys_for_user(&mut conn, owner_id) {
+        Ok(api_keys) => Ok(HttpResponse::Ok().json(
+            api_keys
+                .into_iter()
+                .map(ApiKeyResponse::from)
+                .collect::<Vec<_>>(),
+        )),
+        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+}
+
+// Create an API key, the key itself is only shown once. Keys outlive sessions, so they
+// need a verified email address, which also rules out Guests.
+#[utoipa::path(
+    path = "/api/api-keys",
+    request_body = CreateApiKeyRequest,
+    responses(
+        (status = 200, description = "API key created. Send it as `Authorization: ApiKey <key>` or in the X-API-Key header.", body = ApiKeyResponse),
+        (status = 400, description = "Invalid name, lifetime or scopes"),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Not available to API keys or to accounts that haven't verified their email address"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "createApiKey"
+)]
+#[post("/api-keys")]
+async fn create_api_key_handler(
+    pool: web::Data<DbPool>,
+    req_body: web::Json<CreateApiKeyRequest>,
+    claims: RequireRole<roles::User>,
+) -> Result<impl Responder, AppError> {
+    let owner_id = user_id(&claims.sub)?;
+    let mut conn = get_connection(pool);
+    let user = match find_user_by_id(&mut conn, owner_id) {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(AppError::NotFoundError("User not found".to_string())),
+        Err(_) => return Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    };
+    // Accounts created by an admin or with a changed email address can be unverified
+    if user.email_verified_at.is_none() {
+        return Err(AppError::ForbiddenError(
+            "Verify your email address before creating API keys".to_string(),
+        ));
+    }
+
+    match create_api_key(&mut conn, &user, req_body.into_inner()) {
+        Ok((api_key, key)) => Ok(HttpResponse::Ok().json(ApiKeyResponse {
+            key: Some(key),
+            ..ApiKeyResponse::from(api_key)
+        })),
+        Err(ApiKeyError::Validation(message)) => Err(AppError::ValidationError(message)),
+        Err(ApiKeyError::Database(e)) => {
+            error!("{:?}", e);
+            Err(AppError::DatabaseError("Internal Server Error".to_string()))
+        }
+    }
+}
+
+// Delete an API key of the current user, requests made with it fail from now on
+#[utoipa::path(
+    path = "/api/api-keys/{id}",
+    responses(
+        (status = 200, description = "API key deleted"),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Not available to API keys"),
+        (status = 404, description = "API key not found"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "deleteApiKey"
+)]
+#[delete("/api-keys/{id}")]
+async fn delete_api_key_handler(
+    pool: web::Data<DbPool>,
+    key_id: web::Path<Uuid>,
+    claims: RequireRole<roles::Guest>,
+) -> Result<impl Responder, AppError> {
+    let owner_id = user_id(&claims.sub)?;
+    let mut conn = get_connection(pool);
+
+    match delete_api_key(&mut conn, owner_id, key_id.into_inner()) {
+        Ok(0) => Err(AppError::NotFoundError("API key not found".to_string())),
+        Ok(_) => Ok(HttpResponse::Ok().finish()),
+        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+}
@@ -0,0 +1,326 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{pg::PgConnection, prelude::*, result::QueryResult};
+use openssl::rand::rand_bytes;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{jwt::services::api_key_claims, model::Claims},
+    common::crypto::{generate_opaque_token, hash_token, to_hex},
+    database::model::{
+        api_keys::{ApiKey, CreateApiKeyDb, CreateApiKeyRequest},
+        users::User,
+    },
+    permissions::service::find_permissions_for_role,
+    schema::{api_keys, user_token_revocations},
+    users::service::find_user_by_id,
+};
+
+// Keys look like rja_1a2b3c4d_<secret>, the part before the secret is the visible prefix
+const KEY_PREFIX: &str = "rja";
+const PREFIX_BYTES: usize = 4;
+const DEFAULT_LIFETIME_DAYS: i64 = 90;
+const MAX_LIFETIME_DAYS: i64 = 365;
+// last_used_at is only kept to this precision, so busy keys don't write on every request
+const LAST_USED_INTERVAL_MINUTES: i64 = 5;
+
+#[derive(Debug)]
+pub enum ApiKeyError {
+    Validation(String),
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for ApiKeyError {
+    fn from(e: diesel::result::Error) -> Self {
+        ApiKeyError::Database(e)
+    }
+}
+
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+// The key of a request, from `Authorization: ApiKey <key>` or the `X-API-Key` header
+pub fn parse_api_key<'a>(
+    authorization: Option<&'a str>,
+    x_api_key: Option<&'a str>,
+) -> Option<&'a str> {
+    authorization
+        .and_then(|header| header.strip_prefix("ApiKey "))
+        .or(x_api_key)
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+}
+
+fn generate_api_key() -> (String, String) {
+    let mut buf = [0u8; PREFIX_BYTES];
+    rand_bytes(&mut buf).expect("Error: Unable to generate random bytes");
+    let prefix = format!("{}_{}", KEY_PREFIX, to_hex(&buf));
+    let key = format!("{}_{}", prefix, generate_opaque_token());
+    (prefix, key)
+}
+
+// Create a key for the user, the plain key is only returned here
+pub fn create_api_key(
+    conn: &mut PgConnection,
+    user: &User,
+    request: CreateApiKeyRequest,
+) -> Result<(ApiKey, String), ApiKeyError> {
+    if request.name.trim().is_empty() {
+        return Err(ApiKeyError::Validation("The key needs a name".to_string()));
+    }
+    let lifetime_days = request.expires_in_days.unwrap_or(DEFAULT_LIFETIME_DAYS);
+    if !(1..=MAX_LIFETIME_DAYS).contains(&lifetime_days) {
+        return Err(ApiKeyError::Validation(format!(
+            "Keys expire after 1 to {} days",
+            MAX_LIFETIME_DAYS
+        )));
+    }
+    if request.scopes.is_empty() {
+        return Err(ApiKeyError::Validation(
+            "The key needs at least one scope".to_string(),
+        ));
+    }
+    let permissions = find_permissions_for_role(conn, user.role)?;
+    if let Some(scope) = request
+        .scopes
+        .iter()
+        .find(|scope| !permissions.contains(scope))
+    {
+        return Err(ApiKeyError::Validation(format!(
+            "Your role doesn't have the permission {}",
+            scope
+        )));
+    }
+
+    let (prefix, key) = generate_api_key();
+    let api_key = diesel::insert_into(api_keys::table)
+        .values(CreateApiKeyDb {
+            user_id: user.id,
+            name: request.name,
+            prefix,
+            key_hash: hash_token(&key),
+            scopes: request.scopes.join(" "),
+            expires_at: now() + Duration::days(lifetime_days),
+        })
+        .returning(ApiKey::as_returning())
+        .get_result(conn)?;
+
+    Ok((api_key, key))
+}
+
+pub fn find_api_keys_for_user(conn: &mut PgConnection, owner_id: Uuid) -> QueryResult<Vec<ApiKey>> {
+    api_keys::table
+        .filter(api_keys::user_id.eq(owner_id))
+        .order(api_keys::created_at.asc())
+        .select(ApiKey::as_select())
+        .load(conn)
+}
+
+pub fn delete_api_key(conn: &mut PgConnection, owner_id: Uuid, key_id: Uuid) -> QueryResult<usize> {
+    diesel::delete(
+        api_keys::table
+            .filter(api_keys::id.eq(key_id))
+            .filter(api_keys::user_id.eq(owner_id)),
+    )
+    .execute(conn)
+}
+
+// Whether the user revoked all their tokens, by logging out everywhere or resetting the
+// password, after the key was created
+fn revoked_with_user_tokens(conn: &mut PgConnection, api_key: &ApiKey) -> QueryResult<bool> {
+    let revoked_before = user_token_revocations::table
+        .find(api_key.user_id)
+        .select(user_token_revocations::revoked_before)
+        .first::<NaiveDateTime>(conn)
+        .optional()?;
+    Ok(revoked_before.is_some_and(|revoked_before| api_key.created_at < revoked_before))
+}
+
+fn needs_last_used_update(last_used_at: Option<NaiveDateTime>) -> bool {
+    last_used_at.is_none_or(|last_used_at| {
+        last_used_at <= now() - Duration::minutes(LAST_USED_INTERVAL_MINUTES)
+    })
+}
+
+// Claims for a request made with an API key, None for unknown, expired or revoked keys.
+// The key carries the permissions of its scopes the user's role still has.
+pub fn authenticate_api_key(conn: &mut PgConnection, key: &str) -> QueryResult<Option<Claims>> {
+    let api_key = match api_keys::table
+        .filter(api_keys::key_hash.eq(hash_token(key)))
+        .filter(api_keys::expires_at.gt(now()))
+        .select(ApiKey::as_select())
+        .first(conn)
+        .optional()?
+    {
+        Some(api_key) => api_key,
+        None => return Ok(None),
+    };
+    let user = match find_user_by_id(conn, api_key.user_id)? {
+        Some(user) => user,
+        None => return Ok(None),
+    };
+    if revoked_with_user_tokens(conn, &api_key)? {
+        return Ok(None);
+    }
+
+    if needs_last_used_update(api_key.last_used_at) {
+        diesel::update(api_keys::table.find(api_key.id))
+            .set(api_keys::last_used_at.eq(now()))
+            .execute(conn)?;
+    }
+
+    let permissions = find_permissions_for_role(conn, user.role)?
+        .into_iter()
+        .filter(|permission| {
+            api_key
+                .scopes
+                .split_whitespace()
+                .any(|scope| scope == permission)
+        })
+        .collect();
+
+    Ok(Some(api_key_claims(
+        &user.id.to_string(),
+        user.role,
+        permissions,
+        api_key.id,
+        &api_key.scopes,
+        api_key.created_at.and_utc().timestamp() as usize,
+        api_key.expires_at.and_utc().timestamp() as usize,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use diesel::prelude::*;
+
+    use super::{
+        authenticate_api_key, create_api_key, generate_api_key, needs_last_used_update, now,
+        parse_api_key,
+    };
+    use crate::{
+        authentication::revocation::services::RevocationStore,
+        database::{
+            model::{
+                api_keys::{ApiKey, CreateApiKeyRequest},
+                db::DbPool,
+                users::{User, UserRole},
+            },
+            tools::testing::{create_test_user, test_pool},
+        },
+        schema::api_keys,
+        users::service::delete_user,
+    };
+
+    #[test]
+    fn reads_key_from_either_header() {
+        assert_eq!(
+            parse_api_key(Some("ApiKey rja_1_abc"), None),
+            Some("rja_1_abc")
+        );
+        assert_eq!(parse_api_key(None, Some(" rja_1_abc ")), Some("rja_1_abc"));
+        assert_eq!(
+            parse_api_key(Some("Bearer eyJ"), Some("rja_1_abc")),
+            Some("rja_1_abc")
+        );
+        assert_eq!(parse_api_key(Some("Bearer eyJ"), None), None);
+        assert_eq!(parse_api_key(Some("ApiKey "), None), None);
+    }
+
+    #[test]
+    fn key_starts_with_its_prefix() {
+        let (prefix, key) = generate_api_key();
+        assert!(prefix.starts_with("rja_"));
+        assert_eq!(prefix.len(), 4 + 8);
+        assert!(key.starts_with(&format!("{}_", prefix)));
+        assert_ne!(generate_api_key().1, key);
+    }
+
+    #[test]
+    fn last_use_is_recorded_once_per_interval() {
+        assert!(needs_last_used_update(None));
+        assert!(needs_last_used_update(Some(now() - Duration::minutes(6))));
+        assert!(!needs_last_used_update(Some(now() - Duration::minutes(1))));
+    }
+
+    fn create_test_key(pool: &DbPool, user: &User, scopes: &[&str]) -> (ApiKey, String) {
+        create_api_key(
+            &mut pool.get().unwrap(),
+            user,
+            CreateApiKeyRequest {
+                name: "CI".to_string(),
+                scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+                expires_in_days: None,
+            },
+        )
+        .expect("Failed to create test key.")
+    }
+
+    fn last_used_at(pool: &DbPool, api_key: &ApiKey) -> Option<chrono::NaiveDateTime> {
+        api_keys::table
+            .find(api_key.id)
+            .select(api_keys::last_used_at)
+            .first(&mut pool.get().unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    fn keys_only_carry_their_scopes() {
+        let pool = test_pool();
+        let user = create_test_user(&pool, UserRole::User);
+        let (api_key, key) = create_test_key(&pool, &user, &["users:read"]);
+
+        let claims = authenticate_api_key(&mut pool.get().unwrap(), &key).unwrap();
+        let first_use = last_used_at(&pool, &api_key);
+        authenticate_api_key(&mut pool.get().unwrap(), &key).unwrap();
+        let second_use = last_used_at(&pool, &api_key);
+        let unknown = authenticate_api_key(&mut pool.get().unwrap(), "rja_00000000_nope").unwrap();
+        diesel::update(api_keys::table.find(api_key.id))
+            .set(api_keys::expires_at.eq(now() - Duration::seconds(1)))
+            .execute(&mut pool.get().unwrap())
+            .unwrap();
+        let expired = authenticate_api_key(&mut pool.get().unwrap(), &key).unwrap();
+        delete_user(&mut pool.get().unwrap(), user.id).unwrap();
+
+        let claims = claims.expect("The key should authenticate");
+        assert_eq!(claims.sub, user.id.to_string());
+        assert_eq!(claims.jti, api_key.id);
+        assert_eq!(claims.permissions, vec!["users:read".to_string()]);
+        assert_eq!(
+            claims.iat,
+            api_key.created_at.and_utc().timestamp() as usize
+        );
+        assert!(first_use.is_some());
+        assert_eq!(first_use, second_use);
+        assert!(unknown.is_none());
+        assert!(expired.is_none());
+    }
+
+    #[test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    fn revoking_all_tokens_revokes_older_keys() {
+        let pool = test_pool();
+        let user = create_test_user(&pool, UserRole::User);
+        let (old_api_key, old_key) = create_test_key(&pool, &user, &["users:read"]);
+        // Revocations are kept in whole seconds, move the key clear of the current one
+        diesel::update(api_keys::table.find(old_api_key.id))
+            .set(api_keys::created_at.eq(now() - Duration::minutes(1)))
+            .execute(&mut pool.get().unwrap())
+            .unwrap();
+
+        RevocationStore::new(pool.clone())
+            .revoke_all_for_user(user.id)
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        let (_, new_key) = create_test_key(&pool, &user, &["users:read"]);
+
+        let old = authenticate_api_key(&mut pool.get().unwrap(), &old_key).unwrap();
+        let new = authenticate_api_key(&mut pool.get().unwrap(), &new_key).unwrap();
+        delete_user(&mut pool.get().unwrap(), user.id).unwrap();
+
+        assert!(old.is_none());
+        assert!(new.is_some());
+    }
+}
@@ -0,0 +1,251 @@
+use std::env;
+
+use actix_web::{
+    cookie::{time::Duration, Cookie, SameSite},
+    http::Method,
+    HttpRequest, HttpResponseBuilder,
+};
+use lazy_static::lazy_static;
+use openssl::memcmp;
+
+use crate::{
+    common::crypto::{generate_opaque_token, hash_token},
+    JWT_LIFETIME, OIDC_LOGIN_LIFETIME, REFRESH_TOKEN_LIFETIME,
+};
+
+pub const ACCESS_TOKEN_COOKIE: &str = "access_token";
+pub const REFRESH_TOKEN_COOKIE: &str = "refresh_token";
+// Readable by the web app, which echoes it in the CSRF header
+pub const CSRF_TOKEN_COOKIE: &str = "csrf_token";
+pub const CSRF_TOKEN_HEADER: &str = "X-CSRF-Token";
+// The refresh token is only sent to the routes that use it
+const REFRESH_TOKEN_PATH: &str = "/auth";
+// Hash of the state of an OpenID Connect login, set in both token transports
+pub const OIDC_STATE_COOKIE: &str = "oidc_state";
+const OIDC_STATE_PATH: &str = "/auth/oidc";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenTransport {
+    // Tokens in the Authorization and Refresh-Token headers
+    Header,
+    // Tokens in HttpOnly cookies, with a double submit CSRF token
+    Cookie,
+}
+
+struct CookieSettings {
+    transport: TokenTransport,
+    secure: bool,
+    same_site: SameSite,
+}
+
+lazy_static! {
+    static ref COOKIE_SETTINGS: CookieSettings = settings_from_env()
+        .unwrap_or_else(|e| panic!("Invalid token transport configuration: {}", e));
+}
+
+// AUTH_TOKEN_TRANSPORT selects 'header' (default) or 'cookie'. Cookies are Secure unless
+// AUTH_COOKIE_SECURE is false and SameSite=Lax unless AUTH_COOKIE_SAME_SITE is strict.
+fn settings_from_env() -> Result<CookieSettings, String> {
+    let transport = match env::var("AUTH_TOKEN_TRANSPORT").as_deref() {
+        Ok("header") | Err(_) => TokenTransport::Header,
+        Ok("cookie") => TokenTransport::Cookie,
+        Ok(other) => return Err(format!("Unknown AUTH_TOKEN_TRANSPORT {}", other)),
+    };
+    let secure = match env::var("AUTH_COOKIE_SECURE").as_deref() {
+        Ok("true") | Err(_) => true,
+        Ok("false") => false,
+        Ok(_) => return Err("AUTH_COOKIE_SECURE must be true or false".to_string()),
+    };
+    let same_site = match env::var("AUTH_COOKIE_SAME_SITE").as_deref() {
+        Ok("lax") | Err(_) => SameSite::Lax,
+        Ok("strict") => SameSite::Strict,
+        Ok(_) => return Err("AUTH_COOKIE_SAME_SITE must be lax or strict".to_string()),
+    };
+
+    Ok(CookieSettings {
+        transport,
+        secure,
+        same_site,
+    })
+}
+
+// Load the configuration on startup instead of on the first login.
+pub fn init_token_transport() {
+    lazy_static::initialize(&COOKIE_SETTINGS);
+}
+
+pub fn cookie_mode() -> bool {
+    COOKIE_SETTINGS.transport == TokenTransport::Cookie
+}
+
+fn session_cookie(
+    name: &str,
+    value: String,
+    path: &str,
+    max_age: usize,
+    http_only: bool,
+) -> Cookie<'static> {
+    Cookie::build(name.to_string(), value)
+        .path(path.to_string())
+        .http_only(http_only)
+        .secure(COOKIE_SETTINGS.secure)
+        .same_site(COOKIE_SETTINGS.same_site)
+        .max_age(Duration::seconds(max_age as i64))
+        .finish()
+}
+
+// Hand the tokens of a login or refresh to the browser, with a new CSRF token
+pub fn set_session_cookies(
+    response: &mut HttpResponseBuilder,
+    access_token: &str,
+    refresh_token: &str,
+) {
+    response
+        .cookie(session_cookie(
+            ACCESS_TOKEN_COOKIE,
+            access_token.to_string(),
+            "/",
+            JWT_LIFETIME,
+            true,
+        ))
+        .cookie(session_cookie(
+            REFRESH_TOKEN_COOKIE,
+            refresh_token.to_string(),
+            REFRESH_TOKEN_PATH,
+            REFRESH_TOKEN_LIFETIME,
+            true,
+        ))
+        .cookie(session_cookie(
+            CSRF_TOKEN_COOKIE,
+            generate_opaque_token(),
+            "/",
+            REFRESH_TOKEN_LIFETIME,
+            false,
+        ));
+}
+
+pub fn clear_session_cookies(response: &mut HttpResponseBuilder) {
+    response
+        .cookie(session_cookie(
+            ACCESS_TOKEN_COOKIE,
+            String::new(),
+            "/",
+            0,
+            true,
+        ))
+        .cookie(session_cookie(
+            REFRESH_TOKEN_COOKIE,
+            String::new(),
+            REFRESH_TOKEN_PATH,
+            0,
+            true,
+        ))
+        .cookie(session_cookie(
+            CSRF_TOKEN_COOKIE,
+            String::new(),
+            "/",
+            0,
+            false,
+        ));
+}
+
+// Binds an OpenID Connect login to the browser that started it, so an attacker can't
+// send someone else to the callback with the state and code of their own login. Always
+// SameSite=Lax, the provider redirects back with a cross site navigation.
+pub fn oidc_state_cookie(state: &str) -> Cookie<'static> {
+    Cookie::build(OIDC_STATE_COOKIE, hash_token(state))
+        .path(OIDC_STATE_PATH)
+        .http_only(true)
+        .secure(COOKIE_SETTINGS.secure)
+        .same_site(SameSite::Lax)
+        .max_age(Duration::seconds(OIDC_LOGIN_LIFETIME as i64))
+        .finish()
+}
+
+pub fn clear_oidc_state_cookie() -> Cookie<'static> {
+    let mut cookie = oidc_state_cookie("");
+    cookie.set_value("");
+    cookie.set_max_age(Duration::ZERO);
+    cookie
+}
+
+pub fn oidc_state_matches(cookie: Option<&str>, state: &str) -> bool {
+    csrf_token_matches(cookie, Some(&hash_token(state)))
+}
+
+// Whether the state of an OpenID Connect callback is the one of this browser's login
+pub fn verify_oidc_state(req: &HttpRequest, state: &str) -> bool {
+    let cookie = req.cookie(OIDC_STATE_COOKIE);
+    oidc_state_matches(cookie.as_ref().map(|cookie| cookie.value()), state)
+}
+
+fn cookie_value(req: &HttpRequest, name: &str) -> Option<String> {
+    if !cookie_mode() {
+        return None;
+    }
+    req.cookie(name)
+        .map(|cookie| cookie.value().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+// The access token of a request without an Authorization header, in cookie mode
+pub fn access_token_cookie(req: &HttpRequest) -> Option<String> {
+    if req.headers().contains_key("Authorization") {
+        return None;
+    }
+    cookie_value(req, ACCESS_TOKEN_COOKIE)
+}
+
+pub fn refresh_token_cookie(req: &HttpRequest) -> Option<String> {
+    cookie_value(req, REFRESH_TOKEN_COOKIE)
+}
+
+pub fn csrf_token_matches(cookie: Option<&str>, header: Option<&str>) -> bool {
+    match (cookie, header) {
+        (Some(cookie), Some(header)) => {
+            !cookie.is_empty()
+                && cookie.len() == header.len()
+                && memcmp::eq(cookie.as_bytes(), header.as_bytes())
+        }
+        _ => false,
+    }
+}
+
+// Browsers send cookies along with cross site requests, so requests that change state
+// must prove they were made by a page that can read the CSRF cookie
+pub fn verify_csrf(req: &HttpRequest) -> bool {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return true;
+    }
+    let cookie = req.cookie(CSRF_TOKEN_COOKIE);
+    let header = req
+        .headers()
+        .get(CSRF_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok());
+    csrf_token_matches(cookie.as_ref().map(|cookie| cookie.value()), header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{csrf_token_matches, hash_token, oidc_state_matches};
+
+    #[test]
+    fn csrf_token_must_match_the_cookie() {
+        assert!(csrf_token_matches(Some("abc123"), Some("abc123")));
+        assert!(!csrf_token_matches(Some("abc123"), Some("abc124")));
+        assert!(!csrf_token_matches(Some("abc123"), Some("abc")));
+        assert!(!csrf_token_matches(Some("abc123"), None));
+        assert!(!csrf_token_matches(None, Some("abc123")));
+        assert!(!csrf_token_matches(Some(""), Some("")));
+    }
+
+    #[test]
+    fn oidc_state_must_be_the_one_of_the_cookie() {
+        let cookie = hash_token("state-of-this-browser");
+
+        assert!(oidc_state_matches(Some(&cookie), "state-of-this-browser"));
+        assert!(!oidc_state_matches(Some(&cookie), "state-of-another-login"));
+        assert!(!oidc_state_matches(None, "state-of-this-browser"));
+        assert!(!oidc_state_matches(Some(""), ""));
+    }
+}
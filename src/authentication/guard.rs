@@ -0,0 +1,243 @@
+use std::{
+    future::{ready, Ready},
+    marker::PhantomData,
+    ops::Deref,
+};
+
+use actix_web::{dev::Payload, FromRequest, HttpMessage, HttpRequest};
+
+use super::{
+    model::Claims,
+    service::{authenticate_claims, authenticate_permission},
+};
+use crate::{common::model::AppError, database::model::users::UserRole};
+
+pub trait RoleRequirement {
+    const ROLE: UserRole;
+}
+
+pub trait PermissionRequirement {
+    const PERMISSION: &'static str;
+}
+
+// Marker types for the minimum role a route requires, e.g. `RequireRole<roles::Admin>`.
+pub mod roles {
+    use super::RoleRequirement;
+    use crate::database::model::users::UserRole;
+
+    pub struct Admin;
+    pub struct User;
+    pub struct Guest;
+
+    impl RoleRequirement for Admin {
+        const ROLE: UserRole = UserRole::Admin;
+    }
+
+    impl RoleRequirement for User {
+        const ROLE: UserRole = UserRole::User;
+    }
+
+    impl RoleRequirement for Guest {
+        const ROLE: UserRole = UserRole::Guest;
+    }
+}
+
+// Extracts the claims inserted by AuthenticationCheck and rejects the request with a 403
+// unless the caller has at least role R. Without claims the request is rejected with a 401.
+// Tokens issued to OAuth clients and API keys are limited to the permissions of their
+// scope and never pass a role check, otherwise they could act with the full role of the user.
+// Impersonation tokens are rejected too, so support can't mint credentials for the account
+// or change it. Routes that only read use AllowImpersonation instead.
+pub struct RequireRole<R: RoleRequirement> {
+    pub claims: Claims,
+    role: PhantomData<R>,
+}
+
+// Like RequireRole, but also accepted with an impersonation token
+pub struct AllowImpersonation<R: RoleRequirement> {
+    pub claims: Claims,
+    role: PhantomData<R>,
+}
+
+impl<R: RoleRequirement> Deref for RequireRole<R> {
+    type Target = Claims;
+
+    fn deref(&self) -> &Self::Target {
+        &self.claims
+    }
+}
+
+impl<R: RoleRequirement> Deref for AllowImpersonation<R> {
+    type Target = Claims;
+
+    fn deref(&self) -> &Self::Target {
+        &self.claims
+    }
+}
+
+fn check_role(
+    claims: &Claims,
+    role: &UserRole,
+    allow_impersonation: bool,
+) -> Result<bool, AppError> {
+    if claims.scope.is_some() {
+        return Err(AppError::ForbiddenError(
+            "Not available to scoped tokens and API keys".to_string(),
+        ));
+    }
+    if claims.act.is_some() && !allow_impersonation {
+        return Err(AppError::ForbiddenError(
+            "Not available while impersonating".to_string(),
+        ));
+    }
+    authenticate_claims(claims, role)
+}
+
+fn role_claims(
+    req: &HttpRequest,
+    role: &UserRole,
+    allow_impersonation: bool,
+) -> Result<Claims, AppError> {
+    let claims = match req.extensions().get::<Claims>() {
+        Some(claims) => claims.clone(),
+        None => {
+            return Err(AppError::UnauthorizedError(
+                "Missing or invalid authentication".to_string(),
+            ))
+        }
+    };
+    check_role(&claims, role, allow_impersonation).map(|_| claims)
+}
+
+impl<R: RoleRequirement> FromRequest for RequireRole<R> {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(role_claims(req, &R::ROLE, false).map(|claims| RequireRole {
+            claims,
+            role: PhantomData,
+        }))
+    }
+}
+
+impl<R: RoleRequirement> FromRequest for AllowImpersonation<R> {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            role_claims(req, &R::ROLE, true).map(|claims| AllowImpersonation {
+                claims,
+                role: PhantomData,
+            }),
+        )
+    }
+}
+
+// Marker types for the permission a route requires, e.g. `RequirePermission<permissions::UsersRead>`.
+pub mod permissions {
+    use super::PermissionRequirement;
+
+    pub struct UsersRead;
+    pub struct UsersWrite;
+    pub struct UsersDelete;
+
+    impl PermissionRequirement for UsersRead {
+        const PERMISSION: &'static str = "users:read";
+    }
+
+    impl PermissionRequirement for UsersWrite {
+        const PERMISSION: &'static str = "users:write";
+    }
+
+    impl PermissionRequirement for UsersDelete {
+        const PERMISSION: &'static str = "users:delete";
+    }
+}
+
+// Like RequireRole, but checks the permissions claim for permission P.
+pub struct RequirePermission<P: PermissionRequirement> {
+    pub claims: Claims,
+    permission: PhantomData<P>,
+}
+
+impl<P: PermissionRequirement> Deref for RequirePermission<P> {
+    type Target = Claims;
+
+    fn deref(&self) -> &Self::Target {
+        &self.claims
+    }
+}
+
+impl<P: PermissionRequirement> FromRequest for RequirePermission<P> {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let claims = match req.extensions().get::<Claims>() {
+            Some(claims) => claims.clone(),
+            None => {
+                return ready(Err(AppError::UnauthorizedError(
+                    "Missing or invalid authentication".to_string(),
+                )))
+            }
+        };
+
+        ready(
+            authenticate_permission(&claims, P::PERMISSION).map(|_| RequirePermission {
+                claims,
+                permission: PhantomData,
+            }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::check_role;
+    use crate::authentication::model::{Actor, Claims};
+    use crate::database::model::users::UserRole;
+
+    fn claims(role: UserRole) -> Claims {
+        Claims {
+            sub: Uuid::new_v4().to_string(),
+            iss: String::new(),
+            aud: String::new(),
+            exp: 0,
+            nbf: 0,
+            iat: 0,
+            jti: Uuid::new_v4(),
+            role,
+            permissions: vec![],
+            client_id: None,
+            scope: None,
+            sid: None,
+            act: None,
+        }
+    }
+
+    #[test]
+    fn impersonation_tokens_need_routes_to_opt_in() {
+        let mut impersonated = claims(UserRole::User);
+        impersonated.act = Some(Actor {
+            sub: Uuid::new_v4().to_string(),
+        });
+
+        assert!(check_role(&impersonated, &UserRole::Guest, false).is_err());
+        assert!(check_role(&impersonated, &UserRole::Guest, true).is_ok());
+        assert!(check_role(&impersonated, &UserRole::Admin, true).is_err());
+        assert!(check_role(&claims(UserRole::User), &UserRole::Guest, false).is_ok());
+    }
+
+    #[test]
+    fn scoped_tokens_never_pass_a_role_check() {
+        let mut scoped = claims(UserRole::Admin);
+        scoped.scope = Some("users:read".to_string());
+
+        assert!(check_role(&scoped, &UserRole::Guest, false).is_err());
+        assert!(check_role(&scoped, &UserRole::Guest, true).is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use chrono::{Duration, Utc};
+use diesel::{prelude::*, result::QueryResult, PgConnection};
+use uuid::Uuid;
+
+use crate::{
+    authentication::jwt::services::generate_impersonation_token,
+    database::model::{
+        impersonations::{CreateImpersonationDb, Impersonation},
+        users::UserRole,
+    },
+    permissions::service::find_permissions_for_role,
+    schema::impersonations,
+    users::service::find_user_by_id,
+    IMPERSONATION_LIFETIME,
+};
+
+#[derive(Debug)]
+pub enum ImpersonationError {
+    UserNotFound,
+    NotAllowed(&'static str),
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for ImpersonationError {
+    fn from(e: diesel::result::Error) -> Self {
+        ImpersonationError::Database(e)
+    }
+}
+
+// Admins can't act as themselves or as other admins, which would only hide who did what
+fn check_impersonation(
+    actor_id: Uuid,
+    user_id: Uuid,
+    role: UserRole,
+    reason: &str,
+) -> Result<(), ImpersonationError> {
+    if reason.trim().is_empty() {
+        return Err(ImpersonationError::NotAllowed("A reason is required"));
+    }
+    if actor_id == user_id {
+        return Err(ImpersonationError::NotAllowed(
+            "You can't impersonate yourself",
+        ));
+    }
+    if role == UserRole::Admin {
+        return Err(ImpersonationError::NotAllowed(
+            "Admins can't be impersonated",
+        ));
+    }
+    Ok(())
+}
+
+// Issue a token for the user carrying the admin as its actor and record it in the audit trail
+pub fn impersonate(
+    conn: &mut PgConnection,
+    actor_id: Uuid,
+    user_id: Uuid,
+    reason: &str,
+) -> Result<(String, Impersonation), ImpersonationError> {
+    let user = find_user_by_id(conn, user_id)?.ok_or(ImpersonationError::UserNotFound)?;
+    check_impersonation(actor_id, user.id, user.role, reason)?;
+    let permissions = find_permissions_for_role(conn, user.role)?;
+
+    let (token, token_id) = generate_impersonation_token(
+        &user.id.to_string(),
+        user.role,
+        permissions,
+        &actor_id.to_string(),
+    );
+    let impersonation = diesel::insert_into(impersonations::table)
+        .values(CreateImpersonationDb {
+            actor_id,
+            user_id: user.id,
+            reason: reason.trim().to_string(),
+            token_id,
+            expires_at: Utc::now().naive_utc() + Duration::seconds(IMPERSONATION_LIFETIME as i64),
+        })
+        .returning(Impersonation::as_returning())
+        .get_result(conn)?;
+
+    Ok((token, impersonation))
+}
+
+pub fn find_impersonations(conn: &mut PgConnection) -> QueryResult<Vec<Impersonation>> {
+    impersonations::table
+        .order(impersonations::created_at.desc())
+        .select(Impersonation::as_select())
+        .load(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_non_admins_can_be_impersonated_with_a_reason() {
+        let admin = Uuid::new_v4();
+        let user = Uuid::new_v4();
+
+        assert!(check_impersonation(admin, user, UserRole::User, "Ticket #42").is_ok());
+        assert!(check_impersonation(admin, user, UserRole::Guest, "Ticket #42").is_ok());
+        assert!(check_impersonation(admin, user, UserRole::Admin, "Ticket #42").is_err());
+        assert!(check_impersonation(admin, admin, UserRole::User, "Ticket #42").is_err());
+        assert!(check_impersonation(admin, user, UserRole::User, "  ").is_err());
+    }
+}
@@ -0,0 +1,317 @@
+use std::{env, fs, str::FromStr};
+
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use jsonwebtoken::{
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+        EllipticCurveKeyType, Jwk, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType,
+        PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey,
+};
+use openssl::{
+    bn::{BigNum, BigNumContext},
+    ec::{EcGroup, EcKey},
+    nid::Nid,
+    pkey::{HasPublic, Id, PKey, PKeyRef, Private},
+    rand::rand_bytes,
+    rsa::Rsa,
+};
+
+use crate::common::crypto::to_hex;
+
+const HMAC_SECRET_BYTES: usize = 64;
+const RSA_KEY_BITS: u32 = 2048;
+
+pub struct JwtKey {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    // Only keys we still hold the private half of can sign
+    pub encoding_key: Option<EncodingKey>,
+    pub decoding_key: DecodingKey,
+    // Public half of the key for the JWKS endpoint, HMAC secrets are never published
+    pub jwk: Option<Jwk>,
+}
+
+// Key material as it is stored in a key directory or the signing_keys table.
+pub struct KeyMaterial {
+    pub algorithm: Algorithm,
+    // PKCS#8 PEM for asymmetric keys, base64 for HMAC secrets
+    pub private_key: String,
+    pub public_key: Option<String>,
+}
+
+pub fn is_hmac(algorithm: Algorithm) -> bool {
+    matches!(
+        algorithm,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    )
+}
+
+pub fn parse_algorithm(name: &str) -> Result<Algorithm, String> {
+    Algorithm::from_str(name).map_err(|_| format!("Unsupported JWT algorithm {}", name))
+}
+
+// JWT_ALGORITHM selects the algorithm (HS256 by default).
+pub fn configured_algorithm() -> Result<Algorithm, String> {
+    match env::var("JWT_ALGORITHM") {
+        Ok(name) => parse_algorithm(&name),
+        Err(_) => Ok(Algorithm::HS256),
+    }
+}
+
+impl JwtKey {
+    // The single key configured through JWT_ALGORITHM, JWT_SECRET / JWT_PRIVATE_KEY_PATH
+    // and JWT_KEY_ID.
+    pub fn from_env() -> Result<Self, String> {
+        JwtKey::from_material(env::var("JWT_KEY_ID").ok(), &env_key_material()?)
+    }
+
+    pub fn from_secret(kid: String, algorithm: Algorithm, secret: &[u8]) -> Self {
+        JwtKey {
+            kid,
+            algorithm,
+            encoding_key: Some(EncodingKey::from_secret(secret)),
+            decoding_key: DecodingKey::from_secret(secret),
+            jwk: None,
+        }
+    }
+
+    // Without an explicit key id the kid is derived from the public key, so it changes
+    // whenever the key does.
+    pub fn from_private_pem(
+        kid: Option<String>,
+        algorithm: Algorithm,
+        pem: &[u8],
+    ) -> Result<Self, String> {
+        let private_key = PKey::private_key_from_pem(pem).map_err(|e| e.to_string())?;
+        let mut key = JwtKey::from_public_key(kid, algorithm, &private_key)?;
+
+        // jsonwebtoken only accepts PKCS#8 for EC and Ed25519 keys, so normalize first
+        let pkcs8 = private_key
+            .private_key_to_pem_pkcs8()
+            .map_err(|e| e.to_string())?;
+        let encoding_key = match private_key.id() {
+            Id::RSA => EncodingKey::from_rsa_pem(&pkcs8),
+            Id::EC => EncodingKey::from_ec_pem(&pkcs8),
+            _ => EncodingKey::from_ed_pem(&pkcs8),
+        }
+        .map_err(|e| e.to_string())?;
+
+        key.encoding_key = Some(encoding_key);
+        Ok(key)
+    }
+
+    // A verification only key, e.g. a retired key whose private half has been destroyed.
+    pub fn from_public_pem(
+        kid: Option<String>,
+        algorithm: Algorithm,
+        pem: &[u8],
+    ) -> Result<Self, String> {
+        let public_key = PKey::public_key_from_pem(pem).map_err(|e| e.to_string())?;
+        JwtKey::from_public_key(kid, algorithm, &public_key)
+    }
+
+    pub fn from_material(kid: Option<String>, material: &KeyMaterial) -> Result<Self, String> {
+        if is_hmac(material.algorithm) {
+            let secret = STANDARD
+                .decode(&material.private_key)
+                .map_err(|e| e.to_string())?;
+            return Ok(JwtKey::from_secret(
+                kid.unwrap_or_else(|| "default".to_string()),
+                material.algorithm,
+                &secret,
+            ));
+        }
+        JwtKey::from_private_pem(kid, material.algorithm, material.private_key.as_bytes())
+    }
+
+    fn from_public_key<T: HasPublic>(
+        kid: Option<String>,
+        algorithm: Algorithm,
+        key: &PKeyRef<T>,
+    ) -> Result<Self, String> {
+        let public_der = key.public_key_to_der().map_err(|e| e.to_string())?;
+        let kid =
+            kid.unwrap_or_else(|| to_hex(&openssl::sha::sha256(&public_der))[..16].to_string());
+
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(
+                    KeyAlgorithm::from_str(&format!("{:?}", algorithm))
+                        .map_err(|e| e.to_string())?,
+                ),
+                key_id: Some(kid.clone()),
+                ..Default::default()
+            },
+            algorithm: public_key_parameters(key, algorithm)?,
+        };
+        let decoding_key = DecodingKey::from_jwk(&jwk).map_err(|e| e.to_string())?;
+
+        Ok(JwtKey {
+            kid,
+            algorithm,
+            encoding_key: None,
+            decoding_key,
+            jwk: Some(jwk),
+        })
+    }
+}
+
+// HMAC algorithms sign with JWT_SECRET, asymmetric algorithms load the PEM encoded
+// private key at JWT_PRIVATE_KEY_PATH.
+pub fn env_key_material() -> Result<KeyMaterial, String> {
+    let algorithm = configured_algorithm()?;
+
+    if is_hmac(algorithm) {
+        let secret = env::var("JWT_SECRET").map_err(|_| "JWT_SECRET must be set")?;
+        return Ok(KeyMaterial {
+            algorithm,
+            private_key: STANDARD.encode(secret),
+            public_key: None,
+        });
+    }
+
+    let path = env::var("JWT_PRIVATE_KEY_PATH")
+        .map_err(|_| "JWT_PRIVATE_KEY_PATH must be set for asymmetric algorithms")?;
+    let pem = fs::read(&path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+    let private_key = PKey::private_key_from_pem(&pem).map_err(|e| e.to_string())?;
+    public_key_parameters(&private_key, algorithm)?;
+
+    private_key_material(algorithm, &private_key)
+}
+
+// Pick the algorithm for a key file, preferring JWT_ALGORITHM when it fits the key type.
+pub fn algorithm_for_pem(pem: &[u8], preferred: Algorithm) -> Result<Algorithm, String> {
+    let key = PKey::public_key_from_pem(pem)
+        .or_else(|_| {
+            PKey::private_key_from_pem(pem)
+                .and_then(|key| key.public_key_to_pem())
+                .and_then(|public| PKey::public_key_from_pem(&public))
+        })
+        .map_err(|e| e.to_string())?;
+
+    if public_key_parameters(&key, preferred).is_ok() {
+        return Ok(preferred);
+    }
+    [
+        Algorithm::RS256,
+        Algorithm::ES256,
+        Algorithm::ES384,
+        Algorithm::EdDSA,
+    ]
+    .into_iter()
+    .find(|algorithm| public_key_parameters(&key, *algorithm).is_ok())
+    .ok_or_else(|| "Unsupported key type".to_string())
+}
+
+// Generate fresh key material for the algorithm.
+pub fn generate_key_material(algorithm: Algorithm) -> Result<KeyMaterial, String> {
+    if is_hmac(algorithm) {
+        let mut secret = [0u8; HMAC_SECRET_BYTES];
+        rand_bytes(&mut secret).map_err(|e| e.to_string())?;
+        return Ok(KeyMaterial {
+            algorithm,
+            private_key: STANDARD.encode(secret),
+            public_key: None,
+        });
+    }
+
+    let private_key = match algorithm {
+        Algorithm::ES256 | Algorithm::ES384 => {
+            let nid = if algorithm == Algorithm::ES256 {
+                Nid::X9_62_PRIME256V1
+            } else {
+                Nid::SECP384R1
+            };
+            let group = EcGroup::from_curve_name(nid).map_err(|e| e.to_string())?;
+            EcKey::generate(&group).and_then(PKey::from_ec_key)
+        }
+        Algorithm::EdDSA => PKey::generate_ed25519(),
+        _ => Rsa::generate(RSA_KEY_BITS).and_then(PKey::from_rsa),
+    }
+    .map_err(|e| e.to_string())?;
+
+    private_key_material(algorithm, &private_key)
+}
+
+fn private_key_material(
+    algorithm: Algorithm,
+    private_key: &PKey<Private>,
+) -> Result<KeyMaterial, String> {
+    let pem_string = |pem: Vec<u8>| String::from_utf8(pem).map_err(|e| e.to_string());
+    Ok(KeyMaterial {
+        algorithm,
+        private_key: pem_string(
+            private_key
+                .private_key_to_pem_pkcs8()
+                .map_err(|e| e.to_string())?,
+        )?,
+        public_key: Some(pem_string(
+            private_key.public_key_to_pem().map_err(|e| e.to_string())?,
+        )?),
+    })
+}
+
+fn encode_component(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn public_key_parameters<T: HasPublic>(
+    key: &PKeyRef<T>,
+    algorithm: Algorithm,
+) -> Result<AlgorithmParameters, String> {
+    match (key.id(), algorithm) {
+        (
+            Id::RSA,
+            Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512,
+        ) => {
+            let rsa = key.rsa().map_err(|e| e.to_string())?;
+            Ok(AlgorithmParameters::RSA(RSAKeyParameters {
+                key_type: RSAKeyType::RSA,
+                n: encode_component(&rsa.n().to_vec()),
+                e: encode_component(&rsa.e().to_vec()),
+            }))
+        }
+        (Id::EC, Algorithm::ES256 | Algorithm::ES384) => {
+            let ec = key.ec_key().map_err(|e| e.to_string())?;
+            let (curve, size) = match (ec.group().curve_name(), algorithm) {
+                (Some(Nid::X9_62_PRIME256V1), Algorithm::ES256) => (EllipticCurve::P256, 32),
+                (Some(Nid::SECP384R1), Algorithm::ES384) => (EllipticCurve::P384, 48),
+                _ => return Err(format!("EC curve doesn't match {:?}", algorithm)),
+            };
+            let mut ctx = BigNumContext::new().map_err(|e| e.to_string())?;
+            let mut x = BigNum::new().map_err(|e| e.to_string())?;
+            let mut y = BigNum::new().map_err(|e| e.to_string())?;
+            ec.public_key()
+                .affine_coordinates(ec.group(), &mut x, &mut y, &mut ctx)
+                .map_err(|e| e.to_string())?;
+            Ok(AlgorithmParameters::EllipticCurve(
+                EllipticCurveKeyParameters {
+                    key_type: EllipticCurveKeyType::EC,
+                    curve,
+                    x: encode_component(&x.to_vec_padded(size).map_err(|e| e.to_string())?),
+                    y: encode_component(&y.to_vec_padded(size).map_err(|e| e.to_string())?),
+                },
+            ))
+        }
+        (Id::ED25519, Algorithm::EdDSA) => {
+            let x = key.raw_public_key().map_err(|e| e.to_string())?;
+            Ok(AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: encode_component(&x),
+            }))
+        }
+        _ => Err(format!("Key type doesn't match {:?}", algorithm)),
+    }
+}
@@ -1 +1,3 @@
+pub mod key_ring;
+pub mod keys;
 pub mod services;
@@ -1,62 +1,301 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    env,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
+use super::key_ring::key_ring;
 use crate::{
-    authentication::model::{Claims, LoginRequest},
+    authentication::cookies::services::access_token_cookie,
+    authentication::model::{
+        Actor, Claims, LoginRequest, MfaPendingClaims, OAuthIdTokenClaims, TokenError,
+    },
+    common::password::{hash_password, needs_rehash, verify_dummy_password, verify_password},
     database::{
         model::db::DbPool,
         model::users::{User, UserRole},
         tools::get_connection,
     },
+    schema::users,
     users::service::find_user_by_email,
-    ENCODING_KEY, JWT_ALGORITHM, JWT_LIFETIME, JWT_SECRET,
+    IMPERSONATION_LIFETIME, JWT_LIFETIME, MFA_PENDING_LIFETIME,
 };
 use actix_web::{dev::ServiceRequest, web};
 
-use bcrypt::verify;
-use jsonwebtoken::{decode, encode, DecodingKey, Header};
-use log::error;
+use diesel::{prelude::*, PgConnection};
+use jsonwebtoken::{decode, decode_header, encode, errors::ErrorKind, Header, Validation};
+use lazy_static::lazy_static;
+use log::{error, warn};
+use serde::{de::DeserializeOwned, Serialize};
+use uuid::Uuid;
 
-pub fn generate_token(user_id: &str, role: UserRole) -> String {
-    let now = match SystemTime::now().duration_since(UNIX_EPOCH) {
-        Ok(n) => n.as_secs(),
-        Err(_) => {
-            return String::new();
+lazy_static! {
+    pub static ref JWT_ISSUER: String =
+        env::var("JWT_ISSUER").unwrap_or_else(|_| "rust_jwt_api".to_string());
+    pub static ref JWT_AUDIENCE: String =
+        env::var("JWT_AUDIENCE").unwrap_or_else(|_| "rust_jwt_api".to_string());
+    // Seconds of clock skew tolerated when checking exp and nbf
+    static ref JWT_LEEWAY: u64 = env::var("JWT_LEEWAY")
+        .ok()
+        .and_then(|leeway| leeway.parse().ok())
+        .unwrap_or(30);
+    static ref MFA_PENDING_AUDIENCE: String = format!("{}:mfa", *JWT_AUDIENCE);
+}
+
+fn unix_now() -> Option<usize> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|n| n.as_secs() as usize)
+}
+
+// Sign with the current key of the key ring, its kid tells verifiers which key to use.
+fn sign<T: Serialize>(claims: &T) -> String {
+    let key_ring = key_ring();
+    let signing_key = key_ring.signing_key();
+    let mut header = Header::new(signing_key.algorithm);
+    header.kid = Some(signing_key.kid.clone());
+
+    let encoding_key = signing_key.encoding_key.as_ref().unwrap();
+    encode(&header, claims, encoding_key).unwrap()
+}
+
+pub fn generate_token(
+    user_id: &str,
+    role: UserRole,
+    permissions: Vec<String>,
+    session_id: Uuid,
+) -> String {
+    generate_access_token(
+        user_id,
+        role,
+        permissions,
+        None,
+        None,
+        Some(session_id),
+        None,
+    )
+    .0
+}
+
+// Access token for an OAuth client, the subject is the user or, for the client credentials
+// grant, the client itself.
+pub fn generate_client_token(
+    subject: &str,
+    role: UserRole,
+    permissions: Vec<String>,
+    client_id: &str,
+    scope: &str,
+) -> String {
+    generate_access_token(
+        subject,
+        role,
+        permissions,
+        Some(client_id.to_owned()),
+        Some(scope.to_owned()),
+        None,
+        None,
+    )
+    .0
+}
+
+// Short lived token for an admin acting as the user, its token id is recorded in the audit
+// trail. There is no refresh token, the admin asks for a new token instead.
+pub fn generate_impersonation_token(
+    user_id: &str,
+    role: UserRole,
+    permissions: Vec<String>,
+    actor_id: &str,
+) -> (String, Uuid) {
+    generate_access_token(
+        user_id,
+        role,
+        permissions,
+        None,
+        None,
+        None,
+        Some(Actor {
+            sub: actor_id.to_owned(),
+        }),
+    )
+}
+
+// Returns the token and its id
+fn generate_access_token(
+    subject: &str,
+    role: UserRole,
+    permissions: Vec<String>,
+    client_id: Option<String>,
+    scope: Option<String>,
+    sid: Option<Uuid>,
+    act: Option<Actor>,
+) -> (String, Uuid) {
+    let jti = Uuid::new_v4();
+    let now = match unix_now() {
+        Some(now) => now,
+        None => {
+            return (String::new(), jti);
         }
     };
 
-    let expiration_time = now as usize + (JWT_LIFETIME); // Current time + 30 days
+    let lifetime = match act {
+        Some(_) => IMPERSONATION_LIFETIME,
+        None => JWT_LIFETIME,
+    };
 
     let claims = Claims {
+        sub: subject.to_owned(),
+        iss: JWT_ISSUER.clone(),
+        aud: JWT_AUDIENCE.clone(),
+        exp: now + lifetime,
+        nbf: now,
+        iat: now,
+        jti,
+        role,
+        permissions,
+        client_id,
+        scope,
+        sid,
+        act,
+    };
+
+    (sign(&claims), jti)
+}
+
+// Claims for a request authenticated with an API key. They are never signed, the key id
+// takes the place of the token id and the key's creation and expiry those of the token.
+pub fn api_key_claims(
+    user_id: &str,
+    role: UserRole,
+    permissions: Vec<String>,
+    key_id: Uuid,
+    scope: &str,
+    issued_at: usize,
+    expires_at: usize,
+) -> Claims {
+    Claims {
         sub: user_id.to_owned(),
-        exp: expiration_time,
+        iss: JWT_ISSUER.clone(),
+        aud: JWT_AUDIENCE.clone(),
+        exp: expires_at,
+        nbf: issued_at,
+        iat: issued_at,
+        jti: key_id,
         role,
+        permissions,
+        client_id: None,
+        scope: Some(scope.to_owned()),
+        sid: None,
+        act: None,
+    }
+}
+
+// OpenID Connect ID token, its audience is the client it was issued to
+pub fn generate_id_token(
+    user: &User,
+    client_id: &str,
+    nonce: Option<String>,
+    scopes: &[String],
+) -> String {
+    let now = match unix_now() {
+        Some(now) => now,
+        None => {
+            return String::new();
+        }
+    };
+    let granted = |scope: &str| scopes.iter().any(|granted| granted == scope);
+
+    let claims = OAuthIdTokenClaims {
+        sub: user.id.to_string(),
+        iss: JWT_ISSUER.clone(),
+        aud: client_id.to_owned(),
+        exp: now + JWT_LIFETIME,
+        iat: now,
+        nonce,
+        email: granted("email").then(|| user.email.clone()),
+        email_verified: granted("email").then_some(user.email_verified_at.is_some()),
+        preferred_username: granted("profile").then(|| user.username.clone()),
+        zoneinfo: granted("profile").then(|| user.timezone.clone()),
     };
 
-    encode(&Header::default(), &claims, &ENCODING_KEY).unwrap()
+    sign(&claims)
 }
 
-pub fn validate_token(req: &ServiceRequest) -> Result<Claims, String> {
-    let token = req
-        .headers()
-        .get("Authorization")
-        .ok_or("No Authorization header")?
-        .to_str()
-        .map_err(|_| "Invalid Authorization header")?
-        .trim_start_matches("Bearer ")
-        .to_owned();
+// Token proving the password step of a login succeeded. It has its own audience, so it
+// is never accepted as an access token.
+pub fn generate_mfa_pending_token(user_id: &str) -> String {
+    let now = match unix_now() {
+        Some(now) => now,
+        None => {
+            return String::new();
+        }
+    };
 
-    let claims = decode::<Claims>(
-        &token,
-        &DecodingKey::from_secret(&JWT_SECRET),
-        &jsonwebtoken::Validation::new(JWT_ALGORITHM),
-    )
-    .map(|token_data| token_data.claims)
-    .map_err(|e| {
-        error!("{:?}", e);
-        "Invalid token".to_owned()
-    });
+    let claims = MfaPendingClaims {
+        sub: user_id.to_owned(),
+        iss: JWT_ISSUER.clone(),
+        aud: MFA_PENDING_AUDIENCE.clone(),
+        exp: now + MFA_PENDING_LIFETIME,
+        nbf: now,
+        iat: now,
+        jti: Uuid::new_v4(),
+    };
 
-    claims
+    sign(&claims)
+}
+
+// The bearer token of the Authorization header, or in cookie mode the access token cookie
+pub fn validate_token(req: &ServiceRequest) -> Result<Claims, TokenError> {
+    let token = match req.headers().get("Authorization") {
+        Some(header) => header
+            .to_str()
+            .map_err(|_| TokenError::MalformedToken)?
+            .strip_prefix("Bearer ")
+            .ok_or(TokenError::MissingToken)?
+            .to_owned(),
+        None => access_token_cookie(req.request()).ok_or(TokenError::MissingToken)?,
+    };
+
+    decode_claims(&token)
+}
+
+pub fn decode_claims(token: &str) -> Result<Claims, TokenError> {
+    decode_for_audience(token, &JWT_AUDIENCE)
+}
+
+pub fn decode_mfa_pending_token(token: &str) -> Result<MfaPendingClaims, TokenError> {
+    decode_for_audience(token, &MFA_PENDING_AUDIENCE)
+}
+
+fn decode_for_audience<T: DeserializeOwned>(token: &str, audience: &str) -> Result<T, TokenError> {
+    let header = decode_header(token).map_err(|_| TokenError::MalformedToken)?;
+    let key_ring = key_ring();
+    let key = key_ring
+        .verification_key(header.kid.as_deref())
+        .ok_or(TokenError::UnknownKey)?;
+
+    let mut validation = Validation::new(key.algorithm);
+    validation.set_issuer(&[JWT_ISSUER.as_str()]);
+    validation.set_audience(&[audience]);
+    validation.set_required_spec_claims(&["sub", "iss", "aud", "exp", "nbf", "iat", "jti"]);
+    validation.validate_nbf = true;
+    validation.leeway = *JWT_LEEWAY;
+
+    decode::<T>(token, &key.decoding_key, &validation)
+        .map(|token_data| token_data.claims)
+        .map_err(|e| {
+            error!("{:?}", e);
+            match e.kind() {
+                ErrorKind::ExpiredSignature => TokenError::Expired,
+                ErrorKind::ImmatureSignature => TokenError::NotYetValid,
+                ErrorKind::InvalidIssuer => TokenError::InvalidIssuer,
+                ErrorKind::InvalidAudience => TokenError::InvalidAudience,
+                ErrorKind::MissingRequiredClaim(_) => TokenError::MissingClaim,
+                ErrorKind::InvalidSignature | ErrorKind::InvalidAlgorithm => {
+                    TokenError::InvalidSignature
+                }
+                _ => TokenError::MalformedToken,
+            }
+        })
 }
 
 pub fn verify_login_credentials(
@@ -65,11 +304,121 @@ pub fn verify_login_credentials(
 ) -> Result<User, String> {
     let mut conn = get_connection(pool);
     match find_user_by_email(&mut conn, &login_data.email) {
-        Ok(Some(user)) => match verify(&login_data.password, &user.hashed_password) {
-            Ok(_valid) => Ok(user),
+        Ok(Some(user)) => match verify_password(&login_data.password, &user.hashed_password) {
+            Ok(true) => {
+                if needs_rehash(&user.hashed_password) {
+                    rehash_password(&mut conn, &user, &login_data.password);
+                }
+                Ok(user)
+            }
+            Ok(false) => Err("Invalid password".into()),
             Err(_) => Err("Password verification failed".into()),
         },
-        Ok(None) => Err("User not found".into()),
+        Ok(None) => {
+            verify_dummy_password(&login_data.password);
+            Err("User not found".into())
+        }
         Err(e) => Err(format!("Database error: {}", e)),
     }
 }
+
+// Upgrade a hash made by an older algorithm or with older parameters while the plain
+// password is at hand. Failing to do so doesn't fail the login.
+fn rehash_password(conn: &mut PgConnection, user: &User, password: &str) {
+    let result = hash_password(password).and_then(|hashed| {
+        diesel::update(users::table.find(user.id))
+            .set(users::hashed_password.eq(hashed))
+            .execute(conn)
+            .map_err(|e| e.to_string())
+    });
+    if let Err(e) = result {
+        warn!("Unable to rehash the password of user {}, {}", user.id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use actix_web::web;
+
+    use super::verify_login_credentials;
+    use crate::{
+        authentication::model::LoginRequest,
+        database::{
+            model::{
+                db::DbPool,
+                users::{User, UserRole},
+            },
+            tools::testing::{create_test_user, test_pool, TEST_PASSWORD as PASSWORD},
+        },
+        users::service::delete_user,
+    };
+
+    fn login(pool: &DbPool, email: &str, password: &str) -> Result<User, String> {
+        verify_login_credentials(
+            web::Data::new(pool.clone()),
+            LoginRequest {
+                email: email.to_string(),
+                password: password.to_string(),
+            },
+        )
+    }
+
+    fn time_login(pool: &DbPool, email: &str, password: &str) -> Duration {
+        let start = Instant::now();
+        let _ = login(pool, email, password);
+        start.elapsed()
+    }
+
+    #[test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    fn accepts_correct_password() {
+        let pool = test_pool();
+        let user = create_test_user(&pool, UserRole::User);
+
+        let result = login(&pool, &user.email, PASSWORD);
+        delete_user(&mut pool.get().unwrap(), user.id).unwrap();
+
+        assert_eq!(result.expect("Login should succeed").id, user.id);
+    }
+
+    #[test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    fn rejects_wrong_password() {
+        let pool = test_pool();
+        let user = create_test_user(&pool, UserRole::User);
+
+        let result = login(&pool, &user.email, "wrong password");
+        delete_user(&mut pool.get().unwrap(), user.id).unwrap();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    fn rejects_unknown_email() {
+        let pool = test_pool();
+
+        assert!(login(&pool, "nobody@example.com", PASSWORD).is_err());
+    }
+
+    #[test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    fn unknown_email_takes_as_long_as_wrong_password() {
+        let pool = test_pool();
+        let user = create_test_user(&pool, UserRole::User);
+
+        let wrong_password = time_login(&pool, &user.email, "wrong password");
+        let unknown_email = time_login(&pool, "nobody@example.com", "wrong password");
+        delete_user(&mut pool.get().unwrap(), user.id).unwrap();
+
+        // Without the dummy comparison an unknown email returns after a single query
+        assert!(
+            unknown_email > wrong_password / 2,
+            "unknown email took {:?}, wrong password took {:?}",
+            unknown_email,
+            wrong_password
+        );
+    }
+}
@@ -0,0 +1,2 @@
+pub mod services;
+mod totp;
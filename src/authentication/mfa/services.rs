@@ -0,0 +1,275 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{NaiveDateTime, Utc};
+use diesel::{prelude::*, result::QueryResult, PgConnection};
+use openssl::rand::rand_bytes;
+use uuid::Uuid;
+
+use super::totp::{base32_decode, base32_encode, verify_code, TOTP_DIGITS, TOTP_PERIOD};
+use crate::{
+    authentication::jwt::services::JWT_ISSUER,
+    common::crypto::hash_token,
+    database::model::{
+        mfa::{
+            CreateRecoveryCodeDb, CreateTotpCredentialDb, TotpCredential, TotpEnrollmentResponse,
+        },
+        users::User,
+    },
+    schema::{mfa_recovery_codes, totp_credentials},
+};
+
+// 160 bit secrets, the size RFC 4226 recommends for HMAC-SHA1
+const TOTP_SECRET_BYTES: usize = 20;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+#[derive(Debug)]
+pub enum MfaError {
+    AlreadyEnabled,
+    NotEnrolled,
+    InvalidCode,
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for MfaError {
+    fn from(e: diesel::result::Error) -> Self {
+        MfaError::Database(e)
+    }
+}
+
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|n| n.as_secs())
+        .unwrap_or_default()
+}
+
+// Characters outside the unreserved set of RFC 3986 are percent encoded.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn otpauth_uri(secret: &str, account: &str) -> String {
+    let issuer = percent_encode(&JWT_ISSUER);
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        issuer,
+        percent_encode(account),
+        secret,
+        issuer,
+        TOTP_DIGITS,
+        TOTP_PERIOD
+    )
+}
+
+// Recovery codes are compared without separators and case, users tend to type them loosely.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn generate_recovery_code() -> String {
+    let mut buf = [0u8; 7];
+    rand_bytes(&mut buf).expect("Error: Unable to generate random bytes");
+    let code = base32_encode(&buf).to_ascii_lowercase();
+    format!("{}-{}", &code[..5], &code[5..10])
+}
+
+fn find_totp_credential_for_update(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+) -> QueryResult<Option<TotpCredential>> {
+    totp_credentials::table
+        .find(owner_id)
+        .select(TotpCredential::as_select())
+        .for_update()
+        .first(conn)
+        .optional()
+}
+
+fn check_totp_code(credential: &TotpCredential, code: &str) -> Result<i64, MfaError> {
+    let secret = base32_decode(&credential.secret).ok_or(MfaError::NotEnrolled)?;
+    let last_used_step = credential.last_used_step.map(|step| step as u64);
+    verify_code(&secret, code, unix_time(), last_used_step)
+        .map(|step| step as i64)
+        .ok_or(MfaError::InvalidCode)
+}
+
+pub fn is_mfa_enabled(conn: &mut PgConnection, owner_id: Uuid) -> QueryResult<bool> {
+    diesel::select(diesel::dsl::exists(
+        totp_credentials::table
+            .filter(totp_credentials::user_id.eq(owner_id))
+            .filter(totp_credentials::confirmed_at.is_not_null()),
+    ))
+    .get_result(conn)
+}
+
+// Generate a new secret for the user. Until it is confirmed with a code the secret isn't
+// required at login, so starting over simply replaces an unconfirmed secret.
+pub fn start_totp_enrollment(
+    conn: &mut PgConnection,
+    user: &User,
+) -> Result<TotpEnrollmentResponse, MfaError> {
+    conn.transaction(|conn| {
+        if let Some(credential) = find_totp_credential_for_update(conn, user.id)? {
+            if credential.confirmed_at.is_some() {
+                return Err(MfaError::AlreadyEnabled);
+            }
+        }
+
+        let mut buf = [0u8; TOTP_SECRET_BYTES];
+        rand_bytes(&mut buf).expect("Error: Unable to generate random bytes");
+        let secret = base32_encode(&buf);
+
+        diesel::insert_into(totp_credentials::table)
+            .values(CreateTotpCredentialDb {
+                user_id: user.id,
+                secret: secret.clone(),
+            })
+            .on_conflict(totp_credentials::user_id)
+            .do_update()
+            .set((
+                totp_credentials::secret.eq(&secret),
+                totp_credentials::last_used_step.eq(None::<i64>),
+                totp_credentials::created_at.eq(now()),
+            ))
+            .execute(conn)?;
+
+        Ok(TotpEnrollmentResponse {
+            otpauth_uri: otpauth_uri(&secret, &user.email),
+            secret,
+        })
+    })
+}
+
+// Enable TOTP once the user proves their authenticator produces valid codes, and return
+// a fresh set of recovery codes. Only their hashes are stored.
+pub fn confirm_totp_enrollment(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    code: &str,
+) -> Result<Vec<String>, MfaError> {
+    conn.transaction(|conn| {
+        let credential =
+            find_totp_credential_for_update(conn, owner_id)?.ok_or(MfaError::NotEnrolled)?;
+        if credential.confirmed_at.is_some() {
+            return Err(MfaError::AlreadyEnabled);
+        }
+        let step = check_totp_code(&credential, code)?;
+
+        diesel::update(totp_credentials::table.find(owner_id))
+            .set((
+                totp_credentials::confirmed_at.eq(now()),
+                totp_credentials::last_used_step.eq(step),
+            ))
+            .execute(conn)?;
+
+        diesel::delete(mfa_recovery_codes::table.filter(mfa_recovery_codes::user_id.eq(owner_id)))
+            .execute(conn)?;
+        let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+            .map(|_| generate_recovery_code())
+            .collect();
+        let new_codes: Vec<CreateRecoveryCodeDb> = codes
+            .iter()
+            .map(|code| CreateRecoveryCodeDb {
+                user_id: owner_id,
+                code_hash: hash_token(&normalize_recovery_code(code)),
+            })
+            .collect();
+        diesel::insert_into(mfa_recovery_codes::table)
+            .values(new_codes)
+            .execute(conn)?;
+
+        Ok(codes)
+    })
+}
+
+// Check a TOTP code or an unused recovery code for a user with TOTP enabled. Both are
+// single use, a TOTP code can't be replayed within its validity window.
+pub fn verify_second_factor(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    code: Option<&str>,
+    recovery_code: Option<&str>,
+) -> Result<(), MfaError> {
+    conn.transaction(|conn| {
+        let credential = find_totp_credential_for_update(conn, owner_id)?
+            .filter(|credential| credential.confirmed_at.is_some())
+            .ok_or(MfaError::NotEnrolled)?;
+
+        if let Some(code) = code {
+            let step = check_totp_code(&credential, code)?;
+            diesel::update(totp_credentials::table.find(owner_id))
+                .set(totp_credentials::last_used_step.eq(step))
+                .execute(conn)?;
+            return Ok(());
+        }
+
+        let recovery_code = recovery_code.ok_or(MfaError::InvalidCode)?;
+        let used = diesel::update(
+            mfa_recovery_codes::table
+                .filter(mfa_recovery_codes::user_id.eq(owner_id))
+                .filter(
+                    mfa_recovery_codes::code_hash
+                        .eq(hash_token(&normalize_recovery_code(recovery_code))),
+                )
+                .filter(mfa_recovery_codes::used_at.is_null()),
+        )
+        .set(mfa_recovery_codes::used_at.eq(now()))
+        .execute(conn)?;
+
+        if used == 0 {
+            Err(MfaError::InvalidCode)
+        } else {
+            Ok(())
+        }
+    })
+}
+
+// Turn TOTP off after checking a code, so a stolen access token alone can't remove it.
+pub fn disable_totp(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    code: Option<&str>,
+    recovery_code: Option<&str>,
+) -> Result<(), MfaError> {
+    conn.transaction(|conn| {
+        verify_second_factor(conn, owner_id, code, recovery_code)?;
+        diesel::delete(mfa_recovery_codes::table.filter(mfa_recovery_codes::user_id.eq(owner_id)))
+            .execute(conn)?;
+        diesel::delete(totp_credentials::table.find(owner_id)).execute(conn)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_recovery_code, otpauth_uri};
+
+    #[test]
+    fn recovery_codes_ignore_case_and_separators() {
+        assert_eq!(normalize_recovery_code("AB3DE-fg7hi"), "ab3defg7hi");
+        assert_eq!(normalize_recovery_code(" ab3de fg7hi "), "ab3defg7hi");
+    }
+
+    #[test]
+    fn otpauth_uri_encodes_the_account() {
+        let uri = otpauth_uri("JBSWY3DPEHPK3PXP", "jane+mfa@example.com");
+        assert!(uri.starts_with("otpauth://totp/"));
+        assert!(uri.contains(":jane%2Bmfa%40example.com?secret=JBSWY3DPEHPK3PXP&issuer="));
+        assert!(uri.ends_with("&algorithm=SHA1&digits=6&period=30"));
+    }
+}
@@ -1,19 +1,32 @@
 use actix_web::{
     body::{BoxBody, EitherBody},
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    Error, HttpMessage, HttpResponse,
+    http::header::WWW_AUTHENTICATE,
+    web, Error, HttpMessage, HttpResponse,
 };
 use futures_util::future::LocalBoxFuture;
-use log::warn;
-use std::future::{ready, Ready};
+use log::{error, info, warn};
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+};
 
-use crate::authentication::jwt::services::validate_token;
+use crate::{
+    api_keys::service::{authenticate_api_key, parse_api_key},
+    authentication::{
+        cookies::services::{access_token_cookie, verify_csrf},
+        jwt::services::validate_token,
+        model::{Claims, TokenError, TokenErrorResponse},
+        revocation::services::RevocationStore,
+    },
+    database::model::db::DbPool,
+};
 
 pub struct AuthenticationCheck;
 
 impl<S, B> Transform<S, ServiceRequest> for AuthenticationCheck
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -24,17 +37,39 @@ where
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ready(Ok(AuthenticationCheckMiddleware { service }))
+        ready(Ok(AuthenticationCheckMiddleware {
+            service: Rc::new(service),
+        }))
     }
 }
 
 pub struct AuthenticationCheckMiddleware<S> {
-    service: S,
+    service: Rc<S>,
+}
+
+// RFC 6750 error response, the body tells clients why the token was rejected
+fn unauthorized(error: TokenError) -> HttpResponse {
+    let challenge = match error {
+        TokenError::MissingToken => "Bearer".to_string(),
+        TokenError::InvalidApiKey => "ApiKey".to_string(),
+        _ => format!(
+            "Bearer error=\"invalid_token\", error_description=\"{}\"",
+            error.description()
+        ),
+    };
+    HttpResponse::Unauthorized()
+        .insert_header((WWW_AUTHENTICATE, challenge))
+        .json(TokenErrorResponse::from(error))
+}
+
+fn reject(req: ServiceRequest, error_response: HttpResponse) -> ServiceResponse<BoxBody> {
+    let request_path = req.into_parts().0;
+    ServiceResponse::new(request_path, error_response.map_into_boxed_body())
 }
 
 impl<S, B> Service<ServiceRequest> for AuthenticationCheckMiddleware<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -45,25 +80,174 @@ where
     forward_ready!(service);
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        match validate_token(&req) {
-            Ok(claims) => {
-                req.extensions_mut().insert(claims.clone());
-
-                let fut = self.service.call(req);
-                Box::pin(async move {
-                    let res = fut.await?;
-                    Ok(res.map_into_left_body())
-                })
-            }
+        let service = Rc::clone(&self.service);
+
+        // API keys are looked up in the database, so they are resolved off the event loop
+        if let Some(api_key) = request_api_key(&req) {
+            let pool = req.app_data::<web::Data<DbPool>>().cloned();
+            return Box::pin(async move {
+                let result = match pool {
+                    Some(pool) => web::block(move || {
+                        let mut conn = pool.get().map_err(|e| e.to_string())?;
+                        authenticate_api_key(&mut conn, &api_key).map_err(|e| e.to_string())
+                    })
+                    .await
+                    .map_err(|e| e.to_string())
+                    .and_then(|result| result),
+                    None => Err("Missing database pool".to_string()),
+                };
+
+                match result {
+                    Ok(Some(claims)) => authorize(service, req, claims).await,
+                    Ok(None) => {
+                        warn!("Unable to authenticate in middleware, invalid API key");
+                        let response = reject(req, unauthorized(TokenError::InvalidApiKey));
+                        Ok(response.map_into_right_body())
+                    }
+                    Err(e) => {
+                        error!("Unable to check API key, {}", e);
+                        let response = reject(req, HttpResponse::InternalServerError().finish());
+                        Ok(response.map_into_right_body())
+                    }
+                }
+            });
+        }
+
+        // Cookies are sent with cross site requests too, so they need the CSRF token
+        if access_token_cookie(req.request()).is_some() && !verify_csrf(req.request()) {
+            warn!("Rejected cookie authenticated request without a valid CSRF token");
+            let response = reject(
+                req,
+                HttpResponse::Forbidden()
+                    .json(TokenErrorResponse::from(TokenError::InvalidCsrfToken)),
+            );
+            return Box::pin(async move { Ok(response.map_into_right_body()) });
+        }
 
+        let claims = match validate_token(&req) {
+            Ok(claims) => claims,
             Err(err) => {
                 warn!("Unable to authenticate in middleware, {:?}", err);
-                let error_response = HttpResponse::Unauthorized().finish();
-                let request_path = req.into_parts().0;
-                let response =
-                    ServiceResponse::new(request_path, error_response.map_into_boxed_body());
-                Box::pin(async move { Ok(response.map_into_right_body()) })
+                let response = reject(req, unauthorized(err));
+                return Box::pin(async move { Ok(response.map_into_right_body()) });
+            }
+        };
+
+        Box::pin(authorize(service, req, claims))
+    }
+}
+
+fn request_api_key(req: &ServiceRequest) -> Option<String> {
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    parse_api_key(header("Authorization"), header("X-API-Key")).map(str::to_string)
+}
+
+// Reject revoked tokens, otherwise pass the claims on to the handler
+async fn authorize<S, B>(
+    service: Rc<S>,
+    req: ServiceRequest,
+    claims: Claims,
+) -> Result<ServiceResponse<EitherBody<B, BoxBody>>, Error>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    let revocation_store = req.app_data::<web::Data<RevocationStore>>().cloned();
+
+    if let Some(store) = revocation_store {
+        let token_claims = claims.clone();
+        let revoked = web::block(move || store.is_revoked(&token_claims))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|result| result.map_err(|e| e.to_string()));
+
+        match revoked {
+            Ok(false) => {}
+            Ok(true) => {
+                warn!("Rejected revoked token {}", claims.jti);
+                let response = reject(req, unauthorized(TokenError::Revoked));
+                return Ok(response.map_into_right_body());
+            }
+            Err(e) => {
+                error!("Unable to check token revocation, {}", e);
+                let response = reject(req, HttpResponse::InternalServerError().finish());
+                return Ok(response.map_into_right_body());
             }
         }
     }
+
+    if let Some(actor) = &claims.act {
+        info!(
+            "{} {} by {} acting as {}",
+            req.method(),
+            req.path(),
+            actor.sub,
+            claims.sub
+        );
+    }
+
+    req.extensions_mut().insert(claims);
+    let res = service.call(req).await?;
+    Ok(res.map_into_left_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, test, web, App, HttpResponse};
+
+    use super::AuthenticationCheck;
+    use crate::{
+        authentication::{
+            jwt::services::{decode_claims, generate_token},
+            revocation::services::RevocationStore,
+        },
+        database::{
+            model::users::UserRole,
+            tools::testing::{create_test_user, test_pool},
+        },
+        sessions::service::{end_session, start_session},
+        users::service::delete_user,
+    };
+
+    #[actix_web::test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    async fn rejects_tokens_of_ended_sessions() {
+        let pool = test_pool();
+        let user = create_test_user(&pool, UserRole::User);
+        let session = start_session(&mut pool.get().unwrap(), user.id, None, None).unwrap();
+        let token = generate_token(&user.id.to_string(), user.role, vec![], session.id);
+        let store = web::Data::new(RevocationStore::new(pool.clone()));
+        let app = test::init_service(
+            App::new()
+                .app_data(store.clone())
+                .wrap(AuthenticationCheck)
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let request = || {
+            test::TestRequest::get()
+                .uri("/")
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .to_request()
+        };
+
+        let before = test::call_service(&app, request()).await.status();
+        end_session(&mut pool.get().unwrap(), user.id, session.id).unwrap();
+        store.revoke_session(session.id);
+        let after = test::call_service(&app, request()).await.status();
+        // Other instances learn about the session from the database
+        let elsewhere = RevocationStore::new(pool.clone())
+            .is_revoked(&decode_claims(&token).unwrap())
+            .unwrap();
+        delete_user(&mut pool.get().unwrap(), user.id).unwrap();
+
+        assert_eq!(before, StatusCode::OK);
+        assert_eq!(after, StatusCode::UNAUTHORIZED);
+        assert!(elsewhere);
+    }
 }
@@ -1,5 +1,16 @@
-mod jwt;
+pub mod cookies;
+pub mod guard;
+mod impersonation;
+pub mod jwt;
+mod mfa;
 pub mod middleware;
 pub mod model;
+pub mod oidc;
+mod password_reset;
+pub mod refresh;
+pub mod registration;
+pub mod revocation;
 pub mod routes;
 pub mod service;
+pub mod throttle;
+mod webauthn;
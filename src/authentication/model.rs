@@ -1,13 +1,131 @@
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
+use uuid::Uuid;
 
 use crate::database::model::users::UserRole;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Claims {
     pub sub: String,
+    pub iss: String,
+    pub aud: String,
     pub exp: usize,
+    pub nbf: usize,
+    pub iat: usize,
+    pub jti: Uuid,
     pub role: UserRole,
+    // Permissions granted to the role when the token was issued
+    #[serde(default)]
+    pub permissions: Vec<String>,
+    // Set on tokens issued to OAuth clients, which are limited to the granted scope
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    // Also set for requests made with an API key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    // Session the token was issued for by a login, ending it revokes the token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<Uuid>,
+    // The admin acting as the subject, only set on impersonation tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+}
+
+// RFC 8693 actor claim
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Actor {
+    pub sub: String,
+}
+
+// Issued after the password step of a login when the user has a second factor enabled.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MfaPendingClaims {
+    pub sub: String,
+    pub iss: String,
+    pub aud: String,
+    pub exp: usize,
+    pub nbf: usize,
+    pub iat: usize,
+    pub jti: Uuid,
+}
+
+// ID token issued to OAuth clients that requested the openid scope. The profile and email
+// claims are only included when their scope was granted.
+#[derive(Serialize, Debug, Clone)]
+pub struct OAuthIdTokenClaims {
+    pub sub: String,
+    pub iss: String,
+    pub aud: String,
+    pub exp: usize,
+    pub iat: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preferred_username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zoneinfo: Option<String>,
+}
+
+// Why an access token was rejected, returned to clients in the `error` field of a 401.
+#[derive(Serialize, Debug, ToSchema, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenError {
+    MissingToken,
+    MalformedToken,
+    UnknownKey,
+    InvalidSignature,
+    Expired,
+    NotYetValid,
+    InvalidIssuer,
+    InvalidAudience,
+    MissingClaim,
+    Revoked,
+    InvalidApiKey,
+    InvalidCsrfToken,
+}
+
+impl TokenError {
+    pub fn description(&self) -> &'static str {
+        match self {
+            TokenError::MissingToken => "No bearer token provided",
+            TokenError::MalformedToken => "The token is malformed",
+            TokenError::UnknownKey => "The token was signed with an unknown key",
+            TokenError::InvalidSignature => "The token signature is invalid",
+            TokenError::Expired => "The token has expired",
+            TokenError::NotYetValid => "The token is not valid yet",
+            TokenError::InvalidIssuer => "The token was issued by an unexpected issuer",
+            TokenError::InvalidAudience => "The token is not intended for this audience",
+            TokenError::MissingClaim => "The token is missing a required claim",
+            TokenError::Revoked => "The token has been revoked",
+            TokenError::InvalidApiKey => "The API key is invalid or expired",
+            TokenError::InvalidCsrfToken => "The CSRF token is missing or doesn't match the cookie",
+        }
+    }
+}
+
+impl std::fmt::Display for TokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.description())
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema, Clone)]
+pub struct TokenErrorResponse {
+    pub error: TokenError,
+    pub message: String,
+}
+
+impl From<TokenError> for TokenErrorResponse {
+    fn from(error: TokenError) -> Self {
+        TokenErrorResponse {
+            error,
+            message: error.description().to_string(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, ToSchema, Clone)]
@@ -15,3 +133,68 @@ pub struct LoginRequest {
     pub email: String,
     pub password: String,
 }
+
+#[derive(Deserialize, Debug, ToSchema, Clone)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Deserialize, Debug, ToSchema, Clone)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Clone)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Clone)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: String,
+    pub expires_in: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Clone)]
+pub struct LogoutRequest {
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Clone)]
+pub struct MfaChallengeResponse {
+    pub mfa_required: bool,
+    // Exchange at /auth/mfa/verify together with a code
+    pub mfa_token: String,
+    pub expires_in: usize,
+}
+
+// Either a code from the authenticator or one of the recovery codes
+#[derive(Deserialize, Debug, ToSchema, Clone)]
+pub struct MfaCodeRequest {
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+#[derive(Deserialize, Debug, ToSchema, Clone)]
+pub struct MfaVerifyRequest {
+    pub mfa_token: String,
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+#[derive(Deserialize, Debug, ToSchema, Clone)]
+pub struct TotpConfirmRequest {
+    pub code: String,
+}
+
+// Query string of the redirect back from an OpenID Connect provider
+#[derive(Deserialize, Debug, Clone)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
@@ -0,0 +1,523 @@
+use std::{
+    collections::HashMap,
+    env,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use openssl::sha::sha256;
+use serde::{de::DeserializeOwned, Deserialize};
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+// Discovery documents are refetched after this long, so endpoint changes are picked up
+const METADATA_LIFETIME: Duration = Duration::from_secs(60 * 60);
+const MAX_RESPONSE_BYTES: usize = 1024 * 1024;
+// Seconds of clock skew tolerated on the provider's ID tokens
+const ID_TOKEN_LEEWAY: u64 = 60;
+// Symmetric algorithms would let anyone holding the client secret forge ID tokens
+const ID_TOKEN_ALGORITHMS: [Algorithm; 8] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+];
+
+#[derive(Debug)]
+pub enum OidcError {
+    UnknownProvider,
+    Provider(String),
+    InvalidIdToken(String),
+}
+
+// One provider, configured with OIDC_<NAME>_* environment variables
+#[derive(Debug, Clone)]
+pub struct OidcProvider {
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    // Public clients rely on PKCE alone
+    pub client_secret: Option<String>,
+    pub redirect_uri: String,
+    pub scopes: String,
+}
+
+impl OidcProvider {
+    fn from_env(name: &str) -> Result<Self, String> {
+        let prefix = format!("OIDC_{}_", name.to_uppercase());
+        let required = |key: &str| {
+            env::var(format!("{}{}", prefix, key))
+                .map_err(|_| format!("{}{} must be set for OIDC provider {}", prefix, key, name))
+        };
+
+        Ok(OidcProvider {
+            name: name.to_string(),
+            issuer: required("ISSUER")?.trim_end_matches('/').to_string(),
+            client_id: required("CLIENT_ID")?,
+            client_secret: required("CLIENT_SECRET").ok(),
+            redirect_uri: required("REDIRECT_URI")?,
+            scopes: required("SCOPES").unwrap_or_else(|_| "openid email profile".to_string()),
+        })
+    }
+}
+
+// Subset of the provider's /.well-known/openid-configuration
+#[derive(Deserialize, Debug, Clone)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenEndpointResponse {
+    id_token: String,
+}
+
+// Claims of a verified ID token used to find or create the local account
+#[derive(Deserialize, Debug, Clone)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub name: Option<String>,
+    pub preferred_username: Option<String>,
+    nonce: Option<String>,
+}
+
+// PKCE S256 code challenge for a code verifier (RFC 7636)
+pub fn code_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(sha256(code_verifier.as_bytes()))
+}
+
+// OpenID Connect relying party for every configured provider. Discovery documents and
+// signing keys are fetched on first use and cached, documents for METADATA_LIFETIME and
+// keys until a token is signed with an unknown key id.
+pub struct OidcClient {
+    providers: HashMap<String, OidcProvider>,
+    metadata: Mutex<HashMap<String, (ProviderMetadata, Instant)>>,
+    jwks: Mutex<HashMap<String, JwkSet>>,
+}
+
+impl OidcClient {
+    pub fn new(providers: Vec<OidcProvider>) -> Self {
+        OidcClient {
+            providers: providers
+                .into_iter()
+                .map(|provider| (provider.name.clone(), provider))
+                .collect(),
+            metadata: Mutex::new(HashMap::new()),
+            jwks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // OIDC_PROVIDERS is a comma separated list of provider names, empty disables OIDC login
+    pub fn from_env() -> Result<Self, String> {
+        let providers = env::var("OIDC_PROVIDERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(OidcProvider::from_env)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(OidcClient::new(providers))
+    }
+
+    pub fn provider(&self, name: &str) -> Result<&OidcProvider, OidcError> {
+        self.providers.get(name).ok_or(OidcError::UnknownProvider)
+    }
+
+    pub async fn metadata(&self, provider: &OidcProvider) -> Result<ProviderMetadata, OidcError> {
+        if let Some((metadata, fetched_at)) = self.metadata.lock().unwrap().get(&provider.name) {
+            if fetched_at.elapsed() < METADATA_LIFETIME {
+                return Ok(metadata.clone());
+            }
+        }
+
+        let url = format!("{}/.well-known/openid-configuration", provider.issuer);
+        let metadata: ProviderMetadata = fetch_json(awc::Client::default().get(url)).await?;
+        if metadata.issuer.trim_end_matches('/') != provider.issuer {
+            return Err(OidcError::Provider(format!(
+                "Discovery document is for issuer {}",
+                metadata.issuer
+            )));
+        }
+        self.metadata
+            .lock()
+            .unwrap()
+            .insert(provider.name.clone(), (metadata.clone(), Instant::now()));
+        Ok(metadata)
+    }
+
+    pub async fn authorization_url(
+        &self,
+        provider: &OidcProvider,
+        state: &str,
+        nonce: &str,
+        code_verifier: &str,
+    ) -> Result<String, OidcError> {
+        let metadata = self.metadata(provider).await?;
+        let query = serde_urlencoded::to_string([
+            ("response_type", "code"),
+            ("client_id", provider.client_id.as_str()),
+            ("redirect_uri", provider.redirect_uri.as_str()),
+            ("scope", provider.scopes.as_str()),
+            ("state", state),
+            ("nonce", nonce),
+            ("code_challenge", code_challenge(code_verifier).as_str()),
+            ("code_challenge_method", "S256"),
+        ])
+        .map_err(|e| OidcError::Provider(e.to_string()))?;
+
+        let separator = if metadata.authorization_endpoint.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        Ok(format!(
+            "{}{}{}",
+            metadata.authorization_endpoint, separator, query
+        ))
+    }
+
+    // Redeem an authorization code and return the claims of the verified ID token
+    pub async fn exchange_code(
+        &self,
+        provider: &OidcProvider,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, OidcError> {
+        let metadata = self.metadata(provider).await?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", provider.redirect_uri.as_str()),
+            ("client_id", provider.client_id.as_str()),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(client_secret) = &provider.client_secret {
+            form.push(("client_secret", client_secret.as_str()));
+        }
+
+        let request = awc::Client::default().post(&metadata.token_endpoint);
+        let response: TokenEndpointResponse = fetch_json_form(request, &form).await?;
+        let claims = self
+            .verify_id_token(provider, &metadata, &response.id_token)
+            .await?;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(OidcError::InvalidIdToken("Nonce mismatch".to_string()));
+        }
+        Ok(claims)
+    }
+
+    async fn verify_id_token(
+        &self,
+        provider: &OidcProvider,
+        metadata: &ProviderMetadata,
+        id_token: &str,
+    ) -> Result<IdTokenClaims, OidcError> {
+        let header = decode_header(id_token)
+            .map_err(|_| OidcError::InvalidIdToken("Malformed ID token".to_string()))?;
+        if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+            return Err(OidcError::InvalidIdToken(format!(
+                "Unsupported algorithm {:?}",
+                header.alg
+            )));
+        }
+
+        let jwk = match self
+            .find_jwk(provider, metadata, header.kid.as_deref(), false)
+            .await?
+        {
+            Some(jwk) => jwk,
+            // The provider may have rotated its keys since they were cached
+            None => self
+                .find_jwk(provider, metadata, header.kid.as_deref(), true)
+                .await?
+                .ok_or_else(|| OidcError::InvalidIdToken("Unknown signing key".to_string()))?,
+        };
+        let key = DecodingKey::from_jwk(&jwk)
+            .map_err(|_| OidcError::InvalidIdToken("Unusable signing key".to_string()))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[metadata.issuer.as_str()]);
+        validation.set_audience(&[provider.client_id.as_str()]);
+        validation.set_required_spec_claims(&["sub", "iss", "aud", "exp", "iat"]);
+        validation.leeway = ID_TOKEN_LEEWAY;
+
+        decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map(|token_data| token_data.claims)
+            .map_err(|e| OidcError::InvalidIdToken(e.to_string()))
+    }
+
+    async fn find_jwk(
+        &self,
+        provider: &OidcProvider,
+        metadata: &ProviderMetadata,
+        kid: Option<&str>,
+        refresh: bool,
+    ) -> Result<Option<Jwk>, OidcError> {
+        let cached = self.jwks.lock().unwrap().get(&provider.name).cloned();
+        let jwks = match cached {
+            Some(jwks) if !refresh => jwks,
+            _ => {
+                let jwks: JwkSet =
+                    fetch_json(awc::Client::default().get(&metadata.jwks_uri)).await?;
+                self.jwks
+                    .lock()
+                    .unwrap()
+                    .insert(provider.name.clone(), jwks.clone());
+                jwks
+            }
+        };
+
+        Ok(match kid {
+            Some(kid) => jwks.find(kid).cloned(),
+            // Providers with a single key don't always set a key id
+            None if jwks.keys.len() == 1 => jwks.keys.first().cloned(),
+            None => None,
+        })
+    }
+}
+
+async fn fetch_json<T: DeserializeOwned>(request: awc::ClientRequest) -> Result<T, OidcError> {
+    let mut response = request
+        .timeout(HTTP_TIMEOUT)
+        .send()
+        .await
+        .map_err(|e| OidcError::Provider(e.to_string()))?;
+    read_json(&mut response).await
+}
+
+async fn fetch_json_form<T: DeserializeOwned>(
+    request: awc::ClientRequest,
+    form: &[(&str, &str)],
+) -> Result<T, OidcError> {
+    let mut response = request
+        .timeout(HTTP_TIMEOUT)
+        .send_form(&form)
+        .await
+        .map_err(|e| OidcError::Provider(e.to_string()))?;
+    read_json(&mut response).await
+}
+
+async fn read_json<T: DeserializeOwned, S>(
+    response: &mut awc::ClientResponse<S>,
+) -> Result<T, OidcError>
+where
+    S: futures::Stream<Item = Result<actix_web::web::Bytes, awc::error::PayloadError>> + Unpin,
+{
+    let status = response.status();
+    let body = response
+        .body()
+        .limit(MAX_RESPONSE_BYTES)
+        .await
+        .map_err(|e| OidcError::Provider(e.to_string()))?;
+    if !status.is_success() {
+        return Err(OidcError::Provider(format!(
+            "Provider answered {}: {}",
+            status,
+            String::from_utf8_lossy(&body)
+        )));
+    }
+    serde_json::from_slice(&body).map_err(|e| OidcError::Provider(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder};
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use openssl::rsa::Rsa;
+    use serde_json::json;
+
+    use super::{code_challenge, OidcClient, OidcError, OidcProvider};
+
+    const CLIENT_ID: &str = "test-client";
+    const CODE: &str = "test-code";
+
+    // Local provider that hands out a signed ID token for CODE when the code verifier
+    // matches the challenge of the authorization request
+    struct MockProvider {
+        issuer: String,
+        private_key: Vec<u8>,
+        jwk: serde_json::Value,
+        code_challenge: String,
+        nonce: String,
+        audience: String,
+    }
+
+    type MockState = web::Data<Arc<Mutex<MockProvider>>>;
+
+    #[get("/.well-known/openid-configuration")]
+    async fn discovery(state: MockState) -> impl Responder {
+        let issuer = state.lock().unwrap().issuer.clone();
+        HttpResponse::Ok().json(json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{}/authorize", issuer),
+            "token_endpoint": format!("{}/token", issuer),
+            "jwks_uri": format!("{}/jwks", issuer),
+        }))
+    }
+
+    #[get("/jwks")]
+    async fn jwks(state: MockState) -> impl Responder {
+        HttpResponse::Ok().json(json!({ "keys": [state.lock().unwrap().jwk.clone()] }))
+    }
+
+    #[post("/token")]
+    async fn token(
+        state: MockState,
+        form: web::Form<std::collections::HashMap<String, String>>,
+    ) -> impl Responder {
+        let provider = state.lock().unwrap();
+        let verifier = form.get("code_verifier").cloned().unwrap_or_default();
+        if form.get("code").map(String::as_str) != Some(CODE)
+            || code_challenge(&verifier) != provider.code_challenge
+        {
+            return HttpResponse::BadRequest().json(json!({ "error": "invalid_grant" }));
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let claims = json!({
+            "iss": provider.issuer,
+            "aud": provider.audience,
+            "sub": "mock-subject",
+            "exp": now + 300,
+            "iat": now,
+            "nonce": provider.nonce,
+            "email": "mock.user@example.com",
+            "email_verified": true,
+        });
+        let mut header = Header::new(jsonwebtoken::Algorithm::RS256);
+        header.kid = Some("mock-key".to_string());
+        let key = EncodingKey::from_rsa_pem(&provider.private_key).unwrap();
+        let id_token = encode(&header, &claims, &key).unwrap();
+        HttpResponse::Ok().json(json!({ "access_token": "opaque", "id_token": id_token }))
+    }
+
+    async fn start_mock_provider(audience: &str) -> (OidcProvider, Arc<Mutex<MockProvider>>) {
+        let rsa = Rsa::generate(2048).unwrap();
+        let jwk = json!({
+            "kty": "RSA",
+            "kid": "mock-key",
+            "alg": "RS256",
+            "use": "sig",
+            "n": URL_SAFE_NO_PAD.encode(rsa.n().to_vec()),
+            "e": URL_SAFE_NO_PAD.encode(rsa.e().to_vec()),
+        });
+        let state = Arc::new(Mutex::new(MockProvider {
+            issuer: String::new(),
+            private_key: rsa.private_key_to_pem().unwrap(),
+            jwk,
+            code_challenge: String::new(),
+            nonce: String::new(),
+            audience: audience.to_string(),
+        }));
+
+        let app_state = state.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(web::Data::new(app_state.clone()))
+                .service(discovery)
+                .service(jwks)
+                .service(token)
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let issuer = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        state.lock().unwrap().issuer = issuer.clone();
+
+        let provider = OidcProvider {
+            name: "mock".to_string(),
+            issuer,
+            client_id: CLIENT_ID.to_string(),
+            client_secret: None,
+            redirect_uri: "http://localhost:3030/auth/oidc/mock/callback".to_string(),
+            scopes: "openid email".to_string(),
+        };
+        (provider, state)
+    }
+
+    #[actix_web::test]
+    async fn exchanges_a_code_for_verified_claims() {
+        let (provider, state) = start_mock_provider(CLIENT_ID).await;
+        let client = OidcClient::new(vec![provider.clone()]);
+
+        let url = client
+            .authorization_url(&provider, "state", "nonce", "verifier")
+            .await
+            .unwrap();
+        assert!(url.contains("code_challenge_method=S256"));
+        assert!(url.contains(&format!("code_challenge={}", code_challenge("verifier"))));
+        {
+            let mut state = state.lock().unwrap();
+            state.code_challenge = code_challenge("verifier");
+            state.nonce = "nonce".to_string();
+        }
+
+        let claims = client
+            .exchange_code(&provider, CODE, "verifier", "nonce")
+            .await
+            .unwrap();
+        assert_eq!(claims.sub, "mock-subject");
+        assert_eq!(claims.email.as_deref(), Some("mock.user@example.com"));
+        assert!(claims.email_verified);
+
+        // Wrong code verifier
+        assert!(matches!(
+            client
+                .exchange_code(&provider, CODE, "other", "nonce")
+                .await,
+            Err(OidcError::Provider(_))
+        ));
+        // Token issued for another authorization request
+        assert!(matches!(
+            client
+                .exchange_code(&provider, CODE, "verifier", "other")
+                .await,
+            Err(OidcError::InvalidIdToken(_))
+        ));
+    }
+
+    #[actix_web::test]
+    async fn rejects_id_tokens_for_other_clients() {
+        let (provider, state) = start_mock_provider("another-client").await;
+        let client = OidcClient::new(vec![provider.clone()]);
+        {
+            let mut state = state.lock().unwrap();
+            state.code_challenge = code_challenge("verifier");
+            state.nonce = "nonce".to_string();
+        }
+
+        assert!(matches!(
+            client
+                .exchange_code(&provider, CODE, "verifier", "nonce")
+                .await,
+            Err(OidcError::InvalidIdToken(_))
+        ));
+    }
+}
@@ -0,0 +1,265 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{prelude::*, result::QueryResult, PgConnection};
+use openssl::rand::rand_bytes;
+
+use super::client::IdTokenClaims;
+use crate::{
+    common::crypto::{generate_opaque_token, hash_token, to_hex},
+    database::model::{
+        user_identities::{
+            CreateOidcLoginStateDb, CreateUserIdentityDb, OidcLoginState, UserIdentity,
+        },
+        users::{CreateUserRequest, User, UserRole},
+    },
+    schema::{oidc_login_states, user_identities, users},
+    users::service::{create_user, find_user_by_email, find_user_by_id, find_user_by_username},
+    OIDC_LOGIN_LIFETIME,
+};
+
+#[derive(Debug)]
+pub enum OidcLoginError {
+    InvalidState,
+    EmailNotVerified,
+    UnverifiedAccount,
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for OidcLoginError {
+    fn from(e: diesel::result::Error) -> Self {
+        OidcLoginError::Database(e)
+    }
+}
+
+// Values of an authorization request, kept until the provider redirects back
+pub struct PendingLogin {
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+pub fn start_login(conn: &mut PgConnection, provider: &str) -> QueryResult<PendingLogin> {
+    diesel::delete(oidc_login_states::table.filter(oidc_login_states::expires_at.lt(now())))
+        .execute(conn)?;
+
+    let pending = PendingLogin {
+        state: generate_opaque_token(),
+        nonce: generate_opaque_token(),
+        code_verifier: generate_opaque_token(),
+    };
+    diesel::insert_into(oidc_login_states::table)
+        .values(CreateOidcLoginStateDb {
+            state_hash: hash_token(&pending.state),
+            provider: provider.to_string(),
+            nonce: pending.nonce.clone(),
+            code_verifier: pending.code_verifier.clone(),
+            expires_at: now() + Duration::seconds(OIDC_LOGIN_LIFETIME as i64),
+        })
+        .execute(conn)?;
+
+    Ok(pending)
+}
+
+// A state can only be used once, for the provider it was issued for
+pub fn take_login_state(
+    conn: &mut PgConnection,
+    state: &str,
+    provider: &str,
+) -> Result<OidcLoginState, OidcLoginError> {
+    let stored = diesel::delete(
+        oidc_login_states::table.filter(oidc_login_states::state_hash.eq(hash_token(state))),
+    )
+    .returning(OidcLoginState::as_returning())
+    .get_result(conn)
+    .optional()?
+    .ok_or(OidcLoginError::InvalidState)?;
+
+    if stored.provider != provider || stored.expires_at < now() {
+        return Err(OidcLoginError::InvalidState);
+    }
+    Ok(stored)
+}
+
+fn username_for(claims: &IdTokenClaims, email: &str) -> String {
+    claims
+        .preferred_username
+        .clone()
+        .or_else(|| claims.name.clone())
+        .unwrap_or_else(|| email.split('@').next().unwrap_or(email).to_string())
+}
+
+// Users are looked up by username, so a taken one gets a random suffix, e.g. jane-3f9a1c
+fn unique_username_for(
+    conn: &mut PgConnection,
+    claims: &IdTokenClaims,
+    email: &str,
+) -> QueryResult<String> {
+    let base = username_for(claims, email);
+    let mut username = base.clone();
+    while find_user_by_username(conn, &username)?.is_some() {
+        let mut buf = [0u8; 3];
+        rand_bytes(&mut buf).expect("Error: Unable to generate random bytes");
+        username = format!("{}-{}", base, to_hex(&buf));
+    }
+    Ok(username)
+}
+
+// Find the user linked to the provider account. Without a link, the account is linked to
+// the user with the same email address, or a new user is created, but only if the
+// provider verified that address. Otherwise anyone could claim an existing account by
+// registering its email address at the provider. Likewise existing accounts are only
+// linked once their own email address is verified, so an account registered in advance
+// by someone else can't be taken over through the provider.
+pub fn link_or_create_user(
+    conn: &mut PgConnection,
+    provider: &str,
+    claims: &IdTokenClaims,
+) -> Result<User, OidcLoginError> {
+    conn.transaction(|conn| {
+        let identity = user_identities::table
+            .filter(user_identities::provider.eq(provider))
+            .filter(user_identities::subject.eq(&claims.sub))
+            .select(UserIdentity::as_select())
+            .first(conn)
+            .optional()?;
+
+        if let Some(identity) = identity {
+            diesel::update(user_identities::table.find(identity.id))
+                .set((
+                    user_identities::email.eq(&claims.email),
+                    user_identities::last_login_at.eq(now()),
+                ))
+                .execute(conn)?;
+            return find_user_by_id(conn, identity.user_id)?
+                .ok_or(OidcLoginError::Database(diesel::result::Error::NotFound));
+        }
+
+        let email = match &claims.email {
+            Some(email) if claims.email_verified => email,
+            _ => return Err(OidcLoginError::EmailNotVerified),
+        };
+        let user = match find_user_by_email(conn, email)? {
+            Some(user) if user.email_verified_at.is_none() => {
+                return Err(OidcLoginError::UnverifiedAccount)
+            }
+            Some(user) => user,
+            None => {
+                // The password is random and never shown, the user signs in through the
+                // provider or sets a password with the password reset flow
+                let username = unique_username_for(conn, claims, email)?;
+                let user = create_user(
+                    conn,
+                    CreateUserRequest {
+                        username,
+                        email: email.clone(),
+                        password: generate_opaque_token(),
+                        timezone: "UTC".to_string(),
+                        role: UserRole::User,
+                    },
+                )?;
+                diesel::update(users::table.find(user.id))
+                    .set(users::email_verified_at.eq(now()))
+                    .returning(User::as_returning())
+                    .get_result(conn)?
+            }
+        };
+
+        diesel::insert_into(user_identities::table)
+            .values(CreateUserIdentityDb {
+                user_id: user.id,
+                provider: provider.to_string(),
+                subject: claims.sub.clone(),
+                email: Some(email.clone()),
+                last_login_at: Some(now()),
+            })
+            .execute(conn)?;
+
+        Ok(user)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        database::{
+            model::users::UpdateUserRequest,
+            tools::testing::{create_test_user, test_pool},
+        },
+        users::service::{delete_user, update_user},
+    };
+
+    fn provider_claims(email: &str, username: &str) -> IdTokenClaims {
+        serde_json::from_value(json!({
+            "sub": format!("subject-{}", email),
+            "email": email,
+            "email_verified": true,
+            "preferred_username": username,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    fn changed_email_addresses_are_not_linked_until_verified() {
+        let pool = test_pool();
+        let attacker = create_test_user(&pool, UserRole::User);
+        let mut conn = pool.get().unwrap();
+        diesel::update(users::table.find(attacker.id))
+            .set(users::email_verified_at.eq(now()))
+            .execute(&mut conn)
+            .unwrap();
+        let victim_email = format!("victim.{}", attacker.email);
+
+        let changed = update_user(
+            &mut conn,
+            attacker.id,
+            UpdateUserRequest {
+                username: None,
+                email: Some(victim_email.clone()),
+                password: None,
+                timezone: None,
+                role: None,
+            },
+        )
+        .unwrap();
+        let linked =
+            link_or_create_user(&mut conn, "mock", &provider_claims(&victim_email, "victim"));
+        delete_user(&mut conn, attacker.id).unwrap();
+
+        assert_eq!(changed.email, victim_email);
+        assert!(changed.email_verified_at.is_none());
+        assert!(matches!(linked, Err(OidcLoginError::UnverifiedAccount)));
+    }
+
+    #[test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    fn taken_usernames_get_a_suffix() {
+        let pool = test_pool();
+        let existing = create_test_user(&pool, UserRole::User);
+        let mut conn = pool.get().unwrap();
+        let email = format!("oidc.{}", existing.email);
+
+        let created = link_or_create_user(
+            &mut conn,
+            "mock",
+            &provider_claims(&email, &existing.username),
+        );
+        if let Ok(created) = &created {
+            delete_user(&mut conn, created.id).unwrap();
+        }
+        delete_user(&mut conn, existing.id).unwrap();
+
+        let created = created.expect("The account should be created");
+        assert_eq!(created.email, email);
+        assert_ne!(created.username, existing.username);
+        assert!(created
+            .username
+            .starts_with(&format!("{}-", existing.username)));
+    }
+}
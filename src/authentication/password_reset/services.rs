@@ -0,0 +1,146 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{prelude::*, result::QueryResult, PgConnection};
+use uuid::Uuid;
+
+use crate::{
+    authentication::refresh::services::revoke_all_refresh_tokens,
+    common::{
+        crypto::{generate_opaque_token, hash_token},
+        mailer::{Email, Mailer},
+        password_policy::{validate_password, PasswordViolationDetail},
+    },
+    database::model::{
+        password_reset_tokens::{CreatePasswordResetTokenDb, PasswordResetToken},
+        users::{UpdateUserRequest, User},
+    },
+    schema::password_reset_tokens,
+    users::service::{find_user_by_email, find_user_by_id, update_user},
+    PASSWORD_RESET_LIFETIME,
+};
+
+#[derive(Debug)]
+pub enum PasswordResetError {
+    Invalid,
+    Expired,
+    WeakPassword(Vec<PasswordViolationDetail>),
+    Mail(String),
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for PasswordResetError {
+    fn from(e: diesel::result::Error) -> Self {
+        PasswordResetError::Database(e)
+    }
+}
+
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+// Mail a reset token if the email belongs to an account. Unknown emails succeed
+// silently so the route can't be used to find out which emails are registered, and the
+// route runs this after answering so the response time doesn't give it away either.
+pub fn request_password_reset(
+    conn: &mut PgConnection,
+    mailer: &dyn Mailer,
+    email: &str,
+) -> Result<(), PasswordResetError> {
+    let user = match find_user_by_email(conn, email)? {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+
+    // Only the most recently mailed token can be used
+    let token = conn.transaction(|conn| {
+        expire_reset_tokens(conn, user.id)?;
+        issue_reset_token(conn, user.id)
+    })?;
+
+    mailer
+        .send(&reset_email(&user, &token))
+        .map_err(PasswordResetError::Mail)
+}
+
+fn issue_reset_token(conn: &mut PgConnection, owner_id: Uuid) -> QueryResult<String> {
+    let token = generate_opaque_token();
+    let new_token = CreatePasswordResetTokenDb {
+        user_id: owner_id,
+        token_hash: hash_token(&token),
+        expires_at: now() + Duration::seconds(PASSWORD_RESET_LIFETIME as i64),
+    };
+
+    diesel::insert_into(password_reset_tokens::table)
+        .values(new_token)
+        .execute(conn)?;
+
+    Ok(token)
+}
+
+fn expire_reset_tokens(conn: &mut PgConnection, owner_id: Uuid) -> QueryResult<usize> {
+    diesel::update(
+        password_reset_tokens::table
+            .filter(password_reset_tokens::user_id.eq(owner_id))
+            .filter(password_reset_tokens::used_at.is_null()),
+    )
+    .set(password_reset_tokens::used_at.eq(now()))
+    .execute(conn)
+}
+
+fn reset_email(user: &User, token: &str) -> Email {
+    Email {
+        to: user.email.clone(),
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Hi {},\n\nReset your password by sending this token to /auth/password/reset within {} minutes:\n\n{}\n\nIf you didn't ask for a password reset you can ignore this email.\n",
+            user.username,
+            PASSWORD_RESET_LIFETIME / 60,
+            token
+        ),
+    }
+}
+
+// Set the new password and revoke the user's refresh tokens. Returns the user id so
+// the caller can also revoke access tokens that are still live.
+pub fn reset_password(
+    conn: &mut PgConnection,
+    presented: &str,
+    password: String,
+) -> Result<Uuid, PasswordResetError> {
+    conn.transaction(|conn| {
+        let token = password_reset_tokens::table
+            .filter(password_reset_tokens::token_hash.eq(hash_token(presented)))
+            .select(PasswordResetToken::as_select())
+            .for_update()
+            .first(conn)
+            .optional()?
+            .ok_or(PasswordResetError::Invalid)?;
+
+        if token.used_at.is_some() {
+            return Err(PasswordResetError::Invalid);
+        }
+        if token.expires_at < now() {
+            return Err(PasswordResetError::Expired);
+        }
+
+        // The token is only used up once the new password is accepted
+        let user = find_user_by_id(conn, token.user_id)?.ok_or(PasswordResetError::Invalid)?;
+        validate_password(&password, &[&user.username, &user.email])
+            .map_err(PasswordResetError::WeakPassword)?;
+
+        expire_reset_tokens(conn, token.user_id)?;
+        update_user(
+            conn,
+            token.user_id,
+            UpdateUserRequest {
+                username: None,
+                email: None,
+                password: Some(password),
+                timezone: None,
+                role: None,
+            },
+        )?;
+        revoke_all_refresh_tokens(conn, token.user_id)?;
+
+        Ok(token.user_id)
+    })
+}
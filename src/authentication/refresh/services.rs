@@ -0,0 +1,193 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{prelude::*, result::QueryResult, PgConnection};
+use log::warn;
+use uuid::Uuid;
+
+use crate::{
+    common::crypto::{generate_opaque_token, hash_token},
+    database::model::refresh_tokens::{CreateRefreshTokenDb, RefreshToken},
+    schema::refresh_tokens::{self, dsl::*},
+    REFRESH_TOKEN_LIFETIME,
+};
+
+#[derive(Debug)]
+pub enum RefreshTokenError {
+    Invalid,
+    Expired,
+    Reused,
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for RefreshTokenError {
+    fn from(e: diesel::result::Error) -> Self {
+        RefreshTokenError::Database(e)
+    }
+}
+
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+// Store a new refresh token for the user and return the plain token for the client.
+// Passing a family continues an existing rotation chain, otherwise a new one is started.
+pub fn issue_refresh_token(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    family: Option<Uuid>,
+) -> QueryResult<(RefreshToken, String)> {
+    store_refresh_token(conn, owner_id, family, None, None)
+}
+
+// Refresh token for an OAuth client acting on behalf of the user, limited to the scope
+pub fn issue_client_refresh_token(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    client: Uuid,
+    granted_scope: &str,
+) -> QueryResult<(RefreshToken, String)> {
+    store_refresh_token(
+        conn,
+        owner_id,
+        None,
+        Some(client),
+        Some(granted_scope.to_string()),
+    )
+}
+
+fn store_refresh_token(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    family: Option<Uuid>,
+    client: Option<Uuid>,
+    granted_scope: Option<String>,
+) -> QueryResult<(RefreshToken, String)> {
+    let token = generate_opaque_token();
+    let new_token = CreateRefreshTokenDb {
+        user_id: owner_id,
+        family_id: family.unwrap_or_else(Uuid::new_v4),
+        token_hash: hash_token(&token),
+        expires_at: now() + Duration::seconds(REFRESH_TOKEN_LIFETIME as i64),
+        client_id: client,
+        scope: granted_scope,
+    };
+
+    let stored = diesel::insert_into(refresh_tokens::table)
+        .values(new_token)
+        .returning(RefreshToken::as_returning())
+        .get_result(conn)?;
+
+    Ok((stored, token))
+}
+
+// Exchange a refresh token for a new one in the same family. Presenting a token that
+// has already been rotated revokes the whole family, since either the client or an
+// attacker is holding a stolen copy. Tokens are only accepted from the client they were
+// issued to, None being /auth/refresh.
+pub fn rotate_refresh_token(
+    conn: &mut PgConnection,
+    presented: &str,
+    client: Option<Uuid>,
+) -> Result<(RefreshToken, String), RefreshTokenError> {
+    let presented_hash = hash_token(presented);
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let existing = match refresh_tokens
+            .filter(token_hash.eq(&presented_hash))
+            .select(RefreshToken::as_select())
+            .for_update()
+            .first(conn)
+            .optional()?
+        {
+            Some(existing) => existing,
+            None => return Ok(Err(RefreshTokenError::Invalid)),
+        };
+        if existing.client_id != client {
+            return Ok(Err(RefreshTokenError::Invalid));
+        }
+
+        if existing.revoked_at.is_some() {
+            if existing.replaced_by.is_some() {
+                warn!(
+                    "Refresh token reuse detected for user {}, revoking family {}",
+                    existing.user_id, existing.family_id
+                );
+                revoke_refresh_token_family(conn, existing.family_id)?;
+                return Ok(Err(RefreshTokenError::Reused));
+            }
+            return Ok(Err(RefreshTokenError::Invalid));
+        }
+
+        if existing.expires_at <= now() {
+            return Ok(Err(RefreshTokenError::Expired));
+        }
+
+        let (replacement, token) = store_refresh_token(
+            conn,
+            existing.user_id,
+            Some(existing.family_id),
+            existing.client_id,
+            existing.scope.clone(),
+        )?;
+
+        diesel::update(refresh_tokens.find(existing.id))
+            .set((revoked_at.eq(now()), replaced_by.eq(replacement.id)))
+            .execute(conn)?;
+
+        Ok(Ok((replacement, token)))
+    })?
+}
+
+pub fn revoke_refresh_token_family(conn: &mut PgConnection, family: Uuid) -> QueryResult<usize> {
+    diesel::update(
+        refresh_tokens
+            .filter(family_id.eq(family))
+            .filter(revoked_at.is_null()),
+    )
+    .set(revoked_at.eq(now()))
+    .execute(conn)
+}
+
+// Revoke the family of a refresh token presented on logout, as long as it belongs to the caller.
+pub fn revoke_refresh_token(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    presented: &str,
+) -> QueryResult<usize> {
+    let family = refresh_tokens
+        .filter(token_hash.eq(hash_token(presented)))
+        .filter(user_id.eq(owner_id))
+        .select(family_id)
+        .first::<Uuid>(conn)
+        .optional()?;
+
+    match family {
+        Some(family) => revoke_refresh_token_family(conn, family),
+        None => Ok(0),
+    }
+}
+
+// Revoke what the user granted a client, e.g. when the consent is withdrawn
+pub fn revoke_client_refresh_tokens(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    client: Uuid,
+) -> QueryResult<usize> {
+    diesel::update(
+        refresh_tokens
+            .filter(user_id.eq(owner_id))
+            .filter(client_id.eq(client))
+            .filter(revoked_at.is_null()),
+    )
+    .set(revoked_at.eq(now()))
+    .execute(conn)
+}
+
+pub fn revoke_all_refresh_tokens(conn: &mut PgConnection, owner_id: Uuid) -> QueryResult<usize> {
+    diesel::update(
+        refresh_tokens
+            .filter(user_id.eq(owner_id))
+            .filter(revoked_at.is_null()),
+    )
+    .set(revoked_at.eq(now()))
+    .execute(conn)
+}
@@ -0,0 +1,158 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{
+    prelude::*,
+    result::{DatabaseErrorKind, QueryResult},
+    PgConnection,
+};
+use uuid::Uuid;
+
+use crate::{
+    common::{
+        crypto::{generate_opaque_token, hash_token},
+        mailer::{Email, Mailer},
+    },
+    database::model::{
+        email_verification_tokens::{CreateEmailVerificationTokenDb, EmailVerificationToken},
+        users::{CreateUserRequest, RegisterRequest, User, UserRole},
+    },
+    schema::{email_verification_tokens, users},
+    users::service::create_user,
+    EMAIL_VERIFICATION_LIFETIME,
+};
+
+#[derive(Debug)]
+pub enum RegistrationError {
+    AlreadyExists,
+    Mail(String),
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for RegistrationError {
+    fn from(e: diesel::result::Error) -> Self {
+        match e {
+            diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                RegistrationError::AlreadyExists
+            }
+            e => RegistrationError::Database(e),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum VerificationError {
+    Invalid,
+    Expired,
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for VerificationError {
+    fn from(e: diesel::result::Error) -> Self {
+        VerificationError::Database(e)
+    }
+}
+
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+// Self registered accounts start as Guest and become User once the email is verified.
+// The account is only created if the verification email could be sent.
+pub fn register_user(
+    conn: &mut PgConnection,
+    mailer: &dyn Mailer,
+    user_data: RegisterRequest,
+) -> Result<User, RegistrationError> {
+    conn.transaction(|conn| {
+        let user = create_user(
+            conn,
+            CreateUserRequest {
+                username: user_data.username,
+                email: user_data.email,
+                password: user_data.password,
+                timezone: user_data.timezone,
+                role: UserRole::Guest,
+            },
+        )?;
+
+        send_verification_email(conn, mailer, &user)?;
+        Ok(user)
+    })
+}
+
+// Mail a new verification token to the user's current email address. Run it in the
+// transaction that changed the address, so the change is undone if the mail can't be sent.
+pub fn send_verification_email(
+    conn: &mut PgConnection,
+    mailer: &dyn Mailer,
+    user: &User,
+) -> Result<(), RegistrationError> {
+    let token = issue_verification_token(conn, user.id)?;
+    mailer
+        .send(&verification_email(user, &token))
+        .map_err(RegistrationError::Mail)
+}
+
+fn issue_verification_token(conn: &mut PgConnection, owner_id: Uuid) -> QueryResult<String> {
+    let token = generate_opaque_token();
+    let new_token = CreateEmailVerificationTokenDb {
+        user_id: owner_id,
+        token_hash: hash_token(&token),
+        expires_at: now() + Duration::seconds(EMAIL_VERIFICATION_LIFETIME as i64),
+    };
+
+    diesel::insert_into(email_verification_tokens::table)
+        .values(new_token)
+        .execute(conn)?;
+
+    Ok(token)
+}
+
+fn verification_email(user: &User, token: &str) -> Email {
+    Email {
+        to: user.email.clone(),
+        subject: "Verify your email address".to_string(),
+        body: format!(
+            "Hi {},\n\nConfirm your email address by sending this token to /auth/verify-email within {} hours:\n\n{}\n",
+            user.username,
+            EMAIL_VERIFICATION_LIFETIME / 3600,
+            token
+        ),
+    }
+}
+
+// Mark the token as used and the user's email as verified.
+pub fn verify_email(conn: &mut PgConnection, presented: &str) -> Result<User, VerificationError> {
+    conn.transaction(|conn| {
+        let token = email_verification_tokens::table
+            .filter(email_verification_tokens::token_hash.eq(hash_token(presented)))
+            .select(EmailVerificationToken::as_select())
+            .for_update()
+            .first(conn)
+            .optional()?
+            .ok_or(VerificationError::Invalid)?;
+
+        if token.used_at.is_some() {
+            return Err(VerificationError::Invalid);
+        }
+        if token.expires_at < now() {
+            return Err(VerificationError::Expired);
+        }
+
+        diesel::update(email_verification_tokens::table.find(token.id))
+            .set(email_verification_tokens::used_at.eq(now()))
+            .execute(conn)?;
+
+        diesel::update(
+            users::table
+                .find(token.user_id)
+                .filter(users::role.eq(UserRole::Guest as i32)),
+        )
+        .set(users::role.eq(UserRole::User as i32))
+        .execute(conn)?;
+
+        Ok(diesel::update(users::table.find(token.user_id))
+            .set(users::email_verified_at.eq(now()))
+            .returning(User::as_returning())
+            .get_result(conn)?)
+    })
+}
@@ -0,0 +1,225 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use chrono::{NaiveDateTime, Timelike, Utc};
+use diesel::{prelude::*, result::QueryResult, PgConnection};
+use uuid::Uuid;
+
+use crate::{
+    authentication::model::Claims,
+    database::model::{
+        db::DbPool,
+        revoked_tokens::{CreateRevokedTokenDb, RevokedToken, UserTokenRevocation},
+    },
+    schema::{revoked_tokens, sessions, user_token_revocations},
+    sessions::service::end_all_sessions,
+    JWT_LIFETIME,
+};
+
+// How long the in-memory copy is trusted before it is reloaded from Postgres, which is
+// how revocations made by other instances reach this one.
+const SYNC_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+struct RevocationCache {
+    // jti -> token expiry, entries can be dropped once the token expires anyway
+    tokens: HashMap<Uuid, NaiveDateTime>,
+    // user id -> tokens issued before this time are revoked
+    users: HashMap<Uuid, NaiveDateTime>,
+    // sessions ended within the access token lifetime
+    sessions: HashSet<Uuid>,
+    synced_at: Option<Instant>,
+}
+
+// Revoked access tokens are stored in Postgres and mirrored in memory so the
+// authentication middleware doesn't need a database round trip on every request.
+pub struct RevocationStore {
+    pool: DbPool,
+    cache: RwLock<RevocationCache>,
+}
+
+fn timestamp_to_datetime(timestamp: usize) -> NaiveDateTime {
+    NaiveDateTime::from_timestamp_opt(timestamp as i64, 0).unwrap_or(NaiveDateTime::MAX)
+}
+
+// iat only has whole seconds, so user revocations are stored truncated to whole seconds
+// too. Comparing with `<` keeps a login right after the revocation, in the same second,
+// valid. Tokens issued earlier in that second are rejected through their ended session.
+fn issued_before(iat: usize, revoked_before: NaiveDateTime) -> bool {
+    timestamp_to_datetime(iat) < revoked_before
+}
+
+impl RevocationStore {
+    pub fn new(pool: DbPool) -> Self {
+        RevocationStore {
+            pool,
+            cache: RwLock::new(RevocationCache::default()),
+        }
+    }
+
+    pub fn is_revoked(&self, claims: &Claims) -> QueryResult<bool> {
+        let stale = match self.cache.read().unwrap().synced_at {
+            Some(synced_at) => synced_at.elapsed() >= SYNC_INTERVAL,
+            None => true,
+        };
+        if stale {
+            self.sync()?;
+        }
+
+        let cache = self.cache.read().unwrap();
+        if cache.tokens.contains_key(&claims.jti) {
+            return Ok(true);
+        }
+        if let Some(session_id) = claims.sid {
+            if cache.sessions.contains(&session_id) {
+                return Ok(true);
+            }
+        }
+
+        let revoked_before = Uuid::parse_str(&claims.sub)
+            .ok()
+            .and_then(|user_id| cache.users.get(&user_id));
+
+        Ok(match revoked_before {
+            Some(revoked_before) => issued_before(claims.iat, *revoked_before),
+            None => false,
+        })
+    }
+
+    // Revoke a single access token, e.g. the one used to call /auth/logout.
+    pub fn revoke_token(&self, claims: &Claims) -> QueryResult<()> {
+        let user_id = Uuid::parse_str(&claims.sub)
+            .map_err(|_| diesel::result::Error::QueryBuilderError("Invalid subject".into()))?;
+        let revoked = CreateRevokedTokenDb {
+            jti: claims.jti,
+            user_id,
+            expires_at: timestamp_to_datetime(claims.exp),
+        };
+
+        let mut conn = self.get_connection()?;
+        diesel::insert_into(revoked_tokens::table)
+            .values(&revoked)
+            .on_conflict_do_nothing()
+            .execute(&mut conn)?;
+
+        self.cache
+            .write()
+            .unwrap()
+            .tokens
+            .insert(revoked.jti, revoked.expires_at);
+        Ok(())
+    }
+
+    // Revoke every access token issued to the user before the current second, and end
+    // the user's sessions so their tokens from this second are rejected too.
+    pub fn revoke_all_for_user(&self, user_id: Uuid) -> QueryResult<()> {
+        let now = Utc::now().naive_utc();
+        let revocation = UserTokenRevocation {
+            user_id,
+            revoked_before: now.with_nanosecond(0).unwrap_or(now),
+        };
+
+        let mut conn = self.get_connection()?;
+        let ended_sessions = conn.transaction(|conn| {
+            diesel::insert_into(user_token_revocations::table)
+                .values(&revocation)
+                .on_conflict(user_token_revocations::user_id)
+                .do_update()
+                .set(user_token_revocations::revoked_before.eq(revocation.revoked_before))
+                .execute(conn)?;
+            end_all_sessions(conn, user_id)
+        })?;
+
+        let mut cache = self.cache.write().unwrap();
+        cache.users.insert(user_id, revocation.revoked_before);
+        cache.sessions.extend(ended_sessions);
+        Ok(())
+    }
+
+    // Reject the access tokens of a session that was just ended in the database
+    pub fn revoke_session(&self, session_id: Uuid) {
+        self.cache.write().unwrap().sessions.insert(session_id);
+    }
+
+    fn sync(&self) -> QueryResult<()> {
+        let mut conn = self.get_connection()?;
+        let now = Utc::now().naive_utc();
+
+        // Expired tokens are rejected by signature validation already
+        diesel::delete(revoked_tokens::table.filter(revoked_tokens::expires_at.lt(now)))
+            .execute(&mut conn)?;
+
+        let tokens = revoked_tokens::table
+            .select(RevokedToken::as_select())
+            .load(&mut conn)?;
+        let users = load_recent_user_revocations(&mut conn, now)?;
+        let ended_sessions = load_recently_ended_sessions(&mut conn, now)?;
+
+        let mut cache = self.cache.write().unwrap();
+        cache.tokens = tokens
+            .into_iter()
+            .map(|token| (token.jti, token.expires_at))
+            .collect();
+        cache.users = users
+            .into_iter()
+            .map(|revocation| (revocation.user_id, revocation.revoked_before))
+            .collect();
+        cache.sessions = ended_sessions.into_iter().collect();
+        cache.synced_at = Some(Instant::now());
+        Ok(())
+    }
+
+    fn get_connection(
+        &self,
+    ) -> QueryResult<r2d2::PooledConnection<diesel::r2d2::ConnectionManager<PgConnection>>> {
+        self.pool
+            .get()
+            .map_err(|e| diesel::result::Error::QueryBuilderError(e.into()))
+    }
+}
+
+// Revocations older than the access token lifetime can't match any live token.
+fn load_recent_user_revocations(
+    conn: &mut PgConnection,
+    now: NaiveDateTime,
+) -> QueryResult<Vec<UserTokenRevocation>> {
+    let oldest_live_token = now - chrono::Duration::seconds(JWT_LIFETIME as i64);
+    user_token_revocations::table
+        .filter(user_token_revocations::revoked_before.ge(oldest_live_token))
+        .select(UserTokenRevocation::as_select())
+        .load(conn)
+}
+
+fn load_recently_ended_sessions(
+    conn: &mut PgConnection,
+    now: NaiveDateTime,
+) -> QueryResult<Vec<Uuid>> {
+    let oldest_live_token = now - chrono::Duration::seconds(JWT_LIFETIME as i64);
+    sessions::table
+        .filter(sessions::revoked_at.ge(oldest_live_token))
+        .select(sessions::id)
+        .load(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::issued_before;
+
+    #[test]
+    fn logins_in_the_second_of_a_revocation_stay_valid() {
+        let revoked_before = NaiveDate::from_ymd_opt(2024, 1, 31)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let second = revoked_before.timestamp() as usize;
+
+        assert!(issued_before(second - 1, revoked_before));
+        assert!(!issued_before(second, revoked_before));
+        assert!(!issued_before(second + 1, revoked_before));
+    }
+}
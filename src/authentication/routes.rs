@@ -1,14 +1,226 @@
 use crate::{
+    authentication::cookies::services::{
+        clear_oidc_state_cookie, clear_session_cookies, cookie_mode, oidc_state_cookie,
+        refresh_token_cookie, set_session_cookies, verify_csrf, verify_oidc_state,
+    },
+    authentication::impersonation::services::{
+        find_impersonations, impersonate, ImpersonationError,
+    },
+    authentication::jwt::key_ring::key_ring,
+    authentication::jwt::key_ring::{
+        create_signing_key, find_all_signing_keys, key_source, promote_signing_key,
+        reload_key_ring, retire_signing_key, KeyRingError, KeySource,
+    },
     authentication::jwt::services::generate_token,
-    authentication::jwt::services::verify_login_credentials, common::model::AppError,
-    database::model::db::DbPool,
+    authentication::jwt::services::verify_login_credentials,
+    authentication::jwt::services::{decode_mfa_pending_token, generate_mfa_pending_token},
+    authentication::mfa::services::{
+        confirm_totp_enrollment, disable_totp, is_mfa_enabled, start_totp_enrollment,
+        verify_second_factor, MfaError,
+    },
+    authentication::oidc::client::{OidcClient, OidcError},
+    authentication::oidc::services::{
+        link_or_create_user, start_login, take_login_state, OidcLoginError,
+    },
+    authentication::password_reset::services::{
+        request_password_reset, reset_password, PasswordResetError,
+    },
+    authentication::refresh::services::{
+        issue_refresh_token, revoke_all_refresh_tokens, revoke_refresh_token, rotate_refresh_token,
+        RefreshTokenError,
+    },
+    authentication::registration::services::{
+        register_user, verify_email, RegistrationError, VerificationError,
+    },
+    authentication::revocation::services::RevocationStore,
+    authentication::throttle::services::LoginThrottle,
+    authentication::webauthn::services::{
+        delete_credential, find_credentials_for_user, finish_authentication, finish_registration,
+        start_authentication, start_registration, WebauthnError,
+    },
+    common::{mailer::Mailer, model::AppError, password_policy::validate_password},
+    database::{
+        model::db::DbPool,
+        model::impersonations::{ImpersonationRequest, ImpersonationResponse},
+        model::mfa::RecoveryCodesResponse,
+        model::signing_keys::{CreateSigningKeyRequest, SigningKey, SigningKeyResponse},
+        model::users::{RegisterRequest, User, UserResponse, VerifyEmailRequest},
+        model::webauthn::{
+            AuthenticationCredential, WebauthnCredentialResponse, WebauthnLoginStartRequest,
+            WebauthnRegisterFinishRequest,
+        },
+        tools::get_connection,
+    },
+    permissions::service::find_permissions_for_role,
+    sessions::service::{end_session, start_session, touch_session},
+    users::service::find_user_by_id,
+    IMPERSONATION_LIFETIME, JWT_LIFETIME, MFA_PENDING_LIFETIME,
 };
 
-use super::model::LoginRequest;
-use actix_web::{post, web, HttpResponse, Responder};
+use super::guard::{roles, AllowImpersonation, RequireRole};
+use super::middleware::AuthenticationCheck;
+use super::model::{
+    ForgotPasswordRequest, LoginRequest, LogoutRequest, MfaChallengeResponse, MfaCodeRequest,
+    MfaVerifyRequest, OidcCallbackQuery, RefreshRequest, ResetPasswordRequest, TokenError,
+    TokenResponse, TotpConfirmRequest,
+};
+use actix_web::{
+    delete, get,
+    http::header::{LOCATION, RETRY_AFTER, USER_AGENT},
+    post, web, HttpRequest, HttpResponse, Responder,
+};
+use diesel::{Connection, PgConnection};
+use log::error;
+use std::{net::IpAddr, time::Duration};
+use uuid::Uuid;
 
 pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(register_handler);
+    cfg.service(verify_email_handler);
     cfg.service(login_handler);
+    cfg.service(verify_mfa_handler);
+    cfg.service(start_totp_enrollment_handler);
+    cfg.service(confirm_totp_enrollment_handler);
+    cfg.service(disable_totp_handler);
+    cfg.service(start_webauthn_registration_handler);
+    cfg.service(finish_webauthn_registration_handler);
+    cfg.service(find_webauthn_credentials_handler);
+    cfg.service(delete_webauthn_credential_handler);
+    cfg.service(start_webauthn_login_handler);
+    cfg.service(finish_webauthn_login_handler);
+    cfg.service(start_oidc_login_handler);
+    cfg.service(oidc_callback_handler);
+    cfg.service(forgot_password_handler);
+    cfg.service(reset_password_handler);
+    cfg.service(refresh_handler);
+    cfg.service(logout_handler);
+    cfg.service(logout_all_handler);
+}
+
+pub fn well_known_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(jwks_handler);
+}
+
+pub fn admin_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(find_all_signing_keys_handler);
+    cfg.service(create_signing_key_handler);
+    cfg.service(promote_signing_key_handler);
+    cfg.service(retire_signing_key_handler);
+    cfg.service(reload_signing_keys_handler);
+    cfg.service(unlock_user_handler);
+    cfg.service(impersonate_user_handler);
+    cfg.service(find_impersonations_handler);
+}
+
+// create an account and mail a verification token to its email address
+#[utoipa::path(
+    path = "/auth/register",
+    request_body = RegisterRequest,
+    responses(
+        (status = 200, description = "Registered user. The account has the Guest role until the email address is verified.", body = UserResponse),
+        (status = 400, description = "User already exists or the password doesn't meet the password policy", body = PasswordPolicyErrorResponse),
+        (status = 500, description = "Internal Server Error")
+    ),
+    operation_id = "registerUser"
+)]
+#[post("/register")]
+async fn register_handler(
+    pool: web::Data<DbPool>,
+    mailer: web::Data<dyn Mailer>,
+    req_body: web::Json<RegisterRequest>,
+) -> Result<impl Responder, AppError> {
+    validate_password(&req_body.password, &[&req_body.username, &req_body.email])
+        .map_err(AppError::PasswordPolicyError)?;
+    let mut conn = get_connection(pool);
+
+    match register_user(&mut conn, mailer.as_ref(), req_body.into_inner()) {
+        Ok(user) => Ok(HttpResponse::Ok().json(UserResponse::from(user))),
+        Err(RegistrationError::AlreadyExists) => {
+            Err(AppError::ValidationError("User already exists".to_string()))
+        }
+        Err(RegistrationError::Mail(e)) => {
+            error!("Unable to send verification email, {}", e);
+            Err(AppError::DatabaseError("Internal Server Error".to_string()))
+        }
+        Err(RegistrationError::Database(e)) => {
+            error!("{:?}", e);
+            Err(AppError::DatabaseError("Internal Server Error".to_string()))
+        }
+    }
+}
+
+// confirm an email address with the token from the verification email
+#[utoipa::path(
+    path = "/auth/verify-email",
+    request_body = VerifyEmailRequest,
+    responses(
+        (status = 200, description = "Email verified, Guest accounts are promoted to User.", body = UserResponse),
+        (status = 400, description = "Invalid, used or expired verification token."),
+        (status = 500, description = "Internal Server Error")
+    ),
+    operation_id = "verifyEmail"
+)]
+#[post("/verify-email")]
+async fn verify_email_handler(
+    pool: web::Data<DbPool>,
+    req_body: web::Json<VerifyEmailRequest>,
+) -> Result<impl Responder, AppError> {
+    let mut conn = get_connection(pool);
+
+    match verify_email(&mut conn, &req_body.token) {
+        Ok(user) => Ok(HttpResponse::Ok().json(UserResponse::from(user))),
+        Err(VerificationError::Database(e)) => {
+            error!("{:?}", e);
+            Err(AppError::DatabaseError("Internal Server Error".to_string()))
+        }
+        Err(VerificationError::Expired) => Err(AppError::ValidationError(
+            "Verification token has expired".to_string(),
+        )),
+        Err(VerificationError::Invalid) => Err(AppError::ValidationError(
+            "Invalid verification token".to_string(),
+        )),
+    }
+}
+
+fn too_many_attempts(retry_after: Duration) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .insert_header((RETRY_AFTER, retry_after.as_secs().max(1)))
+        .json("Too many failed login attempts")
+}
+
+// Start a session for the device the request came from and issue its tokens
+fn login_response(
+    conn: &mut PgConnection,
+    req: &HttpRequest,
+    user: User,
+) -> Result<HttpResponse, AppError> {
+    let permissions = find_permissions_for_role(conn, user.role)
+        .map_err(|_| AppError::DatabaseError("Internal Server Error".to_string()))?;
+
+    let user_agent = req
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok());
+    let ip = req.peer_addr().map(|addr| addr.ip());
+    let (session, refresh_token) = conn
+        .transaction::<_, diesel::result::Error, _>(|conn| {
+            let session = start_session(conn, user.id, user_agent, ip)?;
+            let (_, refresh_token) = issue_refresh_token(conn, user.id, Some(session.id))?;
+            Ok((session, refresh_token))
+        })
+        .map_err(|_| AppError::DatabaseError("Internal Server Error".to_string()))?;
+
+    let token = generate_token(&user.id.to_string(), user.role, permissions, session.id);
+
+    let mut response = HttpResponse::Ok();
+    if cookie_mode() {
+        set_session_cookies(&mut response, &token, &refresh_token);
+    } else {
+        response
+            .append_header(("Authorization", format!("Bearer {}", token)))
+            .append_header(("Refresh-Token", refresh_token));
+    }
+    Ok(response.json(UserResponse::from(user)))
 }
 
 // sign in with email and password
@@ -19,26 +231,1214 @@ pub fn config(cfg: &mut web::ServiceConfig) {
         description = "Login credentials"
     ),
     responses(
-        (status = 200, description = "Logged in user.", body = User),
-        (status = 500, description = "Invalid credentials.")
+        (status = 200, description = "Logged in user. The access token is returned in the Authorization header and the refresh token in the Refresh-Token header. Users with two-factor authentication enabled get an MfaChallengeResponse instead, to be completed at /auth/mfa/verify.", body = UserResponse),
+        (status = 401, description = "Invalid credentials."),
+        (status = 429, description = "Too many failed logins for the account or IP address, retry after the number of seconds in the Retry-After header."),
+        (status = 500, description = "Internal Server Error")
     ),
     operation_id = "loginUser"
 )]
 #[post("/login")]
 async fn login_handler(
+    req: HttpRequest,
     pool: web::Data<DbPool>,
+    throttle: web::Data<LoginThrottle>,
     req_body: web::Json<LoginRequest>,
 ) -> Result<impl Responder, AppError> {
-    match verify_login_credentials(pool, req_body.into_inner()) {
+    let login_data = req_body.into_inner();
+    let email = login_data.email.clone();
+    let ip = req.peer_addr().map(|addr| addr.ip());
+
+    match throttle.retry_after(&email, ip) {
+        Ok(None) => {}
+        Ok(Some(retry_after)) => return Ok(too_many_attempts(retry_after)),
+        Err(_) => return Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+
+    match verify_login_credentials(pool.clone(), login_data) {
         Ok(user) => {
-            let token = generate_token(&user.id.to_string(), user.role);
+            let mut conn = get_connection(pool);
+            let mfa_enabled = is_mfa_enabled(&mut conn, user.id)
+                .map_err(|_| AppError::DatabaseError("Internal Server Error".to_string()))?;
+            // Failed attempts are only reset once the second factor is verified as well
+            if mfa_enabled {
+                return Ok(HttpResponse::Ok().json(MfaChallengeResponse {
+                    mfa_required: true,
+                    mfa_token: generate_mfa_pending_token(&user.id.to_string()),
+                    expires_in: MFA_PENDING_LIFETIME,
+                }));
+            }
+
+            throttle
+                .record_success(&email)
+                .map_err(|_| AppError::DatabaseError("Internal Server Error".to_string()))?;
+            login_response(&mut conn, &req, user)
+        }
+        Err(_) => {
+            throttle
+                .record_failure(&email, ip)
+                .map_err(|_| AppError::DatabaseError("Internal Server Error".to_string()))?;
+            Err(AppError::UnauthorizedError(
+                "Invalid credentials".to_string(),
+            ))
+        }
+    }
+}
+
+// complete a login with a TOTP or recovery code
+#[utoipa::path(
+    path = "/auth/mfa/verify",
+    request_body(
+        content = MfaVerifyRequest,
+        description = "Token from the login response and either a TOTP code or a recovery code"
+    ),
+    responses(
+        (status = 200, description = "Logged in user. The access token is returned in the Authorization header and the refresh token in the Refresh-Token header.", body = UserResponse),
+        (status = 401, description = "Invalid or expired MFA token, or an invalid code."),
+        (status = 429, description = "Too many failed logins for the account or IP address, retry after the number of seconds in the Retry-After header."),
+        (status = 500, description = "Internal Server Error")
+    ),
+    operation_id = "verifyMfa"
+)]
+#[post("/mfa/verify")]
+async fn verify_mfa_handler(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    throttle: web::Data<LoginThrottle>,
+    req_body: web::Json<MfaVerifyRequest>,
+) -> Result<impl Responder, AppError> {
+    let request = req_body.into_inner();
+    let ip = req.peer_addr().map(|addr| addr.ip());
+
+    let user_id = decode_mfa_pending_token(&request.mfa_token)
+        .ok()
+        .and_then(|claims| Uuid::parse_str(&claims.sub).ok())
+        .ok_or_else(|| AppError::UnauthorizedError("Invalid MFA token".to_string()))?;
+
+    let mut conn = get_connection(pool);
+    let user = match find_user_by_id(&mut conn, user_id) {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(AppError::UnauthorizedError("Invalid MFA token".to_string())),
+        Err(_) => return Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    };
+
+    match throttle.retry_after(&user.email, ip) {
+        Ok(None) => {}
+        Ok(Some(retry_after)) => return Ok(too_many_attempts(retry_after)),
+        Err(_) => return Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+
+    match verify_second_factor(
+        &mut conn,
+        user.id,
+        request.code.as_deref(),
+        request.recovery_code.as_deref(),
+    ) {
+        Ok(_) => {
+            throttle
+                .record_success(&user.email)
+                .map_err(|_| AppError::DatabaseError("Internal Server Error".to_string()))?;
+            login_response(&mut conn, &req, user)
+        }
+        Err(MfaError::Database(e)) => {
+            error!("{:?}", e);
+            Err(AppError::DatabaseError("Internal Server Error".to_string()))
+        }
+        Err(_) => {
+            throttle
+                .record_failure(&user.email, ip)
+                .map_err(|_| AppError::DatabaseError("Internal Server Error".to_string()))?;
+            Err(AppError::UnauthorizedError("Invalid code".to_string()))
+        }
+    }
+}
+
+fn mfa_error(e: MfaError) -> AppError {
+    match e {
+        MfaError::AlreadyEnabled => {
+            AppError::ValidationError("Two-factor authentication is already enabled".to_string())
+        }
+        MfaError::NotEnrolled => {
+            AppError::ValidationError("Two-factor authentication is not set up".to_string())
+        }
+        MfaError::InvalidCode => AppError::ValidationError("Invalid code".to_string()),
+        MfaError::Database(e) => {
+            error!("{:?}", e);
+            AppError::DatabaseError("Internal Server Error".to_string())
+        }
+    }
+}
+
+fn current_user(conn: &mut PgConnection, sub: &str) -> Result<User, AppError> {
+    let user_id = Uuid::parse_str(sub)
+        .map_err(|_| AppError::UnauthorizedError("Invalid token".to_string()))?;
+    match find_user_by_id(conn, user_id) {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(AppError::NotFoundError("User not found".to_string())),
+        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+}
+
+// generate a TOTP secret for the current user, it is enabled once confirmed with a code
+#[utoipa::path(
+    path = "/auth/mfa/totp/enroll",
+    responses(
+        (status = 200, description = "New TOTP secret and the otpauth URI to show as a QR code. Starting over replaces an unconfirmed secret.", body = TotpEnrollmentResponse),
+        (status = 400, description = "Two-factor authentication is already enabled"),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "startTotpEnrollment"
+)]
+#[post("/mfa/totp/enroll", wrap = "AuthenticationCheck")]
+async fn start_totp_enrollment_handler(
+    pool: web::Data<DbPool>,
+    claims: RequireRole<roles::Guest>,
+) -> Result<impl Responder, AppError> {
+    let mut conn = get_connection(pool);
+    let user = current_user(&mut conn, &claims.sub)?;
+
+    match start_totp_enrollment(&mut conn, &user) {
+        Ok(enrollment) => Ok(HttpResponse::Ok().json(enrollment)),
+        Err(e) => Err(mfa_error(e)),
+    }
+}
+
+// enable TOTP with a first code from the authenticator
+#[utoipa::path(
+    path = "/auth/mfa/totp/confirm",
+    request_body = TotpConfirmRequest,
+    responses(
+        (status = 200, description = "Two-factor authentication enabled. The recovery codes are only shown once.", body = RecoveryCodesResponse),
+        (status = 400, description = "Invalid code, no enrollment was started or two-factor authentication is already enabled"),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 429, description = "Too many failed codes or logins for the account or IP address, retry after the number of seconds in the Retry-After header."),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "confirmTotpEnrollment"
+)]
+#[post("/mfa/totp/confirm", wrap = "AuthenticationCheck")]
+async fn confirm_totp_enrollment_handler(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    throttle: web::Data<LoginThrottle>,
+    req_body: web::Json<TotpConfirmRequest>,
+    claims: RequireRole<roles::Guest>,
+) -> Result<impl Responder, AppError> {
+    let mut conn = get_connection(pool);
+    let user = current_user(&mut conn, &claims.sub)?;
+    let ip = req.peer_addr().map(|addr| addr.ip());
+    if let Some(response) = code_attempts_exceeded(&throttle, &user.email, ip)? {
+        return Ok(response);
+    }
+
+    let result = confirm_totp_enrollment(&mut conn, user.id, &req_body.code);
+    record_code_attempt(&throttle, &user.email, ip, &result)?;
+    match result {
+        Ok(recovery_codes) => Ok(HttpResponse::Ok().json(RecoveryCodesResponse { recovery_codes })),
+        Err(e) => Err(mfa_error(e)),
+    }
+}
+
+// turn TOTP off, requires a current code or a recovery code
+#[utoipa::path(
+    path = "/auth/mfa/totp/disable",
+    request_body = MfaCodeRequest,
+    responses(
+        (status = 200, description = "Two-factor authentication disabled and the recovery codes deleted."),
+        (status = 400, description = "Invalid code or two-factor authentication is not enabled"),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 429, description = "Too many failed codes or logins for the account or IP address, retry after the number of seconds in the Retry-After header."),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "disableTotp"
+)]
+#[post("/mfa/totp/disable", wrap = "AuthenticationCheck")]
+async fn disable_totp_handler(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    throttle: web::Data<LoginThrottle>,
+    req_body: web::Json<MfaCodeRequest>,
+    claims: RequireRole<roles::Guest>,
+) -> Result<impl Responder, AppError> {
+    let mut conn = get_connection(pool);
+    let user = current_user(&mut conn, &claims.sub)?;
+    let ip = req.peer_addr().map(|addr| addr.ip());
+    if let Some(response) = code_attempts_exceeded(&throttle, &user.email, ip)? {
+        return Ok(response);
+    }
+
+    let result = disable_totp(
+        &mut conn,
+        user.id,
+        req_body.code.as_deref(),
+        req_body.recovery_code.as_deref(),
+    );
+    record_code_attempt(&throttle, &user.email, ip, &result)?;
+    match result {
+        Ok(_) => Ok(HttpResponse::Ok().finish()),
+        Err(e) => Err(mfa_error(e)),
+    }
+}
+
+// Codes checked for a signed in user share the login lockout, otherwise a stolen access
+// token could be used to guess them at full speed
+fn code_attempts_exceeded(
+    throttle: &LoginThrottle,
+    email: &str,
+    ip: Option<IpAddr>,
+) -> Result<Option<HttpResponse>, AppError> {
+    match throttle.retry_after(email, ip) {
+        Ok(retry_after) => Ok(retry_after.map(too_many_attempts)),
+        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+}
+
+fn record_code_attempt<T>(
+    throttle: &LoginThrottle,
+    email: &str,
+    ip: Option<IpAddr>,
+    result: &Result<T, MfaError>,
+) -> Result<(), AppError> {
+    let recorded = match result {
+        Ok(_) => throttle.record_success(email),
+        Err(MfaError::InvalidCode) => throttle.record_failure(email, ip),
+        Err(_) => Ok(()),
+    };
+    recorded.map_err(|_| AppError::DatabaseError("Internal Server Error".to_string()))
+}
+
+fn webauthn_error(e: WebauthnError) -> AppError {
+    match e {
+        WebauthnError::InvalidChallenge => {
+            AppError::ValidationError("Invalid or expired challenge".to_string())
+        }
+        WebauthnError::InvalidResponse(message) => AppError::ValidationError(message),
+        WebauthnError::UnknownCredential => {
+            AppError::ValidationError("Unknown credential".to_string())
+        }
+        WebauthnError::AlreadyRegistered => {
+            AppError::ValidationError("Credential is already registered".to_string())
+        }
+        WebauthnError::Database(e) => {
+            error!("{:?}", e);
+            AppError::DatabaseError("Internal Server Error".to_string())
+        }
+    }
+}
+
+// start registering a passkey for the current user
+#[utoipa::path(
+    path = "/auth/webauthn/register/start",
+    responses(
+        (status = 200, description = "Options for navigator.credentials.create(), binary values are base64url encoded. The challenge expires after 5 minutes.", body = CredentialCreationOptions),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "startWebauthnRegistration"
+)]
+#[post("/webauthn/register/start", wrap = "AuthenticationCheck")]
+async fn start_webauthn_registration_handler(
+    pool: web::Data<DbPool>,
+    claims: RequireRole<roles::Guest>,
+) -> Result<impl Responder, AppError> {
+    let mut conn = get_connection(pool);
+    let user = current_user(&mut conn, &claims.sub)?;
+
+    match start_registration(&mut conn, &user) {
+        Ok(options) => Ok(HttpResponse::Ok().json(options)),
+        Err(e) => Err(webauthn_error(e)),
+    }
+}
+
+// store the passkey created by the authenticator
+#[utoipa::path(
+    path = "/auth/webauthn/register/finish",
+    request_body = WebauthnRegisterFinishRequest,
+    responses(
+        (status = 200, description = "Passkey registered.", body = WebauthnCredentialResponse),
+        (status = 400, description = "Invalid or expired challenge, or the attestation doesn't verify"),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "finishWebauthnRegistration"
+)]
+#[post("/webauthn/register/finish", wrap = "AuthenticationCheck")]
+async fn finish_webauthn_registration_handler(
+    pool: web::Data<DbPool>,
+    req_body: web::Json<WebauthnRegisterFinishRequest>,
+    claims: RequireRole<roles::Guest>,
+) -> Result<impl Responder, AppError> {
+    let user_id = Uuid::parse_str(&claims.sub)
+        .map_err(|_| AppError::UnauthorizedError("Invalid token".to_string()))?;
+    let mut conn = get_connection(pool);
+
+    match finish_registration(&mut conn, user_id, req_body.into_inner()) {
+        Ok(credential) => Ok(HttpResponse::Ok().json(WebauthnCredentialResponse::from(credential))),
+        Err(e) => Err(webauthn_error(e)),
+    }
+}
+
+// list the passkeys of the current user
+#[utoipa::path(
+    path = "/auth/webauthn/credentials",
+    responses(
+        (status = 200, description = "Registered passkeys", body = Vec<WebauthnCredentialResponse>),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "findWebauthnCredentials"
+)]
+#[get("/webauthn/credentials", wrap = "AuthenticationCheck")]
+async fn find_webauthn_credentials_handler(
+    pool: web::Data<DbPool>,
+    claims: AllowImpersonation<roles::Guest>,
+) -> Result<impl Responder, AppError> {
+    let user_id = Uuid::parse_str(&claims.sub)
+        .map_err(|_| AppError::UnauthorizedError("Invalid token".to_string()))?;
+    let mut conn = get_connection(pool);
+
+    match find_credentials_for_user(&mut conn, user_id) {
+        Ok(credentials) => Ok(HttpResponse::Ok().json(
+            credentials
+                .into_iter()
+                .map(WebauthnCredentialResponse::from)
+                .collect::<Vec<_>>(),
+        )),
+        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+}
+
+// remove a passkey of the current user
+#[utoipa::path(
+    path = "/auth/webauthn/credentials/{credential_id}",
+    responses(
+        (status = 200, description = "Passkey removed."),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 404, description = "Passkey not found"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "deleteWebauthnCredential"
+)]
+#[delete("/webauthn/credentials/{credential_id}", wrap = "AuthenticationCheck")]
+async fn delete_webauthn_credential_handler(
+    pool: web::Data<DbPool>,
+    credential_id: web::Path<Uuid>,
+    claims: RequireRole<roles::Guest>,
+) -> Result<impl Responder, AppError> {
+    let user_id = Uuid::parse_str(&claims.sub)
+        .map_err(|_| AppError::UnauthorizedError("Invalid token".to_string()))?;
+    let mut conn = get_connection(pool);
+
+    match delete_credential(&mut conn, user_id, *credential_id) {
+        Ok(0) => Err(AppError::NotFoundError("Passkey not found".to_string())),
+        Ok(_) => Ok(HttpResponse::Ok().finish()),
+        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+}
+
+// start signing in with a passkey
+#[utoipa::path(
+    path = "/auth/webauthn/login/start",
+    request_body(
+        content = Option<WebauthnLoginStartRequest>,
+        description = "Email of the account, omit it to let the authenticator pick a discoverable credential"
+    ),
+    responses(
+        (status = 200, description = "Options for navigator.credentials.get(), binary values are base64url encoded. The challenge expires after 5 minutes.", body = CredentialRequestOptions),
+        (status = 500, description = "Internal Server Error")
+    ),
+    operation_id = "startWebauthnLogin"
+)]
+#[post("/webauthn/login/start")]
+async fn start_webauthn_login_handler(
+    pool: web::Data<DbPool>,
+    req_body: Option<web::Json<WebauthnLoginStartRequest>>,
+) -> Result<impl Responder, AppError> {
+    let email = req_body.and_then(|body| body.into_inner().email);
+    let mut conn = get_connection(pool);
+
+    match start_authentication(&mut conn, email.as_deref()) {
+        Ok(options) => Ok(HttpResponse::Ok().json(options)),
+        Err(e) => Err(webauthn_error(e)),
+    }
+}
+
+// finish signing in with a passkey, issues the same tokens as /auth/login
+#[utoipa::path(
+    path = "/auth/webauthn/login/finish",
+    request_body = AuthenticationCredential,
+    responses(
+        (status = 200, description = "Logged in user. The access token is returned in the Authorization header and the refresh token in the Refresh-Token header. Users with two-factor authentication enabled get an MfaChallengeResponse instead.", body = UserResponse),
+        (status = 401, description = "Invalid or expired challenge, unknown passkey or the assertion doesn't verify"),
+        (status = 429, description = "Too many failed logins from the IP address, retry after the number of seconds in the Retry-After header."),
+        (status = 500, description = "Internal Server Error")
+    ),
+    operation_id = "finishWebauthnLogin"
+)]
+#[post("/webauthn/login/finish")]
+async fn finish_webauthn_login_handler(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    throttle: web::Data<LoginThrottle>,
+    req_body: web::Json<AuthenticationCredential>,
+) -> Result<impl Responder, AppError> {
+    // The account is only known once the assertion verifies, so failures count against
+    // the IP address
+    let ip = req.peer_addr().map(|addr| addr.ip());
+    if let Some(retry_after) = throttle.ip_retry_after(ip) {
+        return Ok(too_many_attempts(retry_after));
+    }
+    let mut conn = get_connection(pool);
 
-            Ok(HttpResponse::Ok()
-                .append_header(("Authorization", format!("Bearer {}", token)))
-                .json(user))
+    let user_id = match finish_authentication(&mut conn, req_body.into_inner()) {
+        Ok(user_id) => user_id,
+        Err(WebauthnError::Database(e)) => {
+            error!("{:?}", e);
+            return Err(AppError::DatabaseError("Internal Server Error".to_string()));
+        }
+        Err(e) => {
+            error!("Passkey login failed, {:?}", e);
+            throttle.record_ip_failure(ip);
+            return Err(AppError::UnauthorizedError(
+                "Invalid passkey assertion".to_string(),
+            ));
         }
-        Err(_) => Err(AppError::UnauthorizedError(
-            "Invalid credentials".to_string(),
+    };
+
+    match find_user_by_id(&mut conn, user_id) {
+        Ok(Some(user)) => complete_login(&mut conn, &req, user),
+        Ok(None) => Err(AppError::UnauthorizedError(
+            "Invalid passkey assertion".to_string(),
         )),
+        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+}
+
+fn oidc_error(e: OidcError) -> AppError {
+    match e {
+        OidcError::UnknownProvider => AppError::NotFoundError("Unknown provider".to_string()),
+        OidcError::Provider(message) => {
+            error!("OpenID Connect provider error, {}", message);
+            AppError::UnauthorizedError("The provider rejected the login".to_string())
+        }
+        OidcError::InvalidIdToken(message) => {
+            error!("Invalid ID token, {}", message);
+            AppError::UnauthorizedError("The provider rejected the login".to_string())
+        }
+    }
+}
+
+// Issue tokens after an external login, or ask for the second factor if it is enabled
+fn complete_login(
+    conn: &mut PgConnection,
+    req: &HttpRequest,
+    user: User,
+) -> Result<HttpResponse, AppError> {
+    let mfa_enabled = is_mfa_enabled(conn, user.id)
+        .map_err(|_| AppError::DatabaseError("Internal Server Error".to_string()))?;
+    if mfa_enabled {
+        return Ok(HttpResponse::Ok().json(MfaChallengeResponse {
+            mfa_required: true,
+            mfa_token: generate_mfa_pending_token(&user.id.to_string()),
+            expires_in: MFA_PENDING_LIFETIME,
+        }));
+    }
+    login_response(conn, req, user)
+}
+
+// redirect to an OpenID Connect provider to sign in
+#[utoipa::path(
+    path = "/auth/oidc/{provider}/authorize",
+    params(
+        ("provider" = String, Path, description = "Provider name from OIDC_PROVIDERS")
+    ),
+    responses(
+        (status = 302, description = "Redirect to the provider's authorization endpoint, with a cookie that ties the login to this browser. The request expires after 10 minutes."),
+        (status = 401, description = "The provider's discovery document couldn't be loaded"),
+        (status = 404, description = "Unknown provider"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    operation_id = "startOidcLogin"
+)]
+#[get("/oidc/{provider}/authorize")]
+async fn start_oidc_login_handler(
+    pool: web::Data<DbPool>,
+    oidc_client: web::Data<OidcClient>,
+    provider: web::Path<String>,
+) -> Result<impl Responder, AppError> {
+    let provider = oidc_client.provider(&provider).map_err(oidc_error)?;
+    let mut conn = get_connection(pool);
+
+    let pending = start_login(&mut conn, &provider.name)
+        .map_err(|_| AppError::DatabaseError("Internal Server Error".to_string()))?;
+    let url = oidc_client
+        .authorization_url(
+            provider,
+            &pending.state,
+            &pending.nonce,
+            &pending.code_verifier,
+        )
+        .await
+        .map_err(oidc_error)?;
+
+    Ok(HttpResponse::Found()
+        .insert_header((LOCATION, url))
+        .cookie(oidc_state_cookie(&pending.state))
+        .finish())
+}
+
+// redirect target of the provider, signs in the linked user or creates one
+#[utoipa::path(
+    path = "/auth/oidc/{provider}/callback",
+    params(
+        ("provider" = String, Path, description = "Provider name from OIDC_PROVIDERS"),
+        ("code" = Option<String>, Query, description = "Authorization code"),
+        ("state" = Option<String>, Query, description = "State of the authorization request"),
+        ("error" = Option<String>, Query, description = "Error reported by the provider")
+    ),
+    responses(
+        (status = 200, description = "Logged in user. The access token is returned in the Authorization header and the refresh token in the Refresh-Token header. Users with two-factor authentication enabled get an MfaChallengeResponse instead.", body = UserResponse),
+        (status = 400, description = "Invalid or expired state, a state that wasn't issued to this browser, the provider reported an error, the provider didn't verify the email address or an unverified account uses it"),
+        (status = 401, description = "The code exchange or the ID token verification failed"),
+        (status = 404, description = "Unknown provider"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    operation_id = "oidcCallback"
+)]
+#[get("/oidc/{provider}/callback")]
+async fn oidc_callback_handler(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    oidc_client: web::Data<OidcClient>,
+    provider: web::Path<String>,
+    query: web::Query<OidcCallbackQuery>,
+) -> Result<impl Responder, AppError> {
+    let provider = oidc_client.provider(&provider).map_err(oidc_error)?;
+    let query = query.into_inner();
+    if let Some(error) = query.error {
+        return Err(AppError::ValidationError(format!(
+            "The provider reported {}{}",
+            error,
+            query
+                .error_description
+                .map(|description| format!(": {}", description))
+                .unwrap_or_default()
+        )));
+    }
+    let (code, state) = match (query.code, query.state) {
+        (Some(code), Some(state)) => (code, state),
+        _ => {
+            return Err(AppError::ValidationError(
+                "Missing code or state".to_string(),
+            ))
+        }
+    };
+
+    if !verify_oidc_state(&req, &state) {
+        return Err(AppError::ValidationError(
+            "Invalid or expired state".to_string(),
+        ));
+    }
+
+    let mut conn = get_connection(pool);
+    let login_state = match take_login_state(&mut conn, &state, &provider.name) {
+        Ok(login_state) => login_state,
+        Err(OidcLoginError::Database(e)) => {
+            error!("{:?}", e);
+            return Err(AppError::DatabaseError("Internal Server Error".to_string()));
+        }
+        Err(_) => {
+            return Err(AppError::ValidationError(
+                "Invalid or expired state".to_string(),
+            ))
+        }
+    };
+
+    let claims = oidc_client
+        .exchange_code(
+            provider,
+            &code,
+            &login_state.code_verifier,
+            &login_state.nonce,
+        )
+        .await
+        .map_err(oidc_error)?;
+
+    match link_or_create_user(&mut conn, &provider.name, &claims) {
+        Ok(user) => {
+            let mut response = complete_login(&mut conn, &req, user)?;
+            response
+                .add_cookie(&clear_oidc_state_cookie())
+                .map_err(|_| AppError::DatabaseError("Internal Server Error".to_string()))?;
+            Ok(response)
+        }
+        Err(OidcLoginError::EmailNotVerified) => Err(AppError::ValidationError(
+            "The provider didn't share a verified email address".to_string(),
+        )),
+        Err(OidcLoginError::UnverifiedAccount) => Err(AppError::ValidationError(
+            "Verify the email address of your existing account before signing in with this provider".to_string(),
+        )),
+        Err(OidcLoginError::Database(e)) => {
+            error!("{:?}", e);
+            Err(AppError::DatabaseError("Internal Server Error".to_string()))
+        }
+        Err(OidcLoginError::InvalidState) => Err(AppError::ValidationError(
+            "Invalid or expired state".to_string(),
+        )),
+    }
+}
+
+// mail a password reset token, succeeds whether or not the email is registered
+#[utoipa::path(
+    path = "/auth/password/forgot",
+    request_body = ForgotPasswordRequest,
+    responses(
+        (status = 200, description = "A reset token is mailed if the email belongs to an account."),
+    ),
+    operation_id = "forgotPassword"
+)]
+#[post("/password/forgot")]
+async fn forgot_password_handler(
+    pool: web::Data<DbPool>,
+    mailer: web::Data<dyn Mailer>,
+    req_body: web::Json<ForgotPasswordRequest>,
+) -> impl Responder {
+    let email = req_body.into_inner().email;
+
+    // Answer before looking up the email, a registered one takes longer to handle and
+    // failing would reveal it too, so errors are only logged
+    actix_web::rt::spawn(async move {
+        let result = web::block(move || {
+            let mut conn = get_connection(pool);
+            request_password_reset(&mut conn, mailer.as_ref(), &email)
+        })
+        .await;
+        match result {
+            Ok(Ok(_)) => {}
+            Ok(Err(PasswordResetError::Mail(e))) => {
+                error!("Unable to send password reset email, {}", e)
+            }
+            Ok(Err(e)) => error!("{:?}", e),
+            Err(e) => error!("{:?}", e),
+        }
+    });
+    HttpResponse::Ok().finish()
+}
+
+// set a new password with a reset token and log out every session
+#[utoipa::path(
+    path = "/auth/password/reset",
+    request_body = ResetPasswordRequest,
+    responses(
+        (status = 200, description = "Password changed, every access and refresh token issued to the user is revoked."),
+        (status = 400, description = "Invalid, used or expired reset token, or the password doesn't meet the password policy.", body = PasswordPolicyErrorResponse),
+        (status = 500, description = "Internal Server Error")
+    ),
+    operation_id = "resetPassword"
+)]
+#[post("/password/reset")]
+async fn reset_password_handler(
+    pool: web::Data<DbPool>,
+    revocation_store: web::Data<RevocationStore>,
+    req_body: web::Json<ResetPasswordRequest>,
+) -> Result<impl Responder, AppError> {
+    let mut conn = get_connection(pool);
+    let request = req_body.into_inner();
+
+    let user_id = match reset_password(&mut conn, &request.token, request.password) {
+        Ok(user_id) => user_id,
+        Err(PasswordResetError::Expired) => {
+            return Err(AppError::ValidationError(
+                "Reset token has expired".to_string(),
+            ))
+        }
+        Err(PasswordResetError::Invalid) => {
+            return Err(AppError::ValidationError("Invalid reset token".to_string()))
+        }
+        Err(PasswordResetError::WeakPassword(violations)) => {
+            return Err(AppError::PasswordPolicyError(violations))
+        }
+        Err(PasswordResetError::Database(e)) => {
+            error!("{:?}", e);
+            return Err(AppError::DatabaseError("Internal Server Error".to_string()));
+        }
+        Err(_) => return Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    };
+
+    revocation_store
+        .revoke_all_for_user(user_id)
+        .map_err(|_| AppError::DatabaseError("Internal Server Error".to_string()))?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+// exchange a refresh token for a new access and refresh token pair
+#[utoipa::path(
+    path = "/auth/refresh",
+    request_body(
+        content = Option<RefreshRequest>,
+        description = "Refresh token issued by login or a previous refresh. In cookie mode it is read from the refresh_token cookie instead, together with the X-CSRF-Token header."
+    ),
+    responses(
+        (status = 200, description = "New token pair. The previous refresh token can no longer be used. In cookie mode the tokens are set as cookies and the body is empty.", body = TokenResponse),
+        (status = 401, description = "Invalid, expired or reused refresh token."),
+        (status = 403, description = "The CSRF token is missing or doesn't match the cookie"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    operation_id = "refreshToken"
+)]
+#[post("/refresh")]
+async fn refresh_handler(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    req_body: Option<web::Json<RefreshRequest>>,
+) -> Result<impl Responder, AppError> {
+    let presented = match req_body {
+        Some(body) => body.into_inner().refresh_token,
+        None => {
+            let cookie = refresh_token_cookie(&req)
+                .ok_or_else(|| AppError::UnauthorizedError("Invalid refresh token".to_string()))?;
+            if !verify_csrf(&req) {
+                return Err(AppError::ForbiddenError(
+                    TokenError::InvalidCsrfToken.description().to_string(),
+                ));
+            }
+            cookie
+        }
+    };
+    let mut conn = get_connection(pool);
+
+    let (refresh_token, plain_refresh_token) =
+        match rotate_refresh_token(&mut conn, &presented, None) {
+            Ok(rotated) => rotated,
+            Err(RefreshTokenError::Database(e)) => {
+                error!("{:?}", e);
+                return Err(AppError::DatabaseError("Internal Server Error".to_string()));
+            }
+            Err(_) => {
+                return Err(AppError::UnauthorizedError(
+                    "Invalid refresh token".to_string(),
+                ))
+            }
+        };
+
+    let user = match find_user_by_id(&mut conn, refresh_token.user_id) {
+        Ok(Some(user)) => user,
+        Ok(None) => {
+            return Err(AppError::UnauthorizedError(
+                "Invalid refresh token".to_string(),
+            ))
+        }
+        Err(_) => return Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    };
+
+    let permissions = find_permissions_for_role(&mut conn, user.role)
+        .map_err(|_| AppError::DatabaseError("Internal Server Error".to_string()))?;
+    // Tokens issued at login belong to the session of the same id
+    let session_id = refresh_token.family_id;
+    touch_session(&mut conn, session_id)
+        .map_err(|_| AppError::DatabaseError("Internal Server Error".to_string()))?;
+    let token = generate_token(&user.id.to_string(), user.role, permissions, session_id);
+
+    if cookie_mode() {
+        let mut response = HttpResponse::Ok();
+        set_session_cookies(&mut response, &token, &plain_refresh_token);
+        return Ok(response.finish());
+    }
+    Ok(HttpResponse::Ok()
+        .append_header(("Authorization", format!("Bearer {}", token)))
+        .json(TokenResponse {
+            access_token: token,
+            refresh_token: plain_refresh_token,
+            token_type: "Bearer".to_string(),
+            expires_in: JWT_LIFETIME,
+        }))
+}
+
+// log out of the current session, revoking its access and refresh tokens
+#[utoipa::path(
+    path = "/auth/logout",
+    request_body(
+        content = Option<LogoutRequest>,
+        description = "Refresh token to revoke along with the access token"
+    ),
+    responses(
+        (status = 200, description = "Logged out."),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "logoutUser"
+)]
+#[post("/logout", wrap = "AuthenticationCheck")]
+async fn logout_handler(
+    pool: web::Data<DbPool>,
+    revocation_store: web::Data<RevocationStore>,
+    req: HttpRequest,
+    req_body: Option<web::Json<LogoutRequest>>,
+    claims: AllowImpersonation<roles::Guest>,
+) -> Result<impl Responder, AppError> {
+    let user_id = Uuid::parse_str(&claims.sub)
+        .map_err(|_| AppError::UnauthorizedError("Invalid token".to_string()))?;
+
+    revocation_store
+        .revoke_token(&claims)
+        .map_err(|_| AppError::DatabaseError("Internal Server Error".to_string()))?;
+
+    if let Some(session_id) = claims.sid {
+        let mut conn = get_connection(pool.clone());
+        let ended = end_session(&mut conn, user_id, session_id)
+            .map_err(|_| AppError::DatabaseError("Internal Server Error".to_string()))?;
+        if ended {
+            revocation_store.revoke_session(session_id);
+        }
+    }
+
+    if let Some(refresh_token) = req_body
+        .and_then(|body| body.into_inner().refresh_token)
+        .or_else(|| refresh_token_cookie(&req))
+    {
+        let mut conn = get_connection(pool);
+        revoke_refresh_token(&mut conn, user_id, &refresh_token)
+            .map_err(|_| AppError::DatabaseError("Internal Server Error".to_string()))?;
+    }
+
+    Ok(logged_out())
+}
+
+// revoke every access and refresh token issued to the current user
+#[utoipa::path(
+    path = "/auth/logout-all",
+    responses(
+        (status = 200, description = "Logged out of every session."),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "logoutAllSessions"
+)]
+#[post("/logout-all", wrap = "AuthenticationCheck")]
+async fn logout_all_handler(
+    pool: web::Data<DbPool>,
+    revocation_store: web::Data<RevocationStore>,
+    claims: RequireRole<roles::Guest>,
+) -> Result<impl Responder, AppError> {
+    let user_id = Uuid::parse_str(&claims.sub)
+        .map_err(|_| AppError::UnauthorizedError("Invalid token".to_string()))?;
+
+    revocation_store
+        .revoke_all_for_user(user_id)
+        .map_err(|_| AppError::DatabaseError("Internal Server Error".to_string()))?;
+
+    let mut conn = get_connection(pool);
+    revoke_all_refresh_tokens(&mut conn, user_id)
+        .map_err(|_| AppError::DatabaseError("Internal Server Error".to_string()))?;
+
+    Ok(logged_out())
+}
+
+// In cookie mode the browser also forgets the session cookies
+fn logged_out() -> HttpResponse {
+    let mut response = HttpResponse::Ok();
+    if cookie_mode() {
+        clear_session_cookies(&mut response);
+    }
+    response.finish()
+}
+
+// public keys other services can use to verify tokens issued by this API
+#[utoipa::path(
+    path = "/.well-known/jwks.json",
+    responses(
+        (status = 200, description = "JSON Web Key Set. Empty when tokens are signed with a shared HMAC secret.")
+    ),
+    operation_id = "getJwks"
+)]
+#[get("/.well-known/jwks.json")]
+async fn jwks_handler() -> impl Responder {
+    HttpResponse::Ok().json(key_ring().jwks())
+}
+
+fn key_ring_error(e: KeyRingError) -> AppError {
+    match e {
+        KeyRingError::NotFound => AppError::NotFoundError("Signing key not found".to_string()),
+        KeyRingError::InvalidState(message) | KeyRingError::Key(message) => {
+            AppError::ValidationError(message)
+        }
+        KeyRingError::Database(e) => {
+            error!("{:?}", e);
+            AppError::DatabaseError("Internal Server Error".to_string())
+        }
+    }
+}
+
+// Key changes made through these routes are stored in the signing_keys table
+fn require_database_key_source() -> Result<(), AppError> {
+    if *key_source() != KeySource::Database {
+        return Err(AppError::ValidationError(
+            "Signing keys can only be managed with JWT_KEY_SOURCE=database".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn reload_keys(pool: &DbPool) -> Result<(), AppError> {
+    reload_key_ring(pool).map_err(|e| {
+        error!("Unable to reload JWT keys, {}", e);
+        AppError::DatabaseError("Unable to reload signing keys".to_string())
+    })
+}
+
+fn signing_key_response(key: SigningKey) -> HttpResponse {
+    HttpResponse::Ok().json(SigningKeyResponse::from(key))
+}
+
+// list signing keys without their key material
+#[utoipa::path(
+    path = "/admin/keys",
+    responses(
+        (status = 200, description = "Signing keys, newest first.", body = Vec<SigningKeyResponse>),
+        (status = 400, description = "Keys are not stored in the database."),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Requires the admin role"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "findAllSigningKeys"
+)]
+#[get("/keys", wrap = "AuthenticationCheck")]
+async fn find_all_signing_keys_handler(
+    pool: web::Data<DbPool>,
+    _claims: RequireRole<roles::Admin>,
+) -> Result<impl Responder, AppError> {
+    require_database_key_source()?;
+    let mut conn = get_connection(pool);
+
+    match find_all_signing_keys(&mut conn) {
+        Ok(keys) => Ok(HttpResponse::Ok().json(
+            keys.into_iter()
+                .map(SigningKeyResponse::from)
+                .collect::<Vec<_>>(),
+        )),
+        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+}
+
+// generate a new key that verifies tokens but doesn't sign them until promoted
+#[utoipa::path(
+    path = "/admin/keys",
+    request_body = CreateSigningKeyRequest,
+    responses(
+        (status = 200, description = "Generated key, published on /.well-known/jwks.json.", body = SigningKeyResponse),
+        (status = 400, description = "Unsupported algorithm or keys are not stored in the database."),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Requires the admin role"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "createSigningKey"
+)]
+#[post("/keys", wrap = "AuthenticationCheck")]
+async fn create_signing_key_handler(
+    pool: web::Data<DbPool>,
+    req_body: web::Json<CreateSigningKeyRequest>,
+    _claims: RequireRole<roles::Admin>,
+) -> Result<impl Responder, AppError> {
+    require_database_key_source()?;
+    let mut conn = get_connection(pool.clone());
+
+    let request = req_body.into_inner();
+    let key = create_signing_key(&mut conn, request.algorithm.as_deref(), request.kid)
+        .map_err(key_ring_error)?;
+    reload_keys(&pool)?;
+
+    Ok(signing_key_response(key))
+}
+
+// sign new tokens with the key, the previous signing key keeps verifying
+#[utoipa::path(
+    path = "/admin/keys/{kid}/promote",
+    responses(
+        (status = 200, description = "Key is now used to sign new tokens.", body = SigningKeyResponse),
+        (status = 400, description = "Key can't be promoted."),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Requires the admin role"),
+        (status = 404, description = "Key not found."),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "promoteSigningKey"
+)]
+#[post("/keys/{kid}/promote", wrap = "AuthenticationCheck")]
+async fn promote_signing_key_handler(
+    pool: web::Data<DbPool>,
+    kid: web::Path<String>,
+    _claims: RequireRole<roles::Admin>,
+) -> Result<impl Responder, AppError> {
+    require_database_key_source()?;
+    let mut conn = get_connection(pool.clone());
+
+    let key = promote_signing_key(&mut conn, &kid).map_err(key_ring_error)?;
+    reload_keys(&pool)?;
+
+    Ok(signing_key_response(key))
+}
+
+// stop accepting tokens signed with the key
+#[utoipa::path(
+    path = "/admin/keys/{kid}/retire",
+    responses(
+        (status = 200, description = "Tokens signed with the key are no longer accepted.", body = SigningKeyResponse),
+        (status = 400, description = "The signing key can't be retired."),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Requires the admin role"),
+        (status = 404, description = "Key not found."),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "retireSigningKey"
+)]
+#[post("/keys/{kid}/retire", wrap = "AuthenticationCheck")]
+async fn retire_signing_key_handler(
+    pool: web::Data<DbPool>,
+    kid: web::Path<String>,
+    _claims: RequireRole<roles::Admin>,
+) -> Result<impl Responder, AppError> {
+    require_database_key_source()?;
+    let mut conn = get_connection(pool.clone());
+
+    let key = retire_signing_key(&mut conn, &kid).map_err(key_ring_error)?;
+    reload_keys(&pool)?;
+
+    Ok(signing_key_response(key))
+}
+
+// reload keys from the key directory or database without waiting for the next reload
+#[utoipa::path(
+    path = "/admin/keys/reload",
+    responses(
+        (status = 200, description = "Keys reloaded."),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Requires the admin role"),
+        (status = 500, description = "Keys couldn't be loaded, the previous keys stay in use.")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "reloadSigningKeys"
+)]
+#[post("/keys/reload", wrap = "AuthenticationCheck")]
+async fn reload_signing_keys_handler(
+    pool: web::Data<DbPool>,
+    _claims: RequireRole<roles::Admin>,
+) -> Result<impl Responder, AppError> {
+    reload_keys(&pool)?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+// clear the failed login count and lockout of an account
+#[utoipa::path(
+    path = "/admin/users/{user_id}/unlock",
+    responses(
+        (status = 200, description = "Account unlocked."),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Requires the admin role"),
+        (status = 404, description = "User not found"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "unlockUser"
+)]
+#[post("/users/{user_id}/unlock", wrap = "AuthenticationCheck")]
+async fn unlock_user_handler(
+    throttle: web::Data<LoginThrottle>,
+    user_id: web::Path<Uuid>,
+    _claims: RequireRole<roles::Admin>,
+) -> Result<impl Responder, AppError> {
+    match throttle.unlock(*user_id) {
+        Ok(true) => Ok(HttpResponse::Ok().finish()),
+        Ok(false) => Err(AppError::NotFoundError("User not found".to_string())),
+        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+}
+
+// act as another user to reproduce an issue, the token is recorded in the audit trail
+#[utoipa::path(
+    path = "/admin/impersonate/{user_id}",
+    request_body = ImpersonationRequest,
+    responses(
+        (status = 200, description = "Access token for the user with the admin in its act claim. It can't change passwords or roles and there is no refresh token.", body = ImpersonationResponse),
+        (status = 400, description = "Missing reason, or an attempt to impersonate yourself or another admin"),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Requires the admin role"),
+        (status = 404, description = "User not found"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "impersonateUser"
+)]
+#[post("/impersonate/{user_id}", wrap = "AuthenticationCheck")]
+async fn impersonate_user_handler(
+    pool: web::Data<DbPool>,
+    user_id: web::Path<Uuid>,
+    req_body: web::Json<ImpersonationRequest>,
+    claims: RequireRole<roles::Admin>,
+) -> Result<impl Responder, AppError> {
+    let actor_id = Uuid::parse_str(&claims.sub)
+        .map_err(|_| AppError::UnauthorizedError("Invalid token".to_string()))?;
+    let mut conn = get_connection(pool);
+
+    match impersonate(&mut conn, actor_id, *user_id, &req_body.reason) {
+        Ok((access_token, impersonation)) => Ok(HttpResponse::Ok().json(ImpersonationResponse {
+            access_token,
+            token_type: "Bearer".to_string(),
+            expires_in: IMPERSONATION_LIFETIME,
+            impersonation,
+        })),
+        Err(ImpersonationError::UserNotFound) => {
+            Err(AppError::NotFoundError("User not found".to_string()))
+        }
+        Err(ImpersonationError::NotAllowed(message)) => {
+            Err(AppError::ValidationError(message.to_string()))
+        }
+        Err(ImpersonationError::Database(e)) => {
+            error!("{:?}", e);
+            Err(AppError::DatabaseError("Internal Server Error".to_string()))
+        }
+    }
+}
+
+// audit trail of impersonations, most recent first
+#[utoipa::path(
+    path = "/admin/impersonations",
+    responses(
+        (status = 200, description = "Recorded impersonations", body = Vec<Impersonation>),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Requires the admin role"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "findImpersonations"
+)]
+#[get("/impersonations", wrap = "AuthenticationCheck")]
+async fn find_impersonations_handler(
+    pool: web::Data<DbPool>,
+    _claims: RequireRole<roles::Admin>,
+) -> Result<impl Responder, AppError> {
+    let mut conn = get_connection(pool);
+
+    match find_impersonations(&mut conn) {
+        Ok(impersonations) => Ok(HttpResponse::Ok().json(impersonations)),
+        Err(e) => {
+            error!("{:?}", e);
+            Err(AppError::DatabaseError("Internal Server Error".to_string()))
+        }
     }
 }
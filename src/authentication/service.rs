@@ -1,17 +1,353 @@
-use crate::{common::model::AppError, database::model::users::UserRole};
+use uuid::Uuid;
+
+use crate::{
+    common::model::AppError,
+    database::model::users::{
+        AdminUserResponse, CreateUserRequest, UpdateUserRequest, User, UserListQuery, UserResponse,
+        UserRole, UserSort, UserView,
+    },
+};
 
 use super::model::Claims;
 
-fn authenticate_claims(claims: &Claims, required_role: &UserRole) -> Result<bool, AppError> {
+pub fn authenticate_claims(claims: &Claims, required_role: &UserRole) -> Result<bool, AppError> {
     if claims.role < *required_role {
-        return Err(AppError::UnauthorizedError(
-            "Missing or invalid authentication".to_string(),
-        ));
+        Err(AppError::ForbiddenError(
+            "Insufficient role for this operation".to_string(),
+        ))
+    } else {
+        Ok(true)
+    }
+}
+
+pub fn authenticate_permission(claims: &Claims, permission: &str) -> Result<bool, AppError> {
+    if claims
+        .permissions
+        .iter()
+        .any(|granted| granted == permission)
+    {
+        Ok(true)
     } else {
+        Err(AppError::ForbiddenError(format!(
+            "Missing permission {}",
+            permission
+        )))
+    }
+}
+
+// Account policy: admins may act on any account, everyone else only on their own account
+// and can't change roles.
+fn is_own_account(claims: &Claims, user_id: Uuid) -> bool {
+    Uuid::parse_str(&claims.sub) == Ok(user_id)
+}
+
+pub fn authorize_user_create(
+    claims: &Claims,
+    user_data: &CreateUserRequest,
+) -> Result<bool, AppError> {
+    if claims.role != UserRole::Admin && user_data.role > claims.role {
+        return Err(AppError::ForbiddenError(
+            "Only admins can assign a role above their own".to_string(),
+        ));
+    }
+    Ok(true)
+}
+
+pub fn authorize_user_update(
+    claims: &Claims,
+    user_id: Uuid,
+    user_data: &UpdateUserRequest,
+) -> Result<bool, AppError> {
+    // Support acting as the user may look around but not take over the account, a new
+    // email address would let them reset the password
+    if claims.act.is_some()
+        && (user_data.password.is_some() || user_data.role.is_some() || user_data.email.is_some())
+    {
+        return Err(AppError::ForbiddenError(
+            "Passwords, email addresses and roles can't be changed while impersonating".to_string(),
+        ));
+    }
+    if claims.role == UserRole::Admin {
         return Ok(true);
     }
+    if !is_own_account(claims, user_id) {
+        return Err(AppError::ForbiddenError(
+            "You can only modify your own account".to_string(),
+        ));
+    }
+    if user_data.role.is_some_and(|role| role != claims.role) {
+        return Err(AppError::ForbiddenError(
+            "Only admins can change roles".to_string(),
+        ));
+    }
+    Ok(true)
+}
+
+pub fn authorize_user_delete(claims: &Claims, user_id: Uuid) -> Result<bool, AppError> {
+    if claims.act.is_some() {
+        return Err(AppError::ForbiddenError(
+            "Accounts can't be deleted while impersonating".to_string(),
+        ));
+    }
+    if claims.role == UserRole::Admin || is_own_account(claims, user_id) {
+        Ok(true)
+    } else {
+        Err(AppError::ForbiddenError(
+            "You can only delete your own account".to_string(),
+        ))
+    }
+}
+
+// Only admins see email addresses, so only they may filter, sort or search by them.
+// Otherwise the order or the number of matches would give the addresses away.
+pub fn can_look_up_emails(claims: &Claims) -> bool {
+    claims.role == UserRole::Admin
+}
+
+pub fn authorize_email_lookup(claims: &Claims) -> Result<bool, AppError> {
+    if !can_look_up_emails(claims) {
+        return Err(AppError::ForbiddenError(
+            "Only admins can find users by email".to_string(),
+        ));
+    }
+    Ok(true)
+}
+
+pub fn authorize_user_list(claims: &Claims, params: &UserListQuery) -> Result<bool, AppError> {
+    if !can_look_up_emails(claims) && (params.email.is_some() || params.sort == UserSort::Email) {
+        return Err(AppError::ForbiddenError(
+            "Only admins can filter or sort by email".to_string(),
+        ));
+    }
+    Ok(true)
+}
+
+// Admins see the whole account including the lockout state, owners their own account and
+// everyone else only the public profile.
+pub fn user_view(claims: &Claims, user: User) -> UserView {
+    if claims.role == UserRole::Admin {
+        UserView::Admin(AdminUserResponse::from(user))
+    } else if is_own_account(claims, user.id) {
+        UserView::Owner(UserResponse::from(user))
+    } else {
+        UserView::Public(user.into())
+    }
 }
 
-pub fn authenticate_user_role(claims: &Claims) -> Result<bool, AppError> {
-    authenticate_claims(claims, &UserRole::User)
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    use super::{
+        authorize_email_lookup, authorize_user_create, authorize_user_delete, authorize_user_list,
+        authorize_user_update, user_view,
+    };
+    use crate::authentication::model::{Actor, Claims};
+    use crate::database::model::users::{
+        CreateUserRequest, UpdateUserRequest, User, UserListQuery, UserRole, UserSort, UserView,
+    };
+
+    fn user(role: UserRole) -> User {
+        let now = Utc::now().naive_utc();
+        User {
+            id: Uuid::new_v4(),
+            username: "jane".to_string(),
+            email: "jane@example.com".to_string(),
+            hashed_password: "$argon2id$secret".to_string(),
+            timezone: "UTC".to_string(),
+            role,
+            created_at: now,
+            updated_at: now,
+            email_verified_at: None,
+            failed_login_attempts: 3,
+            locked_until: None,
+        }
+    }
+
+    fn claims(user: &User) -> Claims {
+        Claims {
+            sub: user.id.to_string(),
+            iss: String::new(),
+            aud: String::new(),
+            exp: 0,
+            nbf: 0,
+            iat: 0,
+            jti: Uuid::new_v4(),
+            role: user.role,
+            permissions: vec![],
+            client_id: None,
+            scope: None,
+            sid: None,
+            act: None,
+        }
+    }
+
+    #[test]
+    fn views_depend_on_the_caller() {
+        let admin = user(UserRole::Admin);
+        let owner = user(UserRole::User);
+        let other = user(UserRole::User);
+
+        assert!(matches!(
+            user_view(&claims(&admin), owner.clone()),
+            UserView::Admin(_)
+        ));
+        assert!(matches!(
+            user_view(&claims(&owner), owner.clone()),
+            UserView::Owner(_)
+        ));
+        assert!(matches!(
+            user_view(&claims(&other), owner.clone()),
+            UserView::Public(_)
+        ));
+
+        let json = serde_json::to_string(&user_view(&claims(&admin), owner)).unwrap();
+        assert!(json.contains("failed_login_attempts"));
+        assert!(!json.contains("hashed_password") && !json.contains("argon2"));
+    }
+
+    fn update(password: Option<&str>, email: Option<&str>) -> UpdateUserRequest {
+        UpdateUserRequest {
+            username: None,
+            email: email.map(str::to_string),
+            password: password.map(str::to_string),
+            timezone: Some("Europe/Berlin".to_string()),
+            role: None,
+        }
+    }
+
+    fn create(role: UserRole) -> CreateUserRequest {
+        CreateUserRequest {
+            username: "john".to_string(),
+            email: "john@example.com".to_string(),
+            password: "n3w-Passw0rd".to_string(),
+            timezone: "UTC".to_string(),
+            role,
+        }
+    }
+
+    #[test]
+    fn only_admins_create_users_above_their_role() {
+        let admin = claims(&user(UserRole::Admin));
+        let regular = claims(&user(UserRole::User));
+        let guest = claims(&user(UserRole::Guest));
+
+        assert!(authorize_user_create(&admin, &create(UserRole::Admin)).is_ok());
+        assert!(authorize_user_create(&regular, &create(UserRole::User)).is_ok());
+        assert!(authorize_user_create(&regular, &create(UserRole::Guest)).is_ok());
+        assert!(authorize_user_create(&regular, &create(UserRole::Admin)).is_err());
+        assert!(authorize_user_create(&guest, &create(UserRole::User)).is_err());
+    }
+
+    #[test]
+    fn only_owners_and_admins_update_accounts() {
+        let admin = user(UserRole::Admin);
+        let owner = user(UserRole::User);
+        let other = user(UserRole::User);
+
+        assert!(authorize_user_update(&claims(&admin), owner.id, &update(None, None)).is_ok());
+        assert!(authorize_user_update(&claims(&owner), owner.id, &update(None, None)).is_ok());
+        assert!(authorize_user_update(
+            &claims(&owner),
+            owner.id,
+            &update(Some("n3w-Passw0rd"), Some("mine@example.com"))
+        )
+        .is_ok());
+        assert!(authorize_user_update(&claims(&other), owner.id, &update(None, None)).is_err());
+    }
+
+    #[test]
+    fn only_admins_change_roles() {
+        let admin = user(UserRole::Admin);
+        let owner = user(UserRole::User);
+        let with_role = |role| UpdateUserRequest {
+            role: Some(role),
+            ..update(None, None)
+        };
+
+        assert!(
+            authorize_user_update(&claims(&admin), owner.id, &with_role(UserRole::Admin)).is_ok()
+        );
+        assert!(
+            authorize_user_update(&claims(&admin), owner.id, &with_role(UserRole::Guest)).is_ok()
+        );
+        // Sending the current role back is not a change
+        assert!(
+            authorize_user_update(&claims(&owner), owner.id, &with_role(UserRole::User)).is_ok()
+        );
+        assert!(
+            authorize_user_update(&claims(&owner), owner.id, &with_role(UserRole::Admin)).is_err()
+        );
+        assert!(
+            authorize_user_update(&claims(&owner), owner.id, &with_role(UserRole::Guest)).is_err()
+        );
+    }
+
+    #[test]
+    fn only_owners_and_admins_delete_accounts() {
+        let admin = user(UserRole::Admin);
+        let owner = user(UserRole::User);
+        let other = user(UserRole::User);
+
+        assert!(authorize_user_delete(&claims(&admin), owner.id).is_ok());
+        assert!(authorize_user_delete(&claims(&owner), owner.id).is_ok());
+        assert!(authorize_user_delete(&claims(&other), owner.id).is_err());
+        assert!(authorize_user_delete(&claims(&other), admin.id).is_err());
+    }
+
+    #[test]
+    fn impersonation_can_not_take_over_the_account() {
+        let owner = user(UserRole::User);
+        let mut impersonated = claims(&owner);
+        impersonated.act = Some(Actor {
+            sub: Uuid::new_v4().to_string(),
+        });
+
+        assert!(authorize_user_update(&impersonated, owner.id, &update(None, None)).is_ok());
+        assert!(authorize_user_update(
+            &impersonated,
+            owner.id,
+            &update(Some("n3w-Passw0rd"), None)
+        )
+        .is_err());
+        assert!(authorize_user_update(
+            &impersonated,
+            owner.id,
+            &update(None, Some("mine@example.com"))
+        )
+        .is_err());
+        assert!(authorize_user_delete(&impersonated, owner.id).is_err());
+    }
+
+    #[test]
+    fn only_admins_filter_or_sort_by_email() {
+        let admin = claims(&user(UserRole::Admin));
+        let other = claims(&user(UserRole::User));
+        let by_email = UserListQuery {
+            email: Some("@example.com".to_string()),
+            ..Default::default()
+        };
+        let sorted_by_email = UserListQuery {
+            sort: UserSort::Email,
+            ..Default::default()
+        };
+
+        assert!(authorize_user_list(&admin, &by_email).is_ok());
+        assert!(authorize_user_list(&admin, &sorted_by_email).is_ok());
+        assert!(authorize_user_list(&other, &by_email).is_err());
+        assert!(authorize_user_list(&other, &sorted_by_email).is_err());
+        assert!(authorize_user_list(&other, &UserListQuery::default()).is_ok());
+    }
+
+    #[test]
+    fn only_admins_find_users_by_email() {
+        let mut client = claims(&user(UserRole::Guest));
+        client.client_id = Some("ci".to_string());
+
+        assert!(authorize_email_lookup(&claims(&user(UserRole::Admin))).is_ok());
+        assert!(authorize_email_lookup(&claims(&user(UserRole::User))).is_err());
+        assert!(authorize_email_lookup(&claims(&user(UserRole::Guest))).is_err());
+        assert!(authorize_email_lookup(&client).is_err());
+    }
 }
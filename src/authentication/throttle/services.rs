@@ -0,0 +1,346 @@
+use std::{
+    collections::HashMap,
+    env,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::{NaiveDateTime, Utc};
+use diesel::{prelude::*, result::QueryResult, sql_types::Text, PgConnection};
+use uuid::Uuid;
+
+use crate::{database::model::db::DbPool, schema::users};
+
+// Failed logins allowed before an account or IP address is locked out
+const ACCOUNT_FREE_ATTEMPTS: u32 = 5;
+const IP_FREE_ATTEMPTS: u32 = 20;
+// The lockout doubles with every further failure, up to MAX_LOCKOUT
+const BASE_LOCKOUT: Duration = Duration::from_secs(30);
+const MAX_LOCKOUT: Duration = Duration::from_secs(60 * 60);
+// Failures are forgotten after this long without a new failure
+const ATTEMPT_WINDOW: Duration = Duration::from_secs(60 * 60);
+// Expired in-memory entries are only pruned once there are this many
+const PRUNE_THRESHOLD: usize = 1024;
+
+sql_function!(fn lower(x: Text) -> Text);
+
+fn lockout(failures: u32, free_attempts: u32) -> Option<Duration> {
+    if failures < free_attempts {
+        return None;
+    }
+    let doublings = (failures - free_attempts).min(16);
+    Some((BASE_LOCKOUT * 2u32.pow(doublings)).min(MAX_LOCKOUT))
+}
+
+struct Attempts {
+    failures: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl Attempts {
+    fn retry_after(&self, now: Instant) -> Option<Duration> {
+        self.locked_until
+            .filter(|locked_until| *locked_until > now)
+            .map(|locked_until| locked_until - now)
+    }
+}
+
+#[derive(Default)]
+struct AttemptCounter {
+    entries: HashMap<String, Attempts>,
+}
+
+impl AttemptCounter {
+    fn retry_after(&self, key: &str) -> Option<Duration> {
+        self.entries
+            .get(key)
+            .and_then(|attempts| attempts.retry_after(Instant::now()))
+    }
+
+    fn record_failure(&mut self, key: &str, free_attempts: u32) {
+        let now = Instant::now();
+        if self.entries.len() >= PRUNE_THRESHOLD {
+            self.entries
+                .retain(|_, attempts| now - attempts.last_failure < ATTEMPT_WINDOW);
+        }
+
+        let attempts = self.entries.entry(key.to_string()).or_insert(Attempts {
+            failures: 0,
+            last_failure: now,
+            locked_until: None,
+        });
+        if now - attempts.last_failure >= ATTEMPT_WINDOW {
+            attempts.failures = 0;
+        }
+        attempts.failures += 1;
+        attempts.last_failure = now;
+        attempts.locked_until = lockout(attempts.failures, free_attempts).map(|delay| now + delay);
+    }
+
+    fn reset(&mut self, key: &str) {
+        self.entries.remove(key);
+    }
+}
+
+#[derive(Default)]
+struct ThrottleState {
+    accounts: AttemptCounter,
+    ips: AttemptCounter,
+}
+
+// Counts failed logins per account and per client IP address and locks them out with
+// an exponential backoff. Accounts are keyed by the submitted email, trimmed and lowercased,
+// so unknown emails are locked out just like real accounts. With LOGIN_LOCKOUT_STORE=database (the default)
+// lockouts of existing accounts are stored on the users table and shared by every
+// instance, otherwise everything is kept in memory.
+pub struct LoginThrottle {
+    pool: DbPool,
+    persist: bool,
+    state: Mutex<ThrottleState>,
+}
+
+fn account_key(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+impl LoginThrottle {
+    pub fn new(pool: DbPool) -> Result<Self, String> {
+        let persist = match env::var("LOGIN_LOCKOUT_STORE").as_deref() {
+            Ok("database") | Err(_) => true,
+            Ok("memory") => false,
+            Ok(other) => return Err(format!("Unknown LOGIN_LOCKOUT_STORE {}", other)),
+        };
+        Ok(LoginThrottle {
+            pool,
+            persist,
+            state: Mutex::new(ThrottleState::default()),
+        })
+    }
+
+    // How long the client has to wait before trying again, if it is locked out.
+    pub fn retry_after(&self, email: &str, ip: Option<IpAddr>) -> QueryResult<Option<Duration>> {
+        let key = account_key(email);
+        let in_memory = {
+            let state = self.state.lock().unwrap();
+            let ip_retry = ip.and_then(|ip| state.ips.retry_after(&ip.to_string()));
+            state.accounts.retry_after(&key).max(ip_retry)
+        };
+
+        let stored = if self.persist {
+            let locked_until: Option<NaiveDateTime> = users::table
+                .filter(lower(users::email).eq(&key))
+                .select(diesel::dsl::max(users::locked_until))
+                .first(&mut self.get_connection()?)?;
+            locked_until.and_then(|locked_until| (locked_until - now()).to_std().ok())
+        } else {
+            None
+        };
+
+        Ok(in_memory.max(stored))
+    }
+
+    pub fn record_failure(&self, email: &str, ip: Option<IpAddr>) -> QueryResult<()> {
+        let key = account_key(email);
+        let stored = self.persist && self.record_stored_failure(&key)?;
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(ip) = ip {
+            state.ips.record_failure(&ip.to_string(), IP_FREE_ATTEMPTS);
+        }
+        if !stored {
+            state.accounts.record_failure(&key, ACCOUNT_FREE_ATTEMPTS);
+        }
+        Ok(())
+    }
+
+    // Lockout of the IP address alone, for logins that don't name an account up front
+    // like passkeys
+    pub fn ip_retry_after(&self, ip: Option<IpAddr>) -> Option<Duration> {
+        let state = self.state.lock().unwrap();
+        ip.and_then(|ip| state.ips.retry_after(&ip.to_string()))
+    }
+
+    pub fn record_ip_failure(&self, ip: Option<IpAddr>) {
+        if let Some(ip) = ip {
+            self.state
+                .lock()
+                .unwrap()
+                .ips
+                .record_failure(&ip.to_string(), IP_FREE_ATTEMPTS);
+        }
+    }
+
+    // A successful login resets the account, but not the IP address, so one valid
+    // account can't be used to keep guessing the passwords of others.
+    pub fn record_success(&self, email: &str) -> QueryResult<()> {
+        let key = account_key(email);
+        self.state.lock().unwrap().accounts.reset(&key);
+        if self.persist {
+            diesel::update(users::table.filter(lower(users::email).eq(&key)))
+                .set((
+                    users::failed_login_attempts.eq(0),
+                    users::locked_until.eq(None::<NaiveDateTime>),
+                ))
+                .execute(&mut self.get_connection()?)?;
+        }
+        Ok(())
+    }
+
+    // Clear an account's lockout, returns false if the user doesn't exist.
+    pub fn unlock(&self, user_id: Uuid) -> QueryResult<bool> {
+        let email: Option<String> = diesel::update(users::table.find(user_id))
+            .set((
+                users::failed_login_attempts.eq(0),
+                users::locked_until.eq(None::<NaiveDateTime>),
+            ))
+            .returning(users::email)
+            .get_result(&mut self.get_connection()?)
+            .optional()?;
+
+        if let Some(email) = &email {
+            self.state
+                .lock()
+                .unwrap()
+                .accounts
+                .reset(&account_key(email));
+        }
+        Ok(email.is_some())
+    }
+
+    // Returns false if the email doesn't belong to an account.
+    fn record_stored_failure(&self, key: &str) -> QueryResult<bool> {
+        let mut conn = self.get_connection()?;
+        conn.transaction(|conn| {
+            let now = now();
+            let account = || users::table.filter(lower(users::email).eq(key));
+            // Start counting again after a quiet period, like the in-memory counter
+            let quiet_since = now - chrono::Duration::from_std(ATTEMPT_WINDOW).unwrap();
+            diesel::update(account().filter(users::last_failed_login_at.le(quiet_since)))
+                .set(users::failed_login_attempts.eq(0))
+                .execute(conn)?;
+            let failures: Option<i32> = diesel::update(account())
+                .set((
+                    users::failed_login_attempts.eq(users::failed_login_attempts + 1),
+                    users::last_failed_login_at.eq(now),
+                ))
+                .returning(users::failed_login_attempts)
+                .get_results(conn)?
+                .into_iter()
+                .max();
+            let failures = match failures {
+                Some(failures) => failures,
+                None => return Ok(false),
+            };
+
+            if let Some(delay) = lockout(failures as u32, ACCOUNT_FREE_ATTEMPTS) {
+                let locked_until = now + chrono::Duration::from_std(delay).unwrap();
+                diesel::update(account())
+                    .set(users::locked_until.eq(locked_until))
+                    .execute(conn)?;
+            }
+            Ok(true)
+        })
+    }
+
+    fn get_connection(
+        &self,
+    ) -> QueryResult<r2d2::PooledConnection<diesel::r2d2::ConnectionManager<PgConnection>>> {
+        self.pool
+            .get()
+            .map_err(|e| diesel::result::Error::QueryBuilderError(e.into()))
+    }
+}
+
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{
+            model::users::UserRole,
+            tools::testing::{create_test_user, test_pool},
+        },
+        users::service::delete_user,
+    };
+
+    #[test]
+    fn lockout_doubles_up_to_the_maximum() {
+        assert_eq!(lockout(4, 5), None);
+        assert_eq!(lockout(5, 5), Some(BASE_LOCKOUT));
+        assert_eq!(lockout(6, 5), Some(BASE_LOCKOUT * 2));
+        assert_eq!(lockout(8, 5), Some(BASE_LOCKOUT * 8));
+        assert_eq!(lockout(12, 5), Some(MAX_LOCKOUT));
+        assert_eq!(lockout(u32::MAX, 5), Some(MAX_LOCKOUT));
+    }
+
+    #[test]
+    fn counter_locks_out_after_the_free_attempts() {
+        let mut counter = AttemptCounter::default();
+        for _ in 0..ACCOUNT_FREE_ATTEMPTS - 1 {
+            counter.record_failure("jane@example.com", ACCOUNT_FREE_ATTEMPTS);
+        }
+        let before = counter.retry_after("jane@example.com");
+        counter.record_failure("jane@example.com", ACCOUNT_FREE_ATTEMPTS);
+        let locked = counter.retry_after("jane@example.com");
+        counter.record_failure("jane@example.com", ACCOUNT_FREE_ATTEMPTS);
+        let doubled = counter.retry_after("jane@example.com");
+        counter.reset("jane@example.com");
+
+        assert_eq!(before, None);
+        assert!(locked.is_some_and(|delay| delay <= BASE_LOCKOUT && delay > BASE_LOCKOUT / 2));
+        assert!(doubled.is_some_and(|delay| delay > BASE_LOCKOUT));
+        assert_eq!(counter.retry_after("jane@example.com"), None);
+        assert_eq!(counter.retry_after("john@example.com"), None);
+    }
+
+    #[test]
+    fn accounts_are_keyed_by_the_normalized_email() {
+        assert_eq!(account_key(" Jane@Example.COM "), "jane@example.com");
+    }
+
+    fn stored_failures(pool: &DbPool, user_id: Uuid) -> i32 {
+        users::table
+            .find(user_id)
+            .select(users::failed_login_attempts)
+            .first(&mut pool.get().unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    fn stored_failures_count_every_spelling_and_decay() {
+        let pool = test_pool();
+        let user = create_test_user(&pool, UserRole::User);
+        let throttle = LoginThrottle::new(pool.clone()).unwrap();
+        let shouted = format!(" {} ", user.email.to_uppercase());
+
+        for _ in 0..ACCOUNT_FREE_ATTEMPTS - 1 {
+            throttle.record_failure(&shouted, None).unwrap();
+        }
+        throttle.record_failure(&user.email, None).unwrap();
+        let locked = throttle.retry_after(&shouted, None).unwrap();
+        let counted = stored_failures(&pool, user.id);
+
+        diesel::update(users::table.find(user.id))
+            .set((
+                users::last_failed_login_at.eq(now() - chrono::Duration::hours(2)),
+                users::locked_until.eq(None::<NaiveDateTime>),
+            ))
+            .execute(&mut pool.get().unwrap())
+            .unwrap();
+        throttle.record_failure(&user.email, None).unwrap();
+        let after_quiet_period = stored_failures(&pool, user.id);
+        let unlocked = throttle.retry_after(&user.email, None).unwrap();
+        delete_user(&mut pool.get().unwrap(), user.id).unwrap();
+
+        assert!(locked.is_some());
+        assert_eq!(counted, ACCOUNT_FREE_ATTEMPTS as i32);
+        assert_eq!(after_quiet_period, 1);
+        assert_eq!(unlocked, None);
+    }
+}
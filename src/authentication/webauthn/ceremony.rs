@@ -0,0 +1,424 @@
+use base64::{
+    alphabet,
+    engine::{
+        general_purpose::URL_SAFE_NO_PAD, DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig,
+    },
+    Engine,
+};
+use ciborium::Value;
+use openssl::{
+    bn::BigNum,
+    ec::{EcGroup, EcKey},
+    hash::MessageDigest,
+    memcmp,
+    nid::Nid,
+    pkey::{PKey, Public},
+    rsa::Rsa,
+    sha::sha256,
+    sign::Verifier,
+};
+use serde::Deserialize;
+
+// COSE algorithm identifiers offered to authenticators, in order of preference
+pub const COSE_ES256: i32 = -7;
+pub const COSE_RS256: i32 = -257;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+// Browsers encode without padding, but some client libraries keep it
+const BASE64URL: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+pub fn encode_base64url(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub fn decode_base64url(encoded: &str) -> Result<Vec<u8>, String> {
+    BASE64URL
+        .decode(encoded)
+        .map_err(|_| "Invalid base64url value".to_string())
+}
+
+#[derive(Deserialize)]
+struct CollectedClientData {
+    #[serde(rename = "type")]
+    ceremony_type: String,
+    challenge: String,
+    origin: String,
+}
+
+// Key material of a newly registered credential
+pub struct AttestedCredential {
+    pub credential_id: Vec<u8>,
+    pub public_key: Vec<u8>,
+    pub algorithm: i32,
+    pub sign_count: u32,
+}
+
+struct AuthenticatorData {
+    flags: u8,
+    sign_count: u32,
+    attested_credential: Option<(Vec<u8>, Value)>,
+}
+
+// Check clientDataJSON was produced for this ceremony and origin, and return its challenge.
+// `ceremony_type` is "webauthn.create" or "webauthn.get".
+pub fn verify_client_data(
+    client_data_json: &[u8],
+    ceremony_type: &str,
+    origin: &str,
+) -> Result<String, String> {
+    let client_data: CollectedClientData = serde_json::from_slice(client_data_json)
+        .map_err(|_| "Invalid clientDataJSON".to_string())?;
+    if client_data.ceremony_type != ceremony_type {
+        return Err(format!("Expected a {} response", ceremony_type));
+    }
+    if client_data.origin != origin {
+        return Err(format!("Unexpected origin {}", client_data.origin));
+    }
+    Ok(client_data.challenge)
+}
+
+fn parse_authenticator_data(data: &[u8], rp_id: &str) -> Result<AuthenticatorData, String> {
+    if data.len() < 37 {
+        return Err("Authenticator data is too short".to_string());
+    }
+    if !memcmp::eq(&data[..32], &sha256(rp_id.as_bytes())) {
+        return Err("Authenticator data is for another relying party".to_string());
+    }
+    let flags = data[32];
+    if flags & FLAG_USER_PRESENT == 0 {
+        return Err("User presence was not confirmed".to_string());
+    }
+    let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+
+    let attested_credential = if flags & FLAG_ATTESTED_CREDENTIAL_DATA != 0 {
+        // aaguid (16 bytes), credential id length (2 bytes), credential id, COSE key
+        let rest = &data[37..];
+        if rest.len() < 18 {
+            return Err("Attested credential data is too short".to_string());
+        }
+        let id_length = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+        let credential_id = rest
+            .get(18..18 + id_length)
+            .ok_or("Credential id is truncated")?
+            .to_vec();
+        let mut cose_key = &rest[18 + id_length..];
+        let public_key: Value = ciborium::de::from_reader(&mut cose_key)
+            .map_err(|_| "Invalid credential public key".to_string())?;
+        Some((credential_id, public_key))
+    } else {
+        None
+    };
+
+    Ok(AuthenticatorData {
+        flags,
+        sign_count,
+        attested_credential,
+    })
+}
+
+fn map_entry(map: &[(Value, Value)], key: i64) -> Option<&Value> {
+    map.iter()
+        .find(|(k, _)| k.as_integer().map(i128::from) == Some(key as i128))
+        .map(|(_, v)| v)
+}
+
+fn map_bytes(map: &[(Value, Value)], key: i64) -> Result<&[u8], String> {
+    map_entry(map, key)
+        .and_then(|v| v.as_bytes())
+        .map(|v| v.as_slice())
+        .ok_or_else(|| format!("COSE key parameter {} is missing", key))
+}
+
+// Convert a COSE_Key (RFC 9053) into a DER encoded SubjectPublicKeyInfo
+fn cose_key_to_der(cose_key: &Value) -> Result<(Vec<u8>, i32), String> {
+    let map = cose_key.as_map().ok_or("COSE key is not a map")?;
+    let algorithm = map_entry(map, 3)
+        .and_then(|v| v.as_integer())
+        .and_then(|v| i32::try_from(v).ok())
+        .ok_or("COSE key has no algorithm")?;
+
+    let key: PKey<Public> = match algorithm {
+        COSE_ES256 => {
+            let curve = map_entry(map, -1).and_then(|v| v.as_integer());
+            if curve.map(i128::from) != Some(1) {
+                return Err("ES256 keys must use the P-256 curve".to_string());
+            }
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+            let x = BigNum::from_slice(map_bytes(map, -2)?).map_err(|e| e.to_string())?;
+            let y = BigNum::from_slice(map_bytes(map, -3)?).map_err(|e| e.to_string())?;
+            let ec_key = EcKey::from_public_key_affine_coordinates(&group, &x, &y)
+                .map_err(|_| "Invalid P-256 public key".to_string())?;
+            PKey::from_ec_key(ec_key).map_err(|e| e.to_string())?
+        }
+        COSE_RS256 => {
+            let n = BigNum::from_slice(map_bytes(map, -1)?).map_err(|e| e.to_string())?;
+            let e = BigNum::from_slice(map_bytes(map, -2)?).map_err(|e| e.to_string())?;
+            let rsa = Rsa::from_public_components(n, e).map_err(|e| e.to_string())?;
+            PKey::from_rsa(rsa).map_err(|e| e.to_string())?
+        }
+        _ => return Err(format!("Unsupported COSE algorithm {}", algorithm)),
+    };
+
+    let der = key.public_key_to_der().map_err(|e| e.to_string())?;
+    Ok((der, algorithm))
+}
+
+// Extract the new credential from an attestation object. Registration requests the "none"
+// attestation conveyance, so the attestation statement itself isn't verified.
+pub fn verify_attestation(
+    attestation_object: &[u8],
+    rp_id: &str,
+) -> Result<AttestedCredential, String> {
+    let attestation: Value = ciborium::de::from_reader(attestation_object)
+        .map_err(|_| "Invalid attestation object".to_string())?;
+    let auth_data = attestation
+        .as_map()
+        .and_then(|map| {
+            map.iter()
+                .find(|(k, _)| k.as_text() == Some("authData"))
+                .and_then(|(_, v)| v.as_bytes())
+        })
+        .ok_or("Attestation object has no authenticator data")?;
+
+    let authenticator_data = parse_authenticator_data(auth_data, rp_id)?;
+    let (credential_id, cose_key) = authenticator_data
+        .attested_credential
+        .ok_or("Attestation has no credential data")?;
+    let (public_key, algorithm) = cose_key_to_der(&cose_key)?;
+
+    Ok(AttestedCredential {
+        credential_id,
+        public_key,
+        algorithm,
+        sign_count: authenticator_data.sign_count,
+    })
+}
+
+// Check an assertion signature with the stored public key and return the new signature
+// counter. The authenticator must have verified the user with a PIN or biometrics, a
+// passkey login is only a second factor in itself when it did.
+pub fn verify_assertion(
+    authenticator_data: &[u8],
+    client_data_json: &[u8],
+    signature: &[u8],
+    public_key: &[u8],
+    rp_id: &str,
+) -> Result<u32, String> {
+    let parsed = parse_authenticator_data(authenticator_data, rp_id)?;
+    if parsed.flags & FLAG_ATTESTED_CREDENTIAL_DATA != 0 {
+        return Err("Unexpected credential data in an assertion".to_string());
+    }
+    if parsed.flags & FLAG_USER_VERIFIED == 0 {
+        return Err("User verification was not performed".to_string());
+    }
+
+    let key = PKey::public_key_from_der(public_key).map_err(|e| e.to_string())?;
+    let mut verifier = Verifier::new(MessageDigest::sha256(), &key).map_err(|e| e.to_string())?;
+    verifier.update(authenticator_data).unwrap();
+    verifier.update(&sha256(client_data_json)).unwrap();
+    if verifier.verify(signature).unwrap_or(false) {
+        Ok(parsed.sign_count)
+    } else {
+        Err("Invalid signature".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ciborium::Value;
+    use openssl::{
+        bn::{BigNum, BigNumContext},
+        ec::{EcGroup, EcKey},
+        hash::MessageDigest,
+        nid::Nid,
+        pkey::{PKey, Private},
+        sha::sha256,
+        sign::Signer,
+    };
+
+    use super::{
+        encode_base64url, verify_assertion, verify_attestation, verify_client_data, COSE_ES256,
+    };
+
+    const RP_ID: &str = "localhost";
+    const ORIGIN: &str = "http://localhost:3030";
+
+    // Software authenticator holding a single ES256 credential
+    struct SoftAuthenticator {
+        credential_id: Vec<u8>,
+        key: PKey<Private>,
+    }
+
+    impl SoftAuthenticator {
+        fn new() -> Self {
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+            SoftAuthenticator {
+                credential_id: vec![7; 16],
+                key: PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap(),
+            }
+        }
+
+        fn cose_key(&self) -> Vec<u8> {
+            let ec_key = self.key.ec_key().unwrap();
+            let mut ctx = BigNumContext::new().unwrap();
+            let mut x = BigNum::new().unwrap();
+            let mut y = BigNum::new().unwrap();
+            ec_key
+                .public_key()
+                .affine_coordinates(ec_key.group(), &mut x, &mut y, &mut ctx)
+                .unwrap();
+            let key = Value::Map(vec![
+                (Value::from(1), Value::from(2)),
+                (Value::from(3), Value::from(COSE_ES256)),
+                (Value::from(-1), Value::from(1)),
+                (Value::from(-2), Value::Bytes(x.to_vec_padded(32).unwrap())),
+                (Value::from(-3), Value::Bytes(y.to_vec_padded(32).unwrap())),
+            ]);
+            let mut encoded = Vec::new();
+            ciborium::ser::into_writer(&key, &mut encoded).unwrap();
+            encoded
+        }
+
+        fn client_data(ceremony_type: &str, challenge: &str) -> Vec<u8> {
+            format!(
+                r#"{{"type":"{}","challenge":"{}","origin":"{}"}}"#,
+                ceremony_type, challenge, ORIGIN
+            )
+            .into_bytes()
+        }
+
+        fn attestation_object(&self, rp_id: &str) -> Vec<u8> {
+            let mut auth_data = sha256(rp_id.as_bytes()).to_vec();
+            auth_data.push(0x41);
+            auth_data.extend_from_slice(&0u32.to_be_bytes());
+            auth_data.extend_from_slice(&[0; 16]);
+            auth_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+            auth_data.extend_from_slice(&self.credential_id);
+            auth_data.extend_from_slice(&self.cose_key());
+
+            let attestation = Value::Map(vec![
+                (Value::from("fmt"), Value::from("none")),
+                (Value::from("attStmt"), Value::Map(vec![])),
+                (Value::from("authData"), Value::Bytes(auth_data)),
+            ]);
+            let mut encoded = Vec::new();
+            ciborium::ser::into_writer(&attestation, &mut encoded).unwrap();
+            encoded
+        }
+
+        // Returns the authenticator data and signature
+        fn assert(&self, client_data_json: &[u8], sign_count: u32) -> (Vec<u8>, Vec<u8>) {
+            self.assert_with_flags(client_data_json, sign_count, 0x05)
+        }
+
+        fn assert_with_flags(
+            &self,
+            client_data_json: &[u8],
+            sign_count: u32,
+            flags: u8,
+        ) -> (Vec<u8>, Vec<u8>) {
+            let mut auth_data = sha256(RP_ID.as_bytes()).to_vec();
+            auth_data.push(flags);
+            auth_data.extend_from_slice(&sign_count.to_be_bytes());
+
+            let mut signer = Signer::new(MessageDigest::sha256(), &self.key).unwrap();
+            signer.update(&auth_data).unwrap();
+            signer.update(&sha256(client_data_json)).unwrap();
+            (auth_data, signer.sign_to_vec().unwrap())
+        }
+    }
+
+    #[test]
+    fn registers_and_verifies_an_assertion() {
+        let authenticator = SoftAuthenticator::new();
+        let credential =
+            verify_attestation(&authenticator.attestation_object(RP_ID), RP_ID).unwrap();
+        assert_eq!(credential.credential_id, authenticator.credential_id);
+        assert_eq!(credential.algorithm, COSE_ES256);
+
+        let client_data = SoftAuthenticator::client_data("webauthn.get", "abc");
+        let (auth_data, signature) = authenticator.assert(&client_data, 3);
+        assert_eq!(
+            verify_assertion(
+                &auth_data,
+                &client_data,
+                &signature,
+                &credential.public_key,
+                RP_ID
+            ),
+            Ok(3)
+        );
+    }
+
+    #[test]
+    fn rejects_other_relying_parties_and_bad_signatures() {
+        let authenticator = SoftAuthenticator::new();
+        assert!(
+            verify_attestation(&authenticator.attestation_object("evil.example"), RP_ID).is_err()
+        );
+
+        let credential =
+            verify_attestation(&authenticator.attestation_object(RP_ID), RP_ID).unwrap();
+        let client_data = SoftAuthenticator::client_data("webauthn.get", "abc");
+        let (auth_data, mut signature) = authenticator.assert(&client_data, 1);
+        let other_client_data = SoftAuthenticator::client_data("webauthn.get", "abd");
+        assert!(verify_assertion(
+            &auth_data,
+            &other_client_data,
+            &signature,
+            &credential.public_key,
+            RP_ID
+        )
+        .is_err());
+
+        let last = signature.len() - 1;
+        signature[last] ^= 1;
+        assert!(verify_assertion(
+            &auth_data,
+            &client_data,
+            &signature,
+            &credential.public_key,
+            RP_ID
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn requires_user_verification() {
+        let authenticator = SoftAuthenticator::new();
+        let credential =
+            verify_attestation(&authenticator.attestation_object(RP_ID), RP_ID).unwrap();
+        let client_data = SoftAuthenticator::client_data("webauthn.get", "abc");
+
+        // User present, but not verified
+        let (auth_data, signature) = authenticator.assert_with_flags(&client_data, 1, 0x01);
+        assert!(verify_assertion(
+            &auth_data,
+            &client_data,
+            &signature,
+            &credential.public_key,
+            RP_ID
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn checks_client_data_type_and_origin() {
+        let challenge = encode_base64url(b"challenge");
+        let client_data = SoftAuthenticator::client_data("webauthn.create", &challenge);
+        assert_eq!(
+            verify_client_data(&client_data, "webauthn.create", ORIGIN),
+            Ok(challenge)
+        );
+        assert!(verify_client_data(&client_data, "webauthn.get", ORIGIN).is_err());
+        assert!(
+            verify_client_data(&client_data, "webauthn.create", "https://evil.example").is_err()
+        );
+    }
+}
@@ -0,0 +1,2 @@
+mod ceremony;
+pub mod services;
@@ -0,0 +1,333 @@
+use std::env;
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{
+    prelude::*,
+    result::{DatabaseErrorKind, QueryResult},
+    PgConnection,
+};
+use lazy_static::lazy_static;
+use openssl::rand::rand_bytes;
+use uuid::Uuid;
+
+use super::ceremony::{
+    decode_base64url, encode_base64url, verify_assertion, verify_attestation, verify_client_data,
+    COSE_ES256, COSE_RS256,
+};
+use crate::{
+    common::crypto::hash_token,
+    database::model::{
+        users::User,
+        webauthn::{
+            AuthenticationCredential, AuthenticatorSelection, CreateWebauthnChallengeDb,
+            CreateWebauthnCredentialDb, CredentialCreationOptions, CredentialDescriptor,
+            CredentialParameters, CredentialRequestOptions, RelyingPartyEntity, WebauthnChallenge,
+            WebauthnCredential, WebauthnRegisterFinishRequest, WebauthnUserEntity,
+        },
+    },
+    schema::{users, webauthn_challenges, webauthn_credentials},
+    WEBAUTHN_CHALLENGE_LIFETIME,
+};
+
+lazy_static! {
+    // Domain the credentials are scoped to, the origin must be on it or one of its subdomains
+    static ref WEBAUTHN_RP_ID: String =
+        env::var("WEBAUTHN_RP_ID").unwrap_or_else(|_| "localhost".to_string());
+    static ref WEBAUTHN_RP_NAME: String =
+        env::var("WEBAUTHN_RP_NAME").unwrap_or_else(|_| "rust_jwt_api".to_string());
+    // Origin of the web app running the ceremonies, as reported by the browser
+    static ref WEBAUTHN_ORIGIN: String =
+        env::var("WEBAUTHN_ORIGIN").unwrap_or_else(|_| "http://localhost:3030".to_string());
+}
+
+const CHALLENGE_BYTES: usize = 32;
+const REGISTRATION: &str = "registration";
+const AUTHENTICATION: &str = "authentication";
+const PUBLIC_KEY: &str = "public-key";
+
+#[derive(Debug)]
+pub enum WebauthnError {
+    InvalidChallenge,
+    InvalidResponse(String),
+    UnknownCredential,
+    AlreadyRegistered,
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for WebauthnError {
+    fn from(e: diesel::result::Error) -> Self {
+        match e {
+            diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                WebauthnError::AlreadyRegistered
+            }
+            e => WebauthnError::Database(e),
+        }
+    }
+}
+
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+fn timeout_ms() -> u64 {
+    WEBAUTHN_CHALLENGE_LIFETIME as u64 * 1000
+}
+
+fn descriptor(credential_id: String) -> CredentialDescriptor {
+    CredentialDescriptor {
+        credential_type: PUBLIC_KEY.to_string(),
+        id: credential_id,
+    }
+}
+
+// Store a new random challenge and return it base64url encoded. Expired challenges are
+// cleaned up at the same time.
+fn issue_challenge(
+    conn: &mut PgConnection,
+    owner_id: Option<Uuid>,
+    ceremony: &str,
+) -> QueryResult<String> {
+    diesel::delete(webauthn_challenges::table.filter(webauthn_challenges::expires_at.lt(now())))
+        .execute(conn)?;
+
+    let mut buf = [0u8; CHALLENGE_BYTES];
+    rand_bytes(&mut buf).expect("Error: Unable to generate random bytes");
+    let challenge = encode_base64url(&buf);
+
+    diesel::insert_into(webauthn_challenges::table)
+        .values(CreateWebauthnChallengeDb {
+            user_id: owner_id,
+            challenge_hash: hash_token(&challenge),
+            ceremony: ceremony.to_string(),
+            expires_at: now() + Duration::seconds(WEBAUTHN_CHALLENGE_LIFETIME as i64),
+        })
+        .execute(conn)?;
+
+    Ok(challenge)
+}
+
+// Challenges are single use, consuming one deletes it whether or not the response verifies
+fn take_challenge(
+    conn: &mut PgConnection,
+    challenge: &str,
+    ceremony: &str,
+) -> Result<WebauthnChallenge, WebauthnError> {
+    let stored = diesel::delete(
+        webauthn_challenges::table
+            .filter(webauthn_challenges::challenge_hash.eq(hash_token(challenge)))
+            .filter(webauthn_challenges::ceremony.eq(ceremony)),
+    )
+    .returning(WebauthnChallenge::as_returning())
+    .get_result(conn)
+    .optional()?
+    .ok_or(WebauthnError::InvalidChallenge)?;
+
+    if stored.expires_at < now() {
+        return Err(WebauthnError::InvalidChallenge);
+    }
+    Ok(stored)
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, WebauthnError> {
+    decode_base64url(value).map_err(WebauthnError::InvalidResponse)
+}
+
+pub fn find_credentials_for_user(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+) -> QueryResult<Vec<WebauthnCredential>> {
+    webauthn_credentials::table
+        .filter(webauthn_credentials::user_id.eq(owner_id))
+        .order(webauthn_credentials::created_at.asc())
+        .select(WebauthnCredential::as_select())
+        .load(conn)
+}
+
+pub fn delete_credential(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    credential: Uuid,
+) -> QueryResult<usize> {
+    diesel::delete(
+        webauthn_credentials::table
+            .filter(webauthn_credentials::id.eq(credential))
+            .filter(webauthn_credentials::user_id.eq(owner_id)),
+    )
+    .execute(conn)
+}
+
+// Options for registering a new passkey for the user. Credentials the user already
+// registered are excluded, so an authenticator isn't registered twice.
+pub fn start_registration(
+    conn: &mut PgConnection,
+    user: &User,
+) -> Result<CredentialCreationOptions, WebauthnError> {
+    let challenge = issue_challenge(conn, Some(user.id), REGISTRATION)?;
+    let exclude_credentials = find_credentials_for_user(conn, user.id)?
+        .into_iter()
+        .map(|credential| descriptor(credential.credential_id))
+        .collect();
+
+    Ok(CredentialCreationOptions {
+        challenge,
+        rp: RelyingPartyEntity {
+            id: WEBAUTHN_RP_ID.clone(),
+            name: WEBAUTHN_RP_NAME.clone(),
+        },
+        user: WebauthnUserEntity {
+            id: encode_base64url(user.id.as_bytes()),
+            name: user.email.clone(),
+            display_name: user.username.clone(),
+        },
+        pub_key_cred_params: [COSE_ES256, COSE_RS256]
+            .into_iter()
+            .map(|alg| CredentialParameters {
+                credential_type: PUBLIC_KEY.to_string(),
+                alg,
+            })
+            .collect(),
+        timeout: timeout_ms(),
+        exclude_credentials,
+        authenticator_selection: AuthenticatorSelection {
+            resident_key: "preferred".to_string(),
+            // Logins require user verification, so the authenticator must support it
+            user_verification: "required".to_string(),
+        },
+        attestation: "none".to_string(),
+    })
+}
+
+pub fn finish_registration(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    request: WebauthnRegisterFinishRequest,
+) -> Result<WebauthnCredential, WebauthnError> {
+    let raw_id = decode(&request.credential.raw_id)?;
+    let response = request.credential.response;
+    let client_data_json = decode(&response.client_data_json)?;
+    let attestation_object = decode(&response.attestation_object)?;
+
+    let challenge = verify_client_data(&client_data_json, "webauthn.create", &WEBAUTHN_ORIGIN)
+        .map_err(WebauthnError::InvalidResponse)?;
+    let stored = take_challenge(conn, &challenge, REGISTRATION)?;
+    if stored.user_id != Some(owner_id) {
+        return Err(WebauthnError::InvalidChallenge);
+    }
+
+    let attested = verify_attestation(&attestation_object, &WEBAUTHN_RP_ID)
+        .map_err(WebauthnError::InvalidResponse)?;
+    if attested.credential_id != raw_id {
+        return Err(WebauthnError::InvalidResponse(
+            "Credential id doesn't match the attestation".to_string(),
+        ));
+    }
+    let credential = diesel::insert_into(webauthn_credentials::table)
+        .values(CreateWebauthnCredentialDb {
+            user_id: owner_id,
+            credential_id: encode_base64url(&attested.credential_id),
+            public_key: attested.public_key,
+            algorithm: attested.algorithm,
+            sign_count: attested.sign_count as i64,
+            name: request.name,
+        })
+        .returning(WebauthnCredential::as_returning())
+        .get_result(conn)?;
+
+    Ok(credential)
+}
+
+// Options for signing in with a passkey. Without an email any discoverable credential
+// for this relying party can answer. Unknown emails get an empty list rather than an
+// error, just like registered emails without a passkey, so the route only reveals
+// whether an email has a passkey, not whether it is registered.
+pub fn start_authentication(
+    conn: &mut PgConnection,
+    email: Option<&str>,
+) -> Result<CredentialRequestOptions, WebauthnError> {
+    let owner_id = match email {
+        Some(email) => users::table
+            .filter(users::email.eq(email))
+            .select(users::id)
+            .first::<Uuid>(conn)
+            .optional()?,
+        None => None,
+    };
+    let allow_credentials = match owner_id {
+        Some(owner_id) => find_credentials_for_user(conn, owner_id)?
+            .into_iter()
+            .map(|credential| descriptor(credential.credential_id))
+            .collect(),
+        None => vec![],
+    };
+    let challenge = issue_challenge(conn, owner_id, AUTHENTICATION)?;
+
+    Ok(CredentialRequestOptions {
+        challenge,
+        rp_id: WEBAUTHN_RP_ID.clone(),
+        timeout: timeout_ms(),
+        allow_credentials,
+        user_verification: "required".to_string(),
+    })
+}
+
+// Verify an assertion and return the id of the user it signs in. A signature counter that
+// didn't increase points at a cloned authenticator and fails the ceremony.
+pub fn finish_authentication(
+    conn: &mut PgConnection,
+    credential: AuthenticationCredential,
+) -> Result<Uuid, WebauthnError> {
+    let response = credential.response;
+    let client_data_json = decode(&response.client_data_json)?;
+    let authenticator_data = decode(&response.authenticator_data)?;
+    let signature = decode(&response.signature)?;
+    let credential_id = encode_base64url(&decode(&credential.raw_id)?);
+
+    let challenge = verify_client_data(&client_data_json, "webauthn.get", &WEBAUTHN_ORIGIN)
+        .map_err(WebauthnError::InvalidResponse)?;
+    let stored_challenge = take_challenge(conn, &challenge, AUTHENTICATION)?;
+
+    conn.transaction(|conn| {
+        let stored = webauthn_credentials::table
+            .filter(webauthn_credentials::credential_id.eq(&credential_id))
+            .select(WebauthnCredential::as_select())
+            .for_update()
+            .first(conn)
+            .optional()?
+            .ok_or(WebauthnError::UnknownCredential)?;
+        if stored_challenge
+            .user_id
+            .is_some_and(|owner_id| owner_id != stored.user_id)
+        {
+            return Err(WebauthnError::UnknownCredential);
+        }
+        if let Some(user_handle) = response.user_handle.filter(|handle| !handle.is_empty()) {
+            if decode(&user_handle)? != stored.user_id.as_bytes() {
+                return Err(WebauthnError::UnknownCredential);
+            }
+        }
+
+        let sign_count = verify_assertion(
+            &authenticator_data,
+            &client_data_json,
+            &signature,
+            &stored.public_key,
+            &WEBAUTHN_RP_ID,
+        )
+        .map_err(WebauthnError::InvalidResponse)? as i64;
+        // Authenticators that don't implement a counter always report 0
+        if (sign_count != 0 || stored.sign_count != 0) && sign_count <= stored.sign_count {
+            return Err(WebauthnError::InvalidResponse(
+                "Signature counter did not increase".to_string(),
+            ));
+        }
+
+        diesel::update(webauthn_credentials::table.find(stored.id))
+            .set((
+                webauthn_credentials::sign_count.eq(sign_count),
+                webauthn_credentials::last_used_at.eq(now()),
+            ))
+            .execute(conn)?;
+
+        Ok(stored.user_id)
+    })
+}
@@ -0,0 +1,18 @@
+use openssl::{rand::rand_bytes, sha::sha256};
+
+const OPAQUE_TOKEN_BYTES: usize = 32;
+
+// Random, URL safe token handed to clients. Only its hash is ever stored.
+pub fn generate_opaque_token() -> String {
+    let mut buf = [0u8; OPAQUE_TOKEN_BYTES];
+    rand_bytes(&mut buf).expect("Error: Unable to generate random bytes");
+    to_hex(&buf)
+}
+
+pub fn hash_token(token: &str) -> String {
+    to_hex(&sha256(token.as_bytes()))
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,68 @@
+use std::{env, fs, path::PathBuf, sync::Arc};
+
+use chrono::Utc;
+use log::info;
+
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// Sends account emails such as verification links. Register a real implementation
+// (SMTP, a mail API, ...) in main.rs for production.
+pub trait Mailer: Send + Sync {
+    fn send(&self, email: &Email) -> Result<(), String>;
+}
+
+// Writes emails to the application log.
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send(&self, email: &Email) -> Result<(), String> {
+        info!(
+            "Email to {}\nSubject: {}\n\n{}",
+            email.to, email.subject, email.body
+        );
+        Ok(())
+    }
+}
+
+// Writes each email to its own .eml file in a directory.
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(dir: PathBuf) -> Result<Self, String> {
+        fs::create_dir_all(&dir).map_err(|e| format!("Unable to create {:?}: {}", dir, e))?;
+        Ok(FileMailer { dir })
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, email: &Email) -> Result<(), String> {
+        let file_name = format!(
+            "{}-{}.eml",
+            Utc::now().format("%Y%m%d%H%M%S%f"),
+            email.to.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+        );
+        let contents = format!(
+            "To: {}\nSubject: {}\n\n{}\n",
+            email.to, email.subject, email.body
+        );
+        fs::write(self.dir.join(file_name), contents).map_err(|e| e.to_string())
+    }
+}
+
+// MAILER selects the implementation, 'log' (default) or 'file' which writes to MAILER_DIR.
+pub fn mailer_from_env() -> Result<Arc<dyn Mailer>, String> {
+    match env::var("MAILER").as_deref() {
+        Ok("log") | Err(_) => Ok(Arc::new(LogMailer)),
+        Ok("file") => {
+            let dir = env::var("MAILER_DIR").unwrap_or_else(|_| "mail".to_string());
+            Ok(Arc::new(FileMailer::new(PathBuf::from(dir))?))
+        }
+        Ok(other) => Err(format!("Unknown MAILER {}", other)),
+    }
+}
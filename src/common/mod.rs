@@ -1,3 +1,7 @@
+pub mod crypto;
+pub mod mailer;
 pub mod model;
 pub mod openapi;
+pub mod password;
+pub mod password_policy;
 pub mod time;
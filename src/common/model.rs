@@ -2,12 +2,17 @@ use actix_web::{error::ResponseError, HttpResponse};
 use serde::Serialize;
 use utoipa::{ToResponse, ToSchema};
 
+use super::password_policy::{PasswordPolicyErrorResponse, PasswordViolationDetail};
+
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Serialize, ToSchema, ToResponse)]
 pub enum AppError {
     DatabaseError(String),
     ValidationError(String),
     NotFoundError(String),
     UnauthorizedError(String),
+    ForbiddenError(String),
+    PasswordPolicyError(Vec<PasswordViolationDetail>),
 }
 
 impl std::fmt::Display for AppError {
@@ -23,6 +28,13 @@ impl ResponseError for AppError {
             AppError::ValidationError(message) => HttpResponse::BadRequest().json(message),
             AppError::NotFoundError(message) => HttpResponse::NotFound().json(message),
             AppError::UnauthorizedError(message) => HttpResponse::Unauthorized().json(message),
+            AppError::ForbiddenError(message) => HttpResponse::Forbidden().json(message),
+            AppError::PasswordPolicyError(violations) => {
+                HttpResponse::BadRequest().json(PasswordPolicyErrorResponse {
+                    message: "Password doesn't meet the password policy".to_string(),
+                    violations: violations.clone(),
+                })
+            }
         }
     }
 }
@@ -1,31 +1,184 @@
-use crate::authentication::model::LoginRequest;
+use crate::api_keys::routes as api_keys;
+use crate::authentication::model::{
+    ForgotPasswordRequest, LoginRequest, LogoutRequest, MfaChallengeResponse, MfaCodeRequest,
+    MfaVerifyRequest, RefreshRequest, ResetPasswordRequest, TokenError, TokenErrorResponse,
+    TokenResponse, TotpConfirmRequest,
+};
 use crate::authentication::routes as authentication;
 use crate::common::model::AppError;
+use crate::common::password_policy::{
+    PasswordPolicyErrorResponse, PasswordViolation, PasswordViolationDetail,
+};
+use crate::database::model::api_keys::{ApiKeyResponse, CreateApiKeyRequest};
+use crate::database::model::impersonations::{
+    Impersonation, ImpersonationRequest, ImpersonationResponse,
+};
+use crate::database::model::mfa::{RecoveryCodesResponse, TotpEnrollmentResponse};
+use crate::database::model::oauth::{
+    AuthorizationResponse, ConsentDecisionRequest, CreateOAuthClientRequest, OAuthClientResponse,
+    OAuthConsentResponse, OAuthErrorResponse, OAuthTokenRequest, OAuthTokenResponse,
+    OpenIdConfiguration,
+};
+use crate::database::model::permissions::{
+    CreatePermissionRequest, Permission, RolePermissionsResponse, UpdatePermissionRequest,
+};
+use crate::database::model::sessions::SessionResponse;
+use crate::database::model::signing_keys::{CreateSigningKeyRequest, SigningKeyResponse};
 use crate::database::model::users::UserRole;
-use crate::database::model::users::{CreateUserRequest, UpdateUserRequest, User};
+use crate::database::model::users::{
+    AdminUserResponse, CreateUserRequest, PublicUserResponse, RegisterRequest, SortOrder,
+    UpdateUserRequest, UserResponse, UserSort, UserView, VerifyEmailRequest,
+};
+use crate::database::model::webauthn::{
+    AssertionResponse, AttestationResponse, AuthenticationCredential, AuthenticatorSelection,
+    CredentialCreationOptions, CredentialDescriptor, CredentialParameters,
+    CredentialRequestOptions, RegistrationCredential, RelyingPartyEntity,
+    WebauthnCredentialResponse, WebauthnLoginStartRequest, WebauthnRegisterFinishRequest,
+    WebauthnUserEntity,
+};
 use crate::database::routes as database;
+use crate::oauth::routes as oauth;
+use crate::permissions::routes as permissions;
+use crate::sessions::routes as sessions;
 use crate::users::routes as users;
 
-use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
 use utoipa::{Modify, OpenApi};
 
 #[derive(OpenApi)]
 #[openapi(
     paths(
         // Authentication handlers
+        authentication::register_handler,
+        authentication::verify_email_handler,
         authentication::login_handler,
+        authentication::verify_mfa_handler,
+        authentication::start_totp_enrollment_handler,
+        authentication::confirm_totp_enrollment_handler,
+        authentication::disable_totp_handler,
+        authentication::start_webauthn_registration_handler,
+        authentication::finish_webauthn_registration_handler,
+        authentication::find_webauthn_credentials_handler,
+        authentication::delete_webauthn_credential_handler,
+        authentication::start_webauthn_login_handler,
+        authentication::finish_webauthn_login_handler,
+        authentication::start_oidc_login_handler,
+        authentication::oidc_callback_handler,
+        authentication::forgot_password_handler,
+        authentication::reset_password_handler,
+        authentication::refresh_handler,
+        authentication::logout_handler,
+        authentication::logout_all_handler,
+        authentication::jwks_handler,
+        authentication::find_all_signing_keys_handler,
+        authentication::create_signing_key_handler,
+        authentication::promote_signing_key_handler,
+        authentication::retire_signing_key_handler,
+        authentication::reload_signing_keys_handler,
+        authentication::unlock_user_handler,
+        authentication::impersonate_user_handler,
+        authentication::find_impersonations_handler,
         // User handlers
         users::find_all_users_handler,
+        users::search_users_handler,
         users::find_user_handler,
         users::create_user_handler,
         users::update_user_handler,
         users::delete_user_handler,
+        // API key handlers
+        api_keys::find_api_keys_handler,
+        api_keys::create_api_key_handler,
+        api_keys::delete_api_key_handler,
+        sessions::find_sessions_handler,
+        sessions::delete_session_handler,
+        // Permission handlers
+        permissions::find_all_permissions_handler,
+        permissions::create_permission_handler,
+        permissions::update_permission_handler,
+        permissions::delete_permission_handler,
+        permissions::find_all_role_permissions_handler,
+        permissions::grant_permission_handler,
+        permissions::revoke_permission_handler,
+        // OAuth handlers
+        oauth::authorize_handler,
+        oauth::consent_handler,
+        oauth::token_handler,
+        oauth::find_consents_handler,
+        oauth::revoke_consent_handler,
+        oauth::find_all_clients_handler,
+        oauth::create_client_handler,
+        oauth::delete_client_handler,
+        oauth::openid_configuration_handler,
         // Database handlers
         database::seed_database_handler
     ),
     components(
-        schemas(UpdateUserRequest, CreateUserRequest, User, LoginRequest, UserRole),
-        responses(User, AppError),
+        schemas(
+            UpdateUserRequest,
+            CreateUserRequest,
+            UserResponse,
+            AdminUserResponse,
+            PublicUserResponse,
+            UserView,
+            UserSort,
+            SortOrder,
+            RegisterRequest,
+            VerifyEmailRequest,
+            LoginRequest,
+            MfaChallengeResponse,
+            MfaVerifyRequest,
+            MfaCodeRequest,
+            TotpConfirmRequest,
+            TotpEnrollmentResponse,
+            RecoveryCodesResponse,
+            CredentialCreationOptions,
+            CredentialRequestOptions,
+            RelyingPartyEntity,
+            WebauthnUserEntity,
+            CredentialParameters,
+            CredentialDescriptor,
+            AuthenticatorSelection,
+            WebauthnRegisterFinishRequest,
+            RegistrationCredential,
+            AttestationResponse,
+            WebauthnLoginStartRequest,
+            AuthenticationCredential,
+            AssertionResponse,
+            WebauthnCredentialResponse,
+            ForgotPasswordRequest,
+            ResetPasswordRequest,
+            RefreshRequest,
+            LogoutRequest,
+            TokenResponse,
+            TokenError,
+            TokenErrorResponse,
+            SigningKeyResponse,
+            CreateSigningKeyRequest,
+            Permission,
+            CreatePermissionRequest,
+            UpdatePermissionRequest,
+            RolePermissionsResponse,
+            CreateApiKeyRequest,
+            ApiKeyResponse,
+            SessionResponse,
+            ImpersonationRequest,
+            ImpersonationResponse,
+            Impersonation,
+            CreateOAuthClientRequest,
+            OAuthClientResponse,
+            OAuthConsentResponse,
+            ConsentDecisionRequest,
+            AuthorizationResponse,
+            OAuthTokenRequest,
+            OAuthTokenResponse,
+            OAuthErrorResponse,
+            OpenIdConfiguration,
+            PasswordPolicyErrorResponse,
+            PasswordViolationDetail,
+            PasswordViolation,
+            UserRole
+        ),
+        responses(UserResponse, AppError),
     ),
     info(
         title = "Rust API",
@@ -39,11 +192,12 @@ use utoipa::{Modify, OpenApi};
     tags(
         (name = "authentication", description = "Authentication endpoints."),
         (name = "users", description = "User management endpoints."),
+        (name = "permissions", description = "Role and permission management endpoints."),
+        (name = "oauth", description = "OAuth2 authorization server endpoints."),
         (name = "database", description = "Database management endpoints.")
     ),
     modifiers(&SecurityAddon)
 )]
-
 pub struct ApiDoc;
 
 struct SecurityAddon;
@@ -62,6 +216,10 @@ impl Modify for SecurityAddon {
                         .build(),
                 ),
             );
+            components.add_security_scheme(
+                "api_key",
+                SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-API-Key"))),
+            );
         } else {
             // If components are not defined, create new components with the security scheme
             openapi.components = Some(
@@ -75,6 +233,10 @@ impl Modify for SecurityAddon {
                                 .build(),
                         ),
                     )
+                    .security_scheme(
+                        "api_key",
+                        SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-API-Key"))),
+                    )
                     .build(),
             );
         }
@@ -0,0 +1,183 @@
+use std::env;
+
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher as _, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+use lazy_static::lazy_static;
+use openssl::rand::rand_bytes;
+
+use super::crypto::generate_opaque_token;
+
+const SALT_BYTES: usize = 16;
+
+lazy_static! {
+    static ref PASSWORD_HASHER: Box<dyn PasswordHasher> =
+        hasher_from_env().unwrap_or_else(|e| panic!("Invalid password hashing configuration: {}", e));
+    // Hash of a random password with the configured hasher, compared against for
+    // unknown emails so they take as long as a wrong password.
+    static ref DUMMY_PASSWORD_HASH: String = hash_password(&generate_opaque_token())
+        .expect("Error: Unable to hash the dummy password");
+}
+
+// Hashes new passwords. Hashes are stored as PHC strings ($argon2id$...), legacy
+// bcrypt hashes ($2b$...) keep working and are upgraded on the next login.
+pub trait PasswordHasher: Send + Sync {
+    fn hash(&self, password: &str) -> Result<String, String>;
+    // Whether the hash was made by another algorithm or with other parameters.
+    fn needs_rehash(&self, hash: &str) -> bool;
+}
+
+pub struct Argon2idHasher {
+    params: Params,
+}
+
+impl Argon2idHasher {
+    pub fn new(memory_kib: u32, iterations: u32, parallelism: u32) -> Result<Self, String> {
+        let params =
+            Params::new(memory_kib, iterations, parallelism, None).map_err(|e| e.to_string())?;
+        Ok(Argon2idHasher { params })
+    }
+}
+
+impl PasswordHasher for Argon2idHasher {
+    fn hash(&self, password: &str) -> Result<String, String> {
+        let mut salt = [0u8; SALT_BYTES];
+        rand_bytes(&mut salt).map_err(|e| e.to_string())?;
+        let salt = SaltString::encode_b64(&salt).map_err(|e| e.to_string())?;
+
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| e.to_string())
+    }
+
+    fn needs_rehash(&self, hash: &str) -> bool {
+        let parsed = match PasswordHash::new(hash) {
+            Ok(parsed) => parsed,
+            Err(_) => return true,
+        };
+        let params = match Params::try_from(&parsed) {
+            Ok(params) => params,
+            Err(_) => return true,
+        };
+
+        parsed.algorithm != Algorithm::Argon2id.ident()
+            || parsed.version != Some(Version::V0x13.into())
+            || params.m_cost() != self.params.m_cost()
+            || params.t_cost() != self.params.t_cost()
+            || params.p_cost() != self.params.p_cost()
+    }
+}
+
+pub struct BcryptHasher {
+    cost: u32,
+}
+
+impl BcryptHasher {
+    pub fn new(cost: u32) -> Self {
+        BcryptHasher { cost }
+    }
+}
+
+impl PasswordHasher for BcryptHasher {
+    fn hash(&self, password: &str) -> Result<String, String> {
+        bcrypt::hash(password, self.cost).map_err(|e| e.to_string())
+    }
+
+    fn needs_rehash(&self, hash: &str) -> bool {
+        // $2b$<cost>$<salt and hash>
+        match hash.split('$').collect::<Vec<_>>().as_slice() {
+            ["", "2a" | "2b" | "2y", cost, _] => cost.parse::<u32>() != Ok(self.cost),
+            _ => true,
+        }
+    }
+}
+
+fn env_number(name: &str, default: u32) -> Result<u32, String> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| format!("{} must be a number", name)),
+        Err(_) => Ok(default),
+    }
+}
+
+// PASSWORD_HASHER selects the algorithm for new hashes, 'argon2id' (default) or 'bcrypt'.
+fn hasher_from_env() -> Result<Box<dyn PasswordHasher>, String> {
+    match env::var("PASSWORD_HASHER").as_deref() {
+        Ok("argon2id") | Err(_) => Ok(Box::new(Argon2idHasher::new(
+            env_number("ARGON2_MEMORY_KIB", Params::DEFAULT_M_COST)?,
+            env_number("ARGON2_ITERATIONS", Params::DEFAULT_T_COST)?,
+            env_number("ARGON2_PARALLELISM", Params::DEFAULT_P_COST)?,
+        )?)),
+        Ok("bcrypt") => Ok(Box::new(BcryptHasher::new(env_number(
+            "BCRYPT_COST",
+            bcrypt::DEFAULT_COST,
+        )?))),
+        Ok(other) => Err(format!("Unknown PASSWORD_HASHER {}", other)),
+    }
+}
+
+// Load the configuration on startup instead of on the first login.
+pub fn init_password_hasher() {
+    lazy_static::initialize(&DUMMY_PASSWORD_HASH);
+}
+
+pub fn hash_password(password: &str) -> Result<String, String> {
+    PASSWORD_HASHER.hash(password)
+}
+
+pub fn needs_rehash(hash: &str) -> bool {
+    PASSWORD_HASHER.needs_rehash(hash)
+}
+
+// Verify against a hash made by any supported algorithm, whatever is configured now.
+pub fn verify_password(password: &str, hash: &str) -> Result<bool, String> {
+    if hash.starts_with("$argon2") {
+        let parsed = PasswordHash::new(hash).map_err(|e| e.to_string())?;
+        return Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok());
+    }
+    bcrypt::verify(password, hash).map_err(|e| e.to_string())
+}
+
+pub fn verify_dummy_password(password: &str) {
+    let _ = verify_password(password, &DUMMY_PASSWORD_HASH);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_password, Argon2idHasher, BcryptHasher, PasswordHasher};
+
+    #[test]
+    fn argon2id_round_trip() {
+        let hasher = Argon2idHasher::new(1024, 1, 1).unwrap();
+        let hash = hasher.hash("password").unwrap();
+
+        assert!(hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+        assert!(verify_password("password", &hash).unwrap());
+        assert!(!verify_password("wrong", &hash).unwrap());
+        assert!(!hasher.needs_rehash(&hash));
+        assert!(Argon2idHasher::new(2048, 1, 1).unwrap().needs_rehash(&hash));
+    }
+
+    #[test]
+    fn bcrypt_round_trip() {
+        let hasher = BcryptHasher::new(4);
+        let hash = hasher.hash("password").unwrap();
+
+        assert!(verify_password("password", &hash).unwrap());
+        assert!(!verify_password("wrong", &hash).unwrap());
+        assert!(!hasher.needs_rehash(&hash));
+        assert!(BcryptHasher::new(5).needs_rehash(&hash));
+    }
+
+    #[test]
+    fn bcrypt_hashes_are_upgraded_to_argon2id() {
+        let hash = BcryptHasher::new(4).hash("password").unwrap();
+
+        assert!(Argon2idHasher::new(1024, 1, 1).unwrap().needs_rehash(&hash));
+    }
+}
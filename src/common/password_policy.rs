@@ -0,0 +1,276 @@
+use std::{
+    env,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+};
+
+use lazy_static::lazy_static;
+use openssl::sha::sha1;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::crypto::to_hex;
+
+lazy_static! {
+    static ref PASSWORD_POLICY: PasswordPolicy = PasswordPolicy::from_env()
+        .unwrap_or_else(|e| panic!("Invalid password policy configuration: {}", e));
+}
+
+// Number of leading SHA-1 hex characters that name a range file
+const HASH_PREFIX_LENGTH: usize = 5;
+
+#[derive(Serialize, Debug, ToSchema, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PasswordViolation {
+    TooShort,
+    TooLong,
+    TooFewCharacterClasses,
+    MatchesAccountDetails,
+    Breached,
+}
+
+#[derive(Serialize, Debug, ToSchema, Clone)]
+pub struct PasswordViolationDetail {
+    pub code: PasswordViolation,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, ToSchema, Clone)]
+pub struct PasswordPolicyErrorResponse {
+    pub message: String,
+    pub violations: Vec<PasswordViolationDetail>,
+}
+
+struct PasswordPolicy {
+    min_length: usize,
+    // bcrypt ignores everything after 72 bytes
+    max_bytes: usize,
+    // Out of lowercase, uppercase, digits and other characters
+    min_character_classes: usize,
+    // Directory of <PREFIX>.txt files with the SUFFIX:COUNT lines of breached password
+    // SHA-1 hashes starting with PREFIX, the layout of the Pwned Passwords range API.
+    breached_passwords_dir: Option<PathBuf>,
+}
+
+fn env_number(name: &str, default: usize) -> Result<usize, String> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| format!("{} must be a number", name)),
+        Err(_) => Ok(default),
+    }
+}
+
+impl PasswordPolicy {
+    fn from_env() -> Result<Self, String> {
+        Ok(PasswordPolicy {
+            min_length: env_number("PASSWORD_MIN_LENGTH", 8)?,
+            max_bytes: env_number("PASSWORD_MAX_BYTES", 72)?,
+            min_character_classes: env_number("PASSWORD_MIN_CHARACTER_CLASSES", 2)?,
+            breached_passwords_dir: env::var("BREACHED_PASSWORDS_DIR").ok().map(PathBuf::from),
+        })
+    }
+
+    fn describe(&self, violation: PasswordViolation) -> String {
+        match violation {
+            PasswordViolation::TooShort => {
+                format!("Must be at least {} characters long", self.min_length)
+            }
+            PasswordViolation::TooLong => format!("Must be at most {} bytes long", self.max_bytes),
+            PasswordViolation::TooFewCharacterClasses => format!(
+                "Must contain at least {} of lowercase letters, uppercase letters, digits and symbols",
+                self.min_character_classes
+            ),
+            PasswordViolation::MatchesAccountDetails => {
+                "Must not be the username or email address".to_string()
+            }
+            PasswordViolation::Breached => {
+                "Appears in a list of breached passwords".to_string()
+            }
+        }
+    }
+
+    fn is_breached(&self, password: &str) -> bool {
+        let dir = match &self.breached_passwords_dir {
+            Some(dir) => dir,
+            None => return false,
+        };
+        let hash = to_hex(&sha1(password.as_bytes())).to_uppercase();
+        let (prefix, suffix) = hash.split_at(HASH_PREFIX_LENGTH);
+
+        // Missing range files mean no breached password has the prefix
+        let file = match File::open(dir.join(format!("{}.txt", prefix))) {
+            Ok(file) => file,
+            Err(_) => return false,
+        };
+        BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .any(|line| {
+                line.split(':')
+                    .next()
+                    .is_some_and(|candidate| candidate.trim().eq_ignore_ascii_case(suffix))
+            })
+    }
+
+    fn validate(
+        &self,
+        password: &str,
+        account_details: &[&str],
+    ) -> Result<(), Vec<PasswordViolationDetail>> {
+        let mut violations = Vec::new();
+
+        if password.chars().count() < self.min_length {
+            violations.push(PasswordViolation::TooShort);
+        }
+        if password.len() > self.max_bytes {
+            violations.push(PasswordViolation::TooLong);
+        }
+        if character_classes(password) < self.min_character_classes {
+            violations.push(PasswordViolation::TooFewCharacterClasses);
+        }
+
+        let lowercase = password.to_lowercase();
+        let matches_account = account_details.iter().any(|detail| {
+            let detail = detail.to_lowercase();
+            let local_part = detail.split('@').next().unwrap_or_default();
+            lowercase == detail || lowercase == local_part
+        });
+        if matches_account {
+            violations.push(PasswordViolation::MatchesAccountDetails);
+        }
+        if self.is_breached(password) {
+            violations.push(PasswordViolation::Breached);
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations
+                .into_iter()
+                .map(|violation| PasswordViolationDetail {
+                    code: violation,
+                    message: self.describe(violation),
+                })
+                .collect())
+        }
+    }
+}
+
+fn character_classes(password: &str) -> usize {
+    [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_numeric()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ]
+    .into_iter()
+    .filter(|present| *present)
+    .count()
+}
+
+// Load the configuration on startup instead of on the first password change.
+pub fn init_password_policy() {
+    lazy_static::initialize(&PASSWORD_POLICY);
+}
+
+// Check a new password against the configured policy. account_details are the
+// username and email address of the account the password is for.
+pub fn validate_password(
+    password: &str,
+    account_details: &[&str],
+) -> Result<(), Vec<PasswordViolationDetail>> {
+    PASSWORD_POLICY.validate(password, account_details)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::{character_classes, PasswordPolicy, PasswordViolation};
+
+    // The defaults, independent of the environment the tests run in
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 8,
+            max_bytes: 72,
+            min_character_classes: 2,
+            breached_passwords_dir: None,
+        }
+    }
+
+    fn violations(password: &str, account_details: &[&str]) -> Vec<PasswordViolation> {
+        policy()
+            .validate(password, account_details)
+            .err()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|violation| violation.code)
+            .collect()
+    }
+
+    #[test]
+    fn counts_character_classes() {
+        assert_eq!(character_classes("password"), 1);
+        assert_eq!(character_classes("Password1"), 3);
+        assert_eq!(character_classes("Pass word1"), 4);
+    }
+
+    #[test]
+    fn reports_every_violation() {
+        assert_eq!(
+            violations("admin", &["admin", "admin@admin.com"]),
+            vec![
+                PasswordViolation::TooShort,
+                PasswordViolation::TooFewCharacterClasses,
+                PasswordViolation::MatchesAccountDetails,
+            ]
+        );
+        assert_eq!(
+            violations(&"a1".repeat(40), &[]),
+            vec![PasswordViolation::TooLong]
+        );
+    }
+
+    #[test]
+    fn rejects_email_local_part() {
+        assert_eq!(
+            violations("Jane.Doe-2024", &["jdoe", "jane.doe-2024@example.com"]),
+            vec![PasswordViolation::MatchesAccountDetails]
+        );
+        assert!(policy()
+            .validate("Jane.Doe-2025", &["jdoe", "jane.doe-2024@example.com"])
+            .is_ok());
+    }
+
+    #[test]
+    fn rejects_breached_passwords() {
+        // SHA-1 of "P@ssw0rd" is 21BD12DC183F740EE76F27B78EB39C8AD972A757
+        let dir = env::temp_dir().join(format!("breached-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("21BD1.txt"),
+            "0018A45C4D1DEF81644B54AB7F969B88D65:1\n2DC183F740EE76F27B78EB39C8AD972A757:52579\n",
+        )
+        .unwrap();
+        let policy = PasswordPolicy {
+            breached_passwords_dir: Some(dir.clone()),
+            ..policy()
+        };
+
+        let breached = policy.validate("P@ssw0rd", &[]);
+        let fine = policy.validate("P@ssw0rd-but-longer", &[]);
+        fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(
+            breached
+                .unwrap_err()
+                .iter()
+                .map(|v| v.code)
+                .collect::<Vec<_>>(),
+            vec![PasswordViolation::Breached]
+        );
+        assert!(fine.is_ok());
+    }
+}
@@ -0,0 +1,73 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::schema::api_keys;
+
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = api_keys)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: String,
+    pub expires_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = api_keys)]
+pub struct CreateApiKeyDb {
+    pub user_id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub key_hash: String,
+    pub scopes: String,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Deserialize, Debug, ToSchema, Clone)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    // Permissions of the user's role the key may use, e.g. users:read
+    pub scopes: Vec<String>,
+    // Between 1 and 365, 90 days when omitted
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Serialize, Debug, ToSchema, Clone)]
+pub struct ApiKeyResponse {
+    pub id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    // Only returned when the key is created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub scopes: Vec<String>,
+    pub expires_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+impl From<ApiKey> for ApiKeyResponse {
+    fn from(api_key: ApiKey) -> Self {
+        ApiKeyResponse {
+            id: api_key.id,
+            name: api_key.name,
+            prefix: api_key.prefix,
+            key: None,
+            scopes: api_key
+                .scopes
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+            expires_at: api_key.expires_at,
+            last_used_at: api_key.last_used_at,
+            created_at: api_key.created_at,
+        }
+    }
+}
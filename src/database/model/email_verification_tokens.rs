@@ -0,0 +1,22 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::schema::email_verification_tokens;
+
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = email_verification_tokens)]
+pub struct EmailVerificationToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = email_verification_tokens)]
+pub struct CreateEmailVerificationTokenDb {
+    pub user_id: Uuid,
+    pub token_hash: String,
+    pub expires_at: NaiveDateTime,
+}
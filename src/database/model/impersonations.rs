@@ -0,0 +1,44 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::schema::impersonations;
+
+#[derive(Queryable, Selectable, Serialize, Debug, ToSchema, Clone)]
+#[diesel(table_name = impersonations)]
+pub struct Impersonation {
+    pub id: Uuid,
+    // The admin who acted as the user
+    pub actor_id: Uuid,
+    pub user_id: Uuid,
+    pub reason: String,
+    pub token_id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = impersonations)]
+pub struct CreateImpersonationDb {
+    pub actor_id: Uuid,
+    pub user_id: Uuid,
+    pub reason: String,
+    pub token_id: Uuid,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Deserialize, Debug, ToSchema, Clone)]
+pub struct ImpersonationRequest {
+    // Why support needs to act as the user, e.g. a ticket number
+    pub reason: String,
+}
+
+#[derive(Serialize, Debug, ToSchema, Clone)]
+pub struct ImpersonationResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: usize,
+    pub impersonation: Impersonation,
+}
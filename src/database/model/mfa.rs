@@ -0,0 +1,42 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::schema::{mfa_recovery_codes, totp_credentials};
+
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = totp_credentials)]
+pub struct TotpCredential {
+    pub secret: String,
+    pub confirmed_at: Option<NaiveDateTime>,
+    pub last_used_step: Option<i64>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = totp_credentials)]
+pub struct CreateTotpCredentialDb {
+    pub user_id: Uuid,
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = mfa_recovery_codes)]
+pub struct CreateRecoveryCodeDb {
+    pub user_id: Uuid,
+    pub code_hash: String,
+}
+
+#[derive(Serialize, Debug, ToSchema, Clone)]
+pub struct TotpEnrollmentResponse {
+    // Base32 secret for authenticators that can't scan the otpauth URI
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Serialize, Debug, ToSchema, Clone)]
+pub struct RecoveryCodesResponse {
+    // Shown once, store them somewhere safe
+    pub recovery_codes: Vec<String>,
+}
@@ -1,2 +1,15 @@
+pub mod api_keys;
 pub mod db;
+pub mod email_verification_tokens;
+pub mod impersonations;
+pub mod mfa;
+pub mod oauth;
+pub mod password_reset_tokens;
+pub mod permissions;
+pub mod refresh_tokens;
+pub mod revoked_tokens;
+pub mod sessions;
+pub mod signing_keys;
+pub mod user_identities;
 pub mod users;
+pub mod webauthn;
@@ -0,0 +1,212 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::schema::{oauth_authorization_codes, oauth_clients, oauth_consents};
+
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = oauth_clients)]
+pub struct OAuthClient {
+    pub id: Uuid,
+    pub client_id: String,
+    pub client_secret_hash: Option<String>,
+    pub name: String,
+    pub redirect_uris: String,
+    pub scopes: String,
+    pub grant_types: String,
+    pub first_party: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = oauth_clients)]
+pub struct CreateOAuthClientDb {
+    pub client_id: String,
+    pub client_secret_hash: Option<String>,
+    pub name: String,
+    pub redirect_uris: String,
+    pub scopes: String,
+    pub grant_types: String,
+    pub first_party: bool,
+}
+
+fn default_grant_types() -> Vec<String> {
+    vec![
+        "authorization_code".to_string(),
+        "refresh_token".to_string(),
+    ]
+}
+
+#[derive(Deserialize, Debug, ToSchema, Clone)]
+pub struct CreateOAuthClientRequest {
+    pub name: String,
+    #[serde(default)]
+    pub redirect_uris: Vec<String>,
+    // Scopes the client may request, openid, profile, email or permission names
+    pub scopes: Vec<String>,
+    #[serde(default = "default_grant_types")]
+    pub grant_types: Vec<String>,
+    // Public clients, e.g. single page or mobile apps, can't keep a secret
+    #[serde(default)]
+    pub public: bool,
+    #[serde(default)]
+    pub first_party: bool,
+}
+
+#[derive(Serialize, Debug, ToSchema, Clone)]
+pub struct OAuthClientResponse {
+    pub client_id: String,
+    // Only returned when the client is registered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<String>,
+    pub grant_types: Vec<String>,
+    pub public: bool,
+    pub first_party: bool,
+    pub created_at: NaiveDateTime,
+}
+
+fn split(value: &str) -> Vec<String> {
+    value.split_whitespace().map(str::to_string).collect()
+}
+
+impl From<OAuthClient> for OAuthClientResponse {
+    fn from(client: OAuthClient) -> Self {
+        OAuthClientResponse {
+            client_id: client.client_id,
+            client_secret: None,
+            name: client.name,
+            redirect_uris: split(&client.redirect_uris),
+            scopes: split(&client.scopes),
+            grant_types: split(&client.grant_types),
+            public: client.client_secret_hash.is_none(),
+            first_party: client.first_party,
+            created_at: client.created_at,
+        }
+    }
+}
+
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = oauth_authorization_codes)]
+pub struct AuthorizationCode {
+    pub client_id: Uuid,
+    pub user_id: Uuid,
+    pub redirect_uri: String,
+    pub scope: String,
+    pub code_challenge: String,
+    pub nonce: Option<String>,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = oauth_authorization_codes)]
+pub struct CreateAuthorizationCodeDb {
+    pub code_hash: String,
+    pub client_id: Uuid,
+    pub user_id: Uuid,
+    pub redirect_uri: String,
+    pub scope: String,
+    pub code_challenge: String,
+    pub nonce: Option<String>,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = oauth_consents)]
+pub struct CreateOAuthConsentDb {
+    pub user_id: Uuid,
+    pub client_id: Uuid,
+    pub scopes: String,
+}
+
+#[derive(Serialize, Debug, ToSchema, Clone)]
+pub struct OAuthConsentResponse {
+    pub client_id: String,
+    pub client_name: String,
+    pub scopes: Vec<String>,
+    pub updated_at: NaiveDateTime,
+}
+
+// Query string of an authorization request, PKCE with S256 is required for every client
+#[derive(Deserialize, Debug, IntoParams, Clone)]
+#[into_params(parameter_in = Query)]
+pub struct AuthorizationRequest {
+    pub response_type: String,
+    pub client_id: String,
+    pub redirect_uri: Option<String>,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    pub nonce: Option<String>,
+}
+
+#[derive(Deserialize, Debug, ToSchema, Clone)]
+pub struct ConsentDecisionRequest {
+    pub approve: bool,
+}
+
+// Either the URL to send the browser back to, or what the user is asked to consent to
+#[derive(Serialize, Debug, ToSchema, Clone)]
+pub struct AuthorizationResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redirect_to: Option<String>,
+    pub consent_required: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_name: Option<String>,
+    pub scopes: Vec<String>,
+}
+
+// Form posted to the token endpoint, which parameters are required depends on the grant
+#[derive(Deserialize, Debug, ToSchema, Clone)]
+pub struct OAuthTokenRequest {
+    pub grant_type: String,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+    pub refresh_token: Option<String>,
+    pub scope: Option<String>,
+    // Client authentication for clients that don't use HTTP Basic
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+#[derive(Serialize, Debug, ToSchema, Clone)]
+pub struct OAuthTokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    pub scope: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+// RFC 6749 error response of the authorization and token endpoints
+#[derive(Serialize, Debug, ToSchema, Clone)]
+pub struct OAuthErrorResponse {
+    pub error: String,
+    pub error_description: String,
+}
+
+// There is no authorization_endpoint, /oauth/authorize is an API for the login UI that
+// needs a bearer token and answers with JSON, not the browser redirect clients expect
+#[derive(Serialize, Debug, ToSchema, Clone)]
+pub struct OpenIdConfiguration {
+    pub issuer: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+    pub scopes_supported: Vec<String>,
+    pub response_types_supported: Vec<String>,
+    pub grant_types_supported: Vec<String>,
+    pub subject_types_supported: Vec<String>,
+    pub id_token_signing_alg_values_supported: Vec<String>,
+    pub token_endpoint_auth_methods_supported: Vec<String>,
+    pub code_challenge_methods_supported: Vec<String>,
+    pub claims_supported: Vec<String>,
+}
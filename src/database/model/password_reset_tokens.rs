@@ -0,0 +1,21 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::schema::password_reset_tokens;
+
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = password_reset_tokens)]
+pub struct PasswordResetToken {
+    pub user_id: Uuid,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = password_reset_tokens)]
+pub struct CreatePasswordResetTokenDb {
+    pub user_id: Uuid,
+    pub token_hash: String,
+    pub expires_at: NaiveDateTime,
+}
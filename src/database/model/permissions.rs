@@ -0,0 +1,44 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::schema::{permissions, role_permissions};
+
+use super::users::UserRole;
+
+#[derive(Queryable, Selectable, Serialize, Debug, ToSchema, Clone)]
+#[diesel(table_name = permissions)]
+pub struct Permission {
+    pub name: String,
+    pub description: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Deserialize, Debug, ToSchema, Clone, Insertable)]
+#[diesel(table_name = permissions)]
+pub struct CreatePermissionRequest {
+    // e.g. users:read
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Deserialize, Debug, ToSchema, Clone, AsChangeset)]
+#[diesel(table_name = permissions)]
+#[diesel(treat_none_as_null = true)]
+pub struct UpdatePermissionRequest {
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = role_permissions)]
+pub struct CreateRolePermissionDb {
+    pub role_id: i32,
+    pub permission: String,
+}
+
+#[derive(Serialize, Debug, ToSchema, Clone)]
+pub struct RolePermissionsResponse {
+    pub role: UserRole,
+    pub permissions: Vec<String>,
+}
@@ -0,0 +1,30 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::schema::refresh_tokens;
+
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = refresh_tokens)]
+pub struct RefreshToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub family_id: Uuid,
+    pub expires_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub replaced_by: Option<Uuid>,
+    // Set when the token was issued to an OAuth client
+    pub client_id: Option<Uuid>,
+    pub scope: Option<String>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = refresh_tokens)]
+pub struct CreateRefreshTokenDb {
+    pub user_id: Uuid,
+    pub family_id: Uuid,
+    pub token_hash: String,
+    pub expires_at: NaiveDateTime,
+    pub client_id: Option<Uuid>,
+    pub scope: Option<String>,
+}
@@ -0,0 +1,27 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::schema::{revoked_tokens, user_token_revocations};
+
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = revoked_tokens)]
+pub struct RevokedToken {
+    pub jti: Uuid,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = revoked_tokens)]
+pub struct CreateRevokedTokenDb {
+    pub jti: Uuid,
+    pub user_id: Uuid,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Selectable, Insertable, Debug, Clone)]
+#[diesel(table_name = user_token_revocations)]
+pub struct UserTokenRevocation {
+    pub user_id: Uuid,
+    pub revoked_before: NaiveDateTime,
+}
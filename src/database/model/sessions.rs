@@ -0,0 +1,53 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::schema::sessions;
+
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = sessions)]
+pub struct Session {
+    pub id: Uuid,
+    pub device: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub last_seen_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = sessions)]
+pub struct CreateSessionDb {
+    pub user_id: Uuid,
+    pub device: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+#[derive(Serialize, Debug, ToSchema, Clone)]
+pub struct SessionResponse {
+    pub id: Uuid,
+    pub device: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub last_seen_at: NaiveDateTime,
+    // Whether this is the session the request was made with
+    pub current: bool,
+}
+
+impl SessionResponse {
+    pub fn new(session: Session, current: Option<Uuid>) -> Self {
+        SessionResponse {
+            current: current == Some(session.id),
+            id: session.id,
+            device: session.device,
+            user_agent: session.user_agent,
+            ip_address: session.ip_address,
+            created_at: session.created_at,
+            last_seen_at: session.last_seen_at,
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::schema::signing_keys;
+
+pub const KEY_STATUS_ACTIVE: &str = "active";
+pub const KEY_STATUS_VERIFY: &str = "verify";
+pub const KEY_STATUS_RETIRED: &str = "retired";
+
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = signing_keys)]
+pub struct SigningKey {
+    pub kid: String,
+    pub algorithm: String,
+    pub private_key: String,
+    pub public_key: Option<String>,
+    pub status: String,
+    pub created_at: NaiveDateTime,
+    pub activated_at: Option<NaiveDateTime>,
+    pub retired_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = signing_keys)]
+pub struct CreateSigningKeyDb {
+    pub kid: String,
+    pub algorithm: String,
+    pub private_key: String,
+    pub public_key: Option<String>,
+    pub status: String,
+    pub activated_at: Option<NaiveDateTime>,
+}
+
+// Key metadata returned by the admin routes, the key material never leaves the server.
+#[derive(Serialize, Debug, ToSchema, Clone)]
+pub struct SigningKeyResponse {
+    pub kid: String,
+    pub algorithm: String,
+    pub status: String,
+    pub created_at: NaiveDateTime,
+    pub activated_at: Option<NaiveDateTime>,
+    pub retired_at: Option<NaiveDateTime>,
+}
+
+impl From<SigningKey> for SigningKeyResponse {
+    fn from(key: SigningKey) -> Self {
+        SigningKeyResponse {
+            kid: key.kid,
+            algorithm: key.algorithm,
+            status: key.status,
+            created_at: key.created_at,
+            activated_at: key.activated_at,
+            retired_at: key.retired_at,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema, Clone)]
+pub struct CreateSigningKeyRequest {
+    // Defaults to JWT_ALGORITHM
+    pub algorithm: Option<String>,
+    // Defaults to a fingerprint of the public key
+    pub kid: Option<String>,
+}
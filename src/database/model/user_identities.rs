@@ -0,0 +1,41 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+use crate::schema::{oidc_login_states, user_identities};
+
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = user_identities)]
+pub struct UserIdentity {
+    pub id: Uuid,
+    pub user_id: Uuid,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = user_identities)]
+pub struct CreateUserIdentityDb {
+    pub user_id: Uuid,
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub last_login_at: Option<NaiveDateTime>,
+}
+
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = oidc_login_states)]
+pub struct OidcLoginState {
+    pub provider: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = oidc_login_states)]
+pub struct CreateOidcLoginStateDb {
+    pub state_hash: String,
+    pub provider: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    pub expires_at: NaiveDateTime,
+}
@@ -6,7 +6,7 @@ use diesel::sql_types::Integer;
 use diesel::Queryable;
 use diesel::{deserialize, prelude::*};
 use serde::{Deserialize, Serialize};
-use utoipa::{ToResponse, ToSchema};
+use utoipa::{IntoParams, ToResponse, ToSchema};
 use uuid::Uuid;
 
 use crate::schema::users;
@@ -36,7 +36,9 @@ where
     }
 }
 
-#[derive(Queryable, Serialize, Deserialize, Debug, ToSchema, ToResponse, Clone)]
+// Database row, never serialized. Responses use one of the views below.
+#[derive(Queryable, QueryableByName, Selectable, Debug, Clone)]
+#[diesel(table_name = users)]
 pub struct User {
     pub id: Uuid,
     pub username: String,
@@ -44,8 +46,83 @@ pub struct User {
     pub hashed_password: String,
     pub timezone: String,
     pub role: UserRole,
+    pub updated_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub email_verified_at: Option<NaiveDateTime>,
+    pub failed_login_attempts: i32,
+    pub locked_until: Option<NaiveDateTime>,
+}
+
+// What other users may see of an account
+#[derive(Serialize, Debug, ToSchema, Clone)]
+pub struct PublicUserResponse {
+    pub id: Uuid,
+    pub username: String,
+}
+
+// The account as seen by its owner
+#[derive(Serialize, Debug, ToSchema, ToResponse, Clone)]
+pub struct UserResponse {
+    pub id: Uuid,
+    pub username: String,
+    pub email: String,
+    pub timezone: String,
+    pub role: UserRole,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    pub email_verified_at: Option<NaiveDateTime>,
+}
+
+// Adds the login lockout state admins need to support the user
+#[derive(Serialize, Debug, ToSchema, Clone)]
+pub struct AdminUserResponse {
+    #[serde(flatten)]
+    pub user: UserResponse,
+    pub failed_login_attempts: i32,
+    pub locked_until: Option<NaiveDateTime>,
+}
+
+// The view of an account the caller is allowed to see
+#[derive(Serialize, Debug, ToSchema, Clone)]
+#[serde(untagged)]
+pub enum UserView {
+    Admin(AdminUserResponse),
+    Owner(UserResponse),
+    Public(PublicUserResponse),
+}
+
+impl From<User> for PublicUserResponse {
+    fn from(user: User) -> Self {
+        PublicUserResponse {
+            id: user.id,
+            username: user.username,
+        }
+    }
+}
+
+impl From<User> for UserResponse {
+    fn from(user: User) -> Self {
+        UserResponse {
+            id: user.id,
+            username: user.username,
+            email: user.email,
+            timezone: user.timezone,
+            role: user.role,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+            email_verified_at: user.email_verified_at,
+        }
+    }
+}
+
+impl From<User> for AdminUserResponse {
+    fn from(user: User) -> Self {
+        AdminUserResponse {
+            failed_login_attempts: user.failed_login_attempts,
+            locked_until: user.locked_until,
+            user: UserResponse::from(user),
+        }
+    }
 }
 
 #[derive(Deserialize, Debug, ToSchema, Clone, Insertable)]
@@ -85,3 +162,71 @@ pub struct UpdateUserDb {
     pub timezone: Option<String>,
     pub role: Option<i32>,
 }
+
+#[derive(Deserialize, Debug, ToSchema, Clone)]
+pub struct RegisterRequest {
+    pub username: String,
+    pub email: String,
+    pub password: String,
+    pub timezone: String,
+}
+
+#[derive(Deserialize, Debug, ToSchema, Clone)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum UserSort {
+    #[default]
+    CreatedAt,
+    Username,
+    Email,
+}
+
+#[derive(Deserialize, Debug, ToSchema, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+// Query string of GET /api/users. Pages are either addressed by offset or, for large
+// listings, by the cursor returned in the X-Next-Cursor header of the previous page.
+#[derive(Deserialize, Debug, IntoParams, Clone, Default)]
+#[into_params(parameter_in = Query)]
+pub struct UserListQuery {
+    // Page size between 1 and 100, 50 when omitted
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    // Continue after the last user of the previous page, can't be combined with offset
+    pub cursor: Option<String>,
+    // Only admins may sort by email
+    #[serde(default)]
+    pub sort: UserSort,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub role: Option<UserRole>,
+    pub timezone: Option<String>,
+    // Accounts created at or after this time, e.g. 2024-01-31T00:00:00
+    pub created_after: Option<NaiveDateTime>,
+    // Accounts created before this time
+    pub created_before: Option<NaiveDateTime>,
+    // Case insensitive partial match
+    pub username: Option<String>,
+    // Case insensitive partial match, admins only
+    pub email: Option<String>,
+}
+
+// Query string of GET /api/users/search
+#[derive(Deserialize, Debug, IntoParams, Clone)]
+#[into_params(parameter_in = Query)]
+pub struct UserSearchQuery {
+    // Words of the username or, for admins, the email address, or either of them with a typo
+    pub q: String,
+    // Page size between 1 and 100, 50 when omitted
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
@@ -0,0 +1,178 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::schema::{webauthn_challenges, webauthn_credentials};
+
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = webauthn_credentials)]
+pub struct WebauthnCredential {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub credential_id: String,
+    pub public_key: Vec<u8>,
+    pub sign_count: i64,
+    pub name: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = webauthn_credentials)]
+pub struct CreateWebauthnCredentialDb {
+    pub user_id: Uuid,
+    pub credential_id: String,
+    pub public_key: Vec<u8>,
+    pub algorithm: i32,
+    pub sign_count: i64,
+    pub name: Option<String>,
+}
+
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = webauthn_challenges)]
+pub struct WebauthnChallenge {
+    pub user_id: Option<Uuid>,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = webauthn_challenges)]
+pub struct CreateWebauthnChallengeDb {
+    pub user_id: Option<Uuid>,
+    pub challenge_hash: String,
+    pub ceremony: String,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Debug, ToSchema, Clone)]
+pub struct WebauthnCredentialResponse {
+    pub id: Uuid,
+    pub name: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+impl From<WebauthnCredential> for WebauthnCredentialResponse {
+    fn from(credential: WebauthnCredential) -> Self {
+        WebauthnCredentialResponse {
+            id: credential.id,
+            name: credential.name,
+            created_at: credential.created_at,
+            last_used_at: credential.last_used_at,
+        }
+    }
+}
+
+// The options below follow the WebAuthn JSON names, binary values are base64url encoded.
+
+#[derive(Serialize, Debug, ToSchema, Clone)]
+pub struct RelyingPartyEntity {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Debug, ToSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WebauthnUserEntity {
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Serialize, Debug, ToSchema, Clone)]
+pub struct CredentialParameters {
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub alg: i32,
+}
+
+#[derive(Serialize, Debug, ToSchema, Clone)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub credential_type: String,
+    pub id: String,
+}
+
+#[derive(Serialize, Debug, ToSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    pub resident_key: String,
+    pub user_verification: String,
+}
+
+// Options for navigator.credentials.create()
+#[derive(Serialize, Debug, ToSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialCreationOptions {
+    pub challenge: String,
+    pub rp: RelyingPartyEntity,
+    pub user: WebauthnUserEntity,
+    pub pub_key_cred_params: Vec<CredentialParameters>,
+    // Milliseconds
+    pub timeout: u64,
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+    pub authenticator_selection: AuthenticatorSelection,
+    pub attestation: String,
+}
+
+// Options for navigator.credentials.get()
+#[derive(Serialize, Debug, ToSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialRequestOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    // Milliseconds
+    pub timeout: u64,
+    pub allow_credentials: Vec<CredentialDescriptor>,
+    pub user_verification: String,
+}
+
+#[derive(Deserialize, Debug, ToSchema, Clone)]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    pub attestation_object: String,
+}
+
+// PublicKeyCredential.toJSON() of a new credential
+#[derive(Deserialize, Debug, ToSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationCredential {
+    pub raw_id: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Deserialize, Debug, ToSchema, Clone)]
+pub struct WebauthnRegisterFinishRequest {
+    // Label to tell the user's passkeys apart
+    pub name: Option<String>,
+    pub credential: RegistrationCredential,
+}
+
+#[derive(Deserialize, Debug, ToSchema, Clone)]
+pub struct WebauthnLoginStartRequest {
+    // Restricts the ceremony to the user's credentials, omit it for discoverable credentials
+    pub email: Option<String>,
+}
+
+#[derive(Deserialize, Debug, ToSchema, Clone)]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "authenticatorData")]
+    pub authenticator_data: String,
+    pub signature: String,
+    #[serde(rename = "userHandle")]
+    pub user_handle: Option<String>,
+}
+
+// PublicKeyCredential.toJSON() of an assertion
+#[derive(Deserialize, Debug, ToSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticationCredential {
+    pub raw_id: String,
+    pub response: AssertionResponse,
+}
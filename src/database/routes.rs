@@ -1,6 +1,10 @@
 use crate::{
     common::model::AppError,
-    database::{model::db::DbPool, service::seed_database, tools::get_connection},
+    database::{
+        model::db::DbPool,
+        service::{seed_database, SeedError},
+        tools::get_connection,
+    },
 };
 use actix_web::{get, web, Responder};
 
@@ -11,7 +15,8 @@ pub fn config(cfg: &mut web::ServiceConfig) {
 #[utoipa::path(
     path = "/admin/seed",
     responses(
-        (status = 200, description = "Successfully added data to database.", body = String),
+        (status = 200, description = "Successfully added data to database. The admin password is SEED_ADMIN_PASSWORD or, when it isn't set, a generated one included in the message.", body = String),
+        (status = 400, description = "SEED_ADMIN_PASSWORD doesn't meet the password policy", body = PasswordPolicyErrorResponse),
         (status = 403, description = "Access denied in production mode.", body = String),
         (status = 409, description = "Data may already be seeded in database.", body = String),
         (status = 500, description = "Internal Server Error")
@@ -29,8 +34,18 @@ async fn seed_database_handler(pool: web::Data<DbPool>) -> Result<impl Responder
     let mut conn = get_connection(pool);
     let result = seed_database(&mut conn);
     match result {
-        Ok(_) => Ok("Successfully added data to database. Try logging in with email: admin@admin.com, password: admin".to_string()),
-        Err(e) => Err(AppError::DatabaseError(format!(
+        Ok((user, Some(password))) => Ok(format!(
+            "Successfully added data to database. Try logging in with email: {}, password: {}",
+            user.email, password
+        )),
+        Ok((user, None)) => Ok(format!(
+            "Successfully added data to database. Try logging in with email: {} and SEED_ADMIN_PASSWORD",
+            user.email
+        )),
+        Err(SeedError::InvalidPassword(violations)) => {
+            Err(AppError::PasswordPolicyError(violations))
+        }
+        Err(SeedError::Database(e)) => Err(AppError::DatabaseError(format!(
             "Double check database, data may already be seeded. \n Error: {}",
             e
         ))),
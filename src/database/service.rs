@@ -1,17 +1,57 @@
-use diesel::{PgConnection, QueryResult};
+use std::env;
 
-use crate::{database::model::users::CreateUserRequest, users::service::create_user};
+use diesel::PgConnection;
+
+use crate::{
+    common::{
+        crypto::generate_opaque_token,
+        password_policy::{validate_password, PasswordViolationDetail},
+    },
+    database::model::users::CreateUserRequest,
+    users::service::create_user,
+};
 
 use super::model::users::{User, UserRole};
 
-pub fn seed_database(conn: &mut PgConnection) -> QueryResult<User> {
+const SEED_USERNAME: &str = "admin";
+const SEED_EMAIL: &str = "admin@admin.com";
+
+#[derive(Debug)]
+pub enum SeedError {
+    InvalidPassword(Vec<PasswordViolationDetail>),
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for SeedError {
+    fn from(e: diesel::result::Error) -> Self {
+        SeedError::Database(e)
+    }
+}
+
+// SEED_ADMIN_PASSWORD or, when it isn't set, a random password that is returned so it can
+// be shown once. Either way it has to pass the password policy.
+fn seed_password() -> (String, bool) {
+    match env::var("SEED_ADMIN_PASSWORD") {
+        Ok(password) => (password, false),
+        // Mixed case, digits and a symbol, whatever the policy's character classes are
+        Err(_) => (format!("Seed-{}", &generate_opaque_token()[..32]), true),
+    }
+}
+
+// Returns the seeded user and the generated password, if it wasn't configured
+pub fn seed_database(conn: &mut PgConnection) -> Result<(User, Option<String>), SeedError> {
+    let (password, generated) = seed_password();
+    validate_password(&password, &[SEED_USERNAME, SEED_EMAIL])
+        .map_err(SeedError::InvalidPassword)?;
+
     let user_data = CreateUserRequest {
-        username: "admin".to_string(),
-        email: "admin@admin.com".to_string(),
-        password: "admin".to_string(),
+        username: SEED_USERNAME.to_string(),
+        email: SEED_EMAIL.to_string(),
+        password: password.clone(),
         timezone: "America/Los_Angeles".to_string(),
         role: UserRole::User,
     };
 
-    create_user(conn, user_data)
+    let user = create_user(conn, user_data)?;
+    Ok((user, generated.then_some(password)))
 }
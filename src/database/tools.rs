@@ -36,3 +36,51 @@ pub fn run_migrations(pool: DbPool) -> Result<(), Box<dyn Error + Send + Sync +
 
     Ok(())
 }
+
+// Helpers for the tests that need a database. Run them against a disposable one with
+// TEST_DATABASE_URL=postgres://... cargo test -- --ignored --test-threads=1
+#[cfg(test)]
+pub mod testing {
+    use diesel::{r2d2::ConnectionManager, PgConnection};
+    use uuid::Uuid;
+
+    use super::run_migrations;
+    use crate::{
+        authentication::jwt::key_ring::load_test_key_ring,
+        database::model::{
+            db::DbPool,
+            users::{CreateUserRequest, User, UserRole},
+        },
+        users::service::create_user,
+    };
+
+    pub const TEST_PASSWORD: &str = "correct horse battery staple";
+
+    pub fn test_pool() -> DbPool {
+        let database_url =
+            std::env::var("TEST_DATABASE_URL").expect("Error: Missing TEST_DATABASE_URL.");
+        let pool = r2d2::Pool::builder()
+            .max_size(2)
+            .build(ConnectionManager::<PgConnection>::new(database_url))
+            .expect("Failed to create pool.");
+        run_migrations(pool.clone()).expect("Failed to run migrations.");
+        load_test_key_ring();
+        pool
+    }
+
+    // A user with a unique name and TEST_PASSWORD, delete it at the end of the test
+    pub fn create_test_user(pool: &DbPool, role: UserRole) -> User {
+        let suffix = Uuid::new_v4().simple().to_string();
+        create_user(
+            &mut pool.get().unwrap(),
+            CreateUserRequest {
+                username: format!("test_{}", suffix),
+                email: format!("test_{}@example.com", suffix),
+                password: TEST_PASSWORD.to_string(),
+                timezone: "UTC".to_string(),
+                role,
+            },
+        )
+        .expect("Failed to create test user.")
+    }
+}
@@ -1,7 +1,11 @@
+mod api_keys;
 mod authentication;
 mod common;
 mod database;
+mod oauth;
+mod permissions;
 mod schema;
+mod sessions;
 mod users;
 
 use actix_cors::Cors;
@@ -10,28 +14,37 @@ use actix_web::{
     web::{self, Data},
     App, HttpResponse, HttpServer, Responder,
 };
-use authentication::middleware::AuthenticationCheck;
-use common::openapi::ApiDoc;
+use authentication::{
+    cookies::services::init_token_transport,
+    jwt::key_ring::{reload_key_ring, spawn_key_ring_reload},
+    middleware::AuthenticationCheck,
+    oidc::client::OidcClient,
+    revocation::services::RevocationStore,
+    throttle::services::LoginThrottle,
+};
+use common::{
+    mailer::mailer_from_env, openapi::ApiDoc, password::init_password_hasher,
+    password_policy::init_password_policy,
+};
 use database::{
     model::db::DbPool,
     tools::{establish_db_connection, run_migrations},
 };
 
-use jsonwebtoken::{DecodingKey, EncodingKey};
-use lazy_static::lazy_static;
-use std::env;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
-pub const JWT_ALGORITHM: jsonwebtoken::Algorithm = jsonwebtoken::Algorithm::HS256;
-pub const JWT_LIFETIME: usize = 60 * 60 * 24 * 30; // 30 days
-lazy_static! {
-    static ref JWT_SECRET: Vec<u8> = env::var("JWT_SECRET")
-        .expect("JWT_SECRET must be set in .env file")
-        .into_bytes();
-    static ref ENCODING_KEY: EncodingKey = EncodingKey::from_secret(&JWT_SECRET);
-    static ref DECODING_KEY: DecodingKey = DecodingKey::from_secret(&JWT_SECRET);
-}
+pub const JWT_LIFETIME: usize = 60 * 15; // 15 minutes
+pub const REFRESH_TOKEN_LIFETIME: usize = 60 * 60 * 24 * 30; // 30 days
+pub const EMAIL_VERIFICATION_LIFETIME: usize = 60 * 60 * 24; // 24 hours
+pub const PASSWORD_RESET_LIFETIME: usize = 60 * 60; // 1 hour
+pub const MFA_PENDING_LIFETIME: usize = 60 * 5; // 5 minutes
+pub const WEBAUTHN_CHALLENGE_LIFETIME: usize = 60 * 5; // 5 minutes
+pub const OIDC_LOGIN_LIFETIME: usize = 60 * 10; // 10 minutes
+pub const OAUTH_CODE_LIFETIME: usize = 60; // 1 minute
+
+// Not longer than JWT_LIFETIME, the revocation cache only covers tokens that recent
+pub const IMPERSONATION_LIFETIME: usize = 60 * 10; // 10 minutes
 
 #[get("/")]
 async fn hello() -> impl Responder {
@@ -49,6 +62,19 @@ async fn main() -> std::io::Result<()> {
         Ok(_) => println!("Database schema updated."),
         Err(e) => println!("Error running migrations: {}", e),
     };
+    // Fail on startup rather than on the first login if the signing keys are misconfigured
+    if let Err(e) = reload_key_ring(&pool) {
+        panic!("Unable to load JWT signing keys: {}", e);
+    }
+    spawn_key_ring_reload(pool.clone());
+    init_password_hasher();
+    init_password_policy();
+    init_token_transport();
+    let revocation_store = Data::new(RevocationStore::new(pool.clone()));
+    let login_throttle =
+        Data::new(LoginThrottle::new(pool.clone()).unwrap_or_else(|e| panic!("{}", e)));
+    let mailer = Data::from(mailer_from_env().unwrap_or_else(|e| panic!("{}", e)));
+    let oidc_client = Data::new(OidcClient::from_env().unwrap_or_else(|e| panic!("{}", e)));
 
     HttpServer::new(move || {
         let cors = if cfg!(debug_assertions) {
@@ -59,11 +85,19 @@ async fn main() -> std::io::Result<()> {
                 .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
                 .allowed_headers(vec![http::header::AUTHORIZATION, http::header::ACCEPT])
                 .allowed_header(http::header::CONTENT_TYPE)
+                .allowed_header(http::header::HeaderName::from_static("x-csrf-token"))
+                .expose_headers(vec!["X-Total-Count", "X-Next-Cursor"])
+                // Lets the browser send the session cookies in cookie mode
+                .supports_credentials()
                 .max_age(3600)
         };
         App::new()
             .wrap(cors)
             .app_data(Data::new(pool.clone()))
+            .app_data(revocation_store.clone())
+            .app_data(login_throttle.clone())
+            .app_data(mailer.clone())
+            .app_data(oidc_client.clone())
             .wrap(middleware::Logger::default().log_target("debug"))
             .wrap(middleware::Logger::new(
                 "ip: %a user-agent: ${User-Agent}i time_to_complete: %D",
@@ -78,12 +112,26 @@ async fn main() -> std::io::Result<()> {
             .service(
                 web::scope("/api")
                     .wrap(AuthenticationCheck)
-                    .configure(users::routes::config),
+                    .configure(users::routes::config)
+                    .configure(api_keys::routes::config)
+                    .configure(sessions::routes::config),
             )
             // Register the authentication routes
             .service(web::scope("/auth").configure(authentication::routes::config))
+            // Register the OAuth2 authorization server routes
+            .service(web::scope("/oauth").configure(oauth::routes::config))
+            // Public keys for verifying issued tokens on /.well-known/jwks.json
+            .configure(authentication::routes::well_known_config)
+            // OpenID Connect discovery on /.well-known/openid-configuration
+            .configure(oauth::routes::well_known_config)
             // Register the database routes
-            .service(web::scope("/admin").configure(database::routes::config))
+            .service(
+                web::scope("/admin")
+                    .configure(database::routes::config)
+                    .configure(authentication::routes::admin_config)
+                    .configure(oauth::routes::admin_config)
+                    .configure(permissions::routes::config),
+            )
             // Simple health check for /
             .service(hello)
     })
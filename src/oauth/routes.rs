@@ -0,0 +1,458 @@
+use actix_web::{
+    delete, get,
+    http::header::{CACHE_CONTROL, PRAGMA, WWW_AUTHENTICATE},
+    post, web, HttpRequest, HttpResponse, Responder,
+};
+use log::error;
+use uuid::Uuid;
+
+use crate::authentication::guard::{roles, AllowImpersonation, RequireRole};
+use crate::authentication::jwt::{key_ring::key_ring, services::JWT_ISSUER};
+use crate::authentication::middleware::AuthenticationCheck;
+use crate::common::model::AppError;
+use crate::database::model::oauth::{
+    AuthorizationRequest, AuthorizationResponse, ConsentDecisionRequest, CreateOAuthClientRequest,
+    OAuthClientResponse, OAuthErrorResponse, OAuthTokenRequest, OpenIdConfiguration,
+};
+use crate::database::{model::db::DbPool, tools::get_connection};
+use crate::oauth::service::{
+    authenticate_client, authorization_code_grant, client_credentials_grant, create_client,
+    delete_client, find_all_clients, find_consents, grant_consent, has_consent,
+    issue_authorization_code, parse_basic_credentials, redirect_with, refresh_token_grant,
+    revoke_consent, supports_grant, validate_authorization_request, validate_client_request,
+    OAuthError, ValidatedAuthorization, AUTHORIZATION_CODE, CLIENT_CREDENTIALS, GRANT_TYPES,
+    OAUTH_BASE_URL, OPENID_SCOPES, REFRESH_TOKEN,
+};
+use crate::permissions::service::find_all_permissions;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(authorize_handler);
+    cfg.service(consent_handler);
+    cfg.service(token_handler);
+    cfg.service(find_consents_handler);
+    cfg.service(revoke_consent_handler);
+}
+
+pub fn admin_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(find_all_clients_handler);
+    cfg.service(create_client_handler);
+    cfg.service(delete_client_handler);
+}
+
+pub fn well_known_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(openid_configuration_handler);
+}
+
+// RFC 6749 error response, failed client authentication also gets a Basic challenge
+fn oauth_error(e: OAuthError) -> HttpResponse {
+    let mut response = match &e {
+        OAuthError::Database(e) => {
+            error!("{:?}", e);
+            HttpResponse::InternalServerError()
+        }
+        OAuthError::InvalidClient => {
+            let mut response = HttpResponse::Unauthorized();
+            response.insert_header((WWW_AUTHENTICATE, "Basic"));
+            response
+        }
+        _ => HttpResponse::BadRequest(),
+    };
+    response
+        .insert_header((CACHE_CONTROL, "no-store"))
+        .json(OAuthErrorResponse {
+            error: e.code().to_string(),
+            error_description: e.description(),
+        })
+}
+
+fn user_id(sub: &str) -> Result<Uuid, AppError> {
+    Uuid::parse_str(sub).map_err(|_| AppError::UnauthorizedError("Invalid token".to_string()))
+}
+
+// Redirect back to the client with a new authorization code
+fn code_redirect(
+    pool: web::Data<DbPool>,
+    owner_id: Uuid,
+    authorization: &ValidatedAuthorization,
+) -> HttpResponse {
+    let mut conn = get_connection(pool);
+    let code = match issue_authorization_code(&mut conn, owner_id, authorization) {
+        Ok(code) => code,
+        Err(e) => return oauth_error(OAuthError::Database(e)),
+    };
+    let mut params = vec![("code", code.as_str())];
+    if let Some(state) = &authorization.state {
+        params.push(("state", state));
+    }
+
+    HttpResponse::Ok().json(AuthorizationResponse {
+        redirect_to: Some(redirect_with(&authorization.redirect_uri, &params)),
+        consent_required: false,
+        client_name: None,
+        scopes: authorization.scopes.clone(),
+    })
+}
+
+// authorization endpoint, called by the login UI for the signed in user
+#[utoipa::path(
+    path = "/oauth/authorize",
+    params(AuthorizationRequest),
+    responses(
+        (status = 200, description = "Either the URL to redirect the browser to with the authorization code, or consent_required with the client name and scopes to show the user.", body = AuthorizationResponse),
+        (status = 400, description = "Invalid authorization request", body = OAuthErrorResponse),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "authorizeClient"
+)]
+#[get("/authorize", wrap = "AuthenticationCheck")]
+async fn authorize_handler(
+    pool: web::Data<DbPool>,
+    query: web::Query<AuthorizationRequest>,
+    claims: RequireRole<roles::Guest>,
+) -> Result<impl Responder, AppError> {
+    let owner_id = user_id(&claims.sub)?;
+    let mut conn = get_connection(pool.clone());
+
+    let authorization = match validate_authorization_request(&mut conn, query.into_inner()) {
+        Ok(authorization) => authorization,
+        Err(e) => return Ok(oauth_error(e)),
+    };
+    match has_consent(
+        &mut conn,
+        owner_id,
+        &authorization.client,
+        &authorization.scopes,
+    ) {
+        Ok(true) => Ok(code_redirect(pool, owner_id, &authorization)),
+        Ok(false) => Ok(HttpResponse::Ok().json(AuthorizationResponse {
+            redirect_to: None,
+            consent_required: true,
+            client_name: Some(authorization.client.name),
+            scopes: authorization.scopes,
+        })),
+        Err(e) => Ok(oauth_error(OAuthError::Database(e))),
+    }
+}
+
+// record the user's decision on the consent screen
+#[utoipa::path(
+    path = "/oauth/authorize",
+    params(AuthorizationRequest),
+    request_body = ConsentDecisionRequest,
+    responses(
+        (status = 200, description = "URL to redirect the browser to, with the authorization code when approved and an access_denied error otherwise.", body = AuthorizationResponse),
+        (status = 400, description = "Invalid authorization request", body = OAuthErrorResponse),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "consentToClient"
+)]
+#[post("/authorize", wrap = "AuthenticationCheck")]
+async fn consent_handler(
+    pool: web::Data<DbPool>,
+    query: web::Query<AuthorizationRequest>,
+    req_body: web::Json<ConsentDecisionRequest>,
+    claims: RequireRole<roles::Guest>,
+) -> Result<impl Responder, AppError> {
+    let owner_id = user_id(&claims.sub)?;
+    let mut conn = get_connection(pool.clone());
+
+    let authorization = match validate_authorization_request(&mut conn, query.into_inner()) {
+        Ok(authorization) => authorization,
+        Err(e) => return Ok(oauth_error(e)),
+    };
+    if !req_body.approve {
+        let mut params = vec![("error", "access_denied")];
+        if let Some(state) = &authorization.state {
+            params.push(("state", state));
+        }
+        return Ok(HttpResponse::Ok().json(AuthorizationResponse {
+            redirect_to: Some(redirect_with(&authorization.redirect_uri, &params)),
+            consent_required: false,
+            client_name: None,
+            scopes: vec![],
+        }));
+    }
+
+    if let Err(e) = grant_consent(
+        &mut conn,
+        owner_id,
+        &authorization.client,
+        &authorization.scopes,
+    ) {
+        return Ok(oauth_error(OAuthError::Database(e)));
+    }
+    Ok(code_redirect(pool, owner_id, &authorization))
+}
+
+// token endpoint for the authorization_code, refresh_token and client_credentials grants
+#[utoipa::path(
+    path = "/oauth/token",
+    request_body(
+        content = OAuthTokenRequest,
+        content_type = "application/x-www-form-urlencoded",
+        description = "Clients authenticate with HTTP Basic or client_id and client_secret in the form. Public clients only send client_id."
+    ),
+    responses(
+        (status = 200, description = "Access token, with a refresh token when the client may use the refresh_token grant and an ID token for the openid scope.", body = OAuthTokenResponse),
+        (status = 400, description = "Invalid grant, scope or request", body = OAuthErrorResponse),
+        (status = 401, description = "Client authentication failed", body = OAuthErrorResponse),
+        (status = 500, description = "Internal Server Error")
+    ),
+    operation_id = "issueOAuthToken"
+)]
+#[post("/token")]
+async fn token_handler(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    form: web::Form<OAuthTokenRequest>,
+) -> impl Responder {
+    let request = form.into_inner();
+    let credentials = match req.headers().get("Authorization") {
+        Some(header) => header.to_str().ok().and_then(parse_basic_credentials),
+        None => request
+            .client_id
+            .clone()
+            .map(|client_id| (client_id, request.client_secret.clone().unwrap_or_default())),
+    };
+    let (client_id, client_secret) = match credentials {
+        Some(credentials) => credentials,
+        None => return oauth_error(OAuthError::InvalidClient),
+    };
+
+    let mut conn = get_connection(pool);
+    let secret = (!client_secret.is_empty()).then_some(client_secret.as_str());
+    let client = match authenticate_client(&mut conn, &client_id, secret) {
+        Ok(client) => client,
+        Err(e) => return oauth_error(e),
+    };
+
+    let grant_type = request.grant_type.as_str();
+    if !GRANT_TYPES.contains(&grant_type) {
+        return oauth_error(OAuthError::UnsupportedGrantType);
+    }
+    if !supports_grant(&client, grant_type) {
+        return oauth_error(OAuthError::UnauthorizedClient);
+    }
+    let result = match grant_type {
+        AUTHORIZATION_CODE => authorization_code_grant(&mut conn, &client, &request),
+        REFRESH_TOKEN => refresh_token_grant(&mut conn, &client, &request),
+        CLIENT_CREDENTIALS => client_credentials_grant(&client, &request),
+        _ => Err(OAuthError::UnsupportedGrantType),
+    };
+
+    match result {
+        Ok(tokens) => HttpResponse::Ok()
+            .insert_header((CACHE_CONTROL, "no-store"))
+            .insert_header((PRAGMA, "no-cache"))
+            .json(tokens),
+        Err(e) => oauth_error(e),
+    }
+}
+
+// find the clients the current user allowed to access the account
+#[utoipa::path(
+    path = "/oauth/consents",
+    responses(
+        (status = 200, description = "Consents of the current user", body = Vec<OAuthConsentResponse>),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "findOAuthConsents"
+)]
+#[get("/consents", wrap = "AuthenticationCheck")]
+async fn find_consents_handler(
+    pool: web::Data<DbPool>,
+    claims: AllowImpersonation<roles::Guest>,
+) -> Result<impl Responder, AppError> {
+    let owner_id = user_id(&claims.sub)?;
+    let mut conn = get_connection(pool);
+
+    match find_consents(&mut conn, owner_id) {
+        Ok(consents) => Ok(HttpResponse::Ok().json(consents)),
+        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+}
+
+// withdraw a consent, the client's refresh tokens for the user are revoked with it
+#[utoipa::path(
+    path = "/oauth/consents/{client_id}",
+    responses(
+        (status = 200, description = "Consent withdrawn"),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 404, description = "No consent for this client"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "revokeOAuthConsent"
+)]
+#[delete("/consents/{client_id}", wrap = "AuthenticationCheck")]
+async fn revoke_consent_handler(
+    pool: web::Data<DbPool>,
+    client_id: web::Path<String>,
+    claims: RequireRole<roles::Guest>,
+) -> Result<impl Responder, AppError> {
+    let owner_id = user_id(&claims.sub)?;
+    let mut conn = get_connection(pool);
+
+    match revoke_consent(&mut conn, owner_id, &client_id) {
+        Ok(0) => Err(AppError::NotFoundError(
+            "No consent for this client".to_string(),
+        )),
+        Ok(_) => Ok(HttpResponse::Ok().finish()),
+        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+}
+
+// Find all OAuth clients handler
+#[utoipa::path(
+    path = "/admin/oauth/clients",
+    responses(
+        (status = 200, description = "Successful response", body = Vec<OAuthClientResponse>),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Requires the admin role"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "findAllOAuthClients"
+)]
+#[get("/oauth/clients", wrap = "AuthenticationCheck")]
+async fn find_all_clients_handler(
+    pool: web::Data<DbPool>,
+    _claims: RequireRole<roles::Admin>,
+) -> Result<impl Responder, AppError> {
+    let mut conn = get_connection(pool);
+
+    match find_all_clients(&mut conn) {
+        Ok(clients) => Ok(HttpResponse::Ok().json(
+            clients
+                .into_iter()
+                .map(OAuthClientResponse::from)
+                .collect::<Vec<_>>(),
+        )),
+        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+}
+
+// Register an OAuth client, the secret of a confidential client is only shown once. With the
+// client_credentials grant the client gets every permission among its scopes for itself.
+#[utoipa::path(
+    path = "/admin/oauth/clients",
+    request_body = CreateOAuthClientRequest,
+    responses(
+        (status = 200, description = "Client registered, with its secret unless it is a public client", body = OAuthClientResponse),
+        (status = 400, description = "Invalid grant types, redirect URIs or scopes"),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Requires the admin role"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "createOAuthClient"
+)]
+#[post("/oauth/clients", wrap = "AuthenticationCheck")]
+async fn create_client_handler(
+    pool: web::Data<DbPool>,
+    req_body: web::Json<CreateOAuthClientRequest>,
+    _claims: RequireRole<roles::Admin>,
+) -> Result<impl Responder, AppError> {
+    let mut conn = get_connection(pool);
+    let request = req_body.into_inner();
+
+    match validate_client_request(&mut conn, &request) {
+        Ok(Ok(())) => {}
+        Ok(Err(message)) => return Err(AppError::ValidationError(message)),
+        Err(_) => return Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+    match create_client(&mut conn, request) {
+        Ok((client, client_secret)) => Ok(HttpResponse::Ok().json(OAuthClientResponse {
+            client_secret,
+            ..OAuthClientResponse::from(client)
+        })),
+        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+}
+
+// Delete an OAuth client, its tokens can no longer be refreshed
+#[utoipa::path(
+    path = "/admin/oauth/clients/{client_id}",
+    responses(
+        (status = 200, description = "Client deleted"),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Requires the admin role"),
+        (status = 404, description = "Client not found"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "deleteOAuthClient"
+)]
+#[delete("/oauth/clients/{client_id}", wrap = "AuthenticationCheck")]
+async fn delete_client_handler(
+    pool: web::Data<DbPool>,
+    client_id: web::Path<String>,
+    _claims: RequireRole<roles::Admin>,
+) -> Result<impl Responder, AppError> {
+    let mut conn = get_connection(pool);
+
+    match delete_client(&mut conn, &client_id) {
+        Ok(0) => Err(AppError::NotFoundError("Client not found".to_string())),
+        Ok(_) => Ok(HttpResponse::Ok().finish()),
+        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+}
+
+// OpenID Connect discovery document for clients of the authorization server
+#[utoipa::path(
+    path = "/.well-known/openid-configuration",
+    responses(
+        (status = 200, description = "OpenID Provider metadata", body = OpenIdConfiguration),
+        (status = 500, description = "Internal Server Error")
+    ),
+    operation_id = "getOpenIdConfiguration"
+)]
+#[get("/.well-known/openid-configuration")]
+async fn openid_configuration_handler(pool: web::Data<DbPool>) -> Result<impl Responder, AppError> {
+    let mut conn = get_connection(pool);
+    let permissions = find_all_permissions(&mut conn)
+        .map_err(|_| AppError::DatabaseError("Internal Server Error".to_string()))?;
+    let strings = |values: &[&str]| values.iter().map(|value| value.to_string()).collect();
+
+    Ok(HttpResponse::Ok().json(OpenIdConfiguration {
+        issuer: JWT_ISSUER.clone(),
+        token_endpoint: format!("{}/oauth/token", *OAUTH_BASE_URL),
+        jwks_uri: format!("{}/.well-known/jwks.json", *OAUTH_BASE_URL),
+        scopes_supported: OPENID_SCOPES
+            .iter()
+            .map(|scope| scope.to_string())
+            .chain(permissions.into_iter().map(|permission| permission.name))
+            .collect(),
+        response_types_supported: strings(&["code"]),
+        grant_types_supported: strings(&GRANT_TYPES),
+        subject_types_supported: strings(&["public"]),
+        id_token_signing_alg_values_supported: vec![format!(
+            "{:?}",
+            key_ring().signing_key().algorithm
+        )],
+        token_endpoint_auth_methods_supported: strings(&[
+            "client_secret_basic",
+            "client_secret_post",
+            "none",
+        ]),
+        code_challenge_methods_supported: strings(&["S256"]),
+        claims_supported: strings(&[
+            "sub",
+            "iss",
+            "aud",
+            "exp",
+            "iat",
+            "nonce",
+            "email",
+            "email_verified",
+            "preferred_username",
+            "zoneinfo",
+        ]),
+    }))
+}
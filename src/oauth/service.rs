@@ -0,0 +1,976 @@
+use std::env;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::{pg::PgConnection, prelude::*, result::QueryResult};
+use lazy_static::lazy_static;
+use openssl::memcmp;
+use uuid::Uuid;
+
+use crate::{
+    authentication::{
+        jwt::services::{generate_client_token, generate_id_token},
+        oidc::client::code_challenge,
+        refresh::services::{
+            issue_client_refresh_token, revoke_client_refresh_tokens, rotate_refresh_token,
+            RefreshTokenError,
+        },
+    },
+    common::crypto::{generate_opaque_token, hash_token},
+    database::model::{
+        oauth::{
+            AuthorizationCode, AuthorizationRequest, CreateAuthorizationCodeDb,
+            CreateOAuthClientDb, CreateOAuthClientRequest, CreateOAuthConsentDb, OAuthClient,
+            OAuthConsentResponse, OAuthTokenRequest, OAuthTokenResponse,
+        },
+        users::{User, UserRole},
+    },
+    permissions::service::{find_all_permissions, find_permissions_for_role},
+    schema::{oauth_authorization_codes, oauth_clients, oauth_consents},
+    users::service::find_user_by_id,
+    JWT_LIFETIME, OAUTH_CODE_LIFETIME,
+};
+
+lazy_static! {
+    // Public URL of this API, used for the endpoints in the discovery document
+    pub static ref OAUTH_BASE_URL: String = env::var("OAUTH_BASE_URL")
+        .map(|url| url.trim_end_matches('/').to_string())
+        .unwrap_or_else(|_| "http://localhost:3030".to_string());
+}
+
+pub const AUTHORIZATION_CODE: &str = "authorization_code";
+pub const CLIENT_CREDENTIALS: &str = "client_credentials";
+pub const REFRESH_TOKEN: &str = "refresh_token";
+pub const GRANT_TYPES: [&str; 3] = [AUTHORIZATION_CODE, CLIENT_CREDENTIALS, REFRESH_TOKEN];
+// Scopes for the ID token, every other scope is the name of a permission
+pub const OPENID_SCOPES: [&str; 3] = ["openid", "profile", "email"];
+
+#[derive(Debug)]
+pub enum OAuthError {
+    InvalidRequest(String),
+    InvalidClient,
+    InvalidGrant(String),
+    UnauthorizedClient,
+    UnsupportedGrantType,
+    UnsupportedResponseType,
+    InvalidScope,
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for OAuthError {
+    fn from(e: diesel::result::Error) -> Self {
+        OAuthError::Database(e)
+    }
+}
+
+impl OAuthError {
+    // Error code of the RFC 6749 error response
+    pub fn code(&self) -> &'static str {
+        match self {
+            OAuthError::InvalidRequest(_) => "invalid_request",
+            OAuthError::InvalidClient => "invalid_client",
+            OAuthError::InvalidGrant(_) => "invalid_grant",
+            OAuthError::UnauthorizedClient => "unauthorized_client",
+            OAuthError::UnsupportedGrantType => "unsupported_grant_type",
+            OAuthError::UnsupportedResponseType => "unsupported_response_type",
+            OAuthError::InvalidScope => "invalid_scope",
+            OAuthError::Database(_) => "server_error",
+        }
+    }
+
+    pub fn description(&self) -> String {
+        match self {
+            OAuthError::InvalidRequest(message) | OAuthError::InvalidGrant(message) => {
+                message.clone()
+            }
+            OAuthError::InvalidClient => "Client authentication failed".to_string(),
+            OAuthError::UnauthorizedClient => {
+                "The client is not allowed to use this grant type".to_string()
+            }
+            OAuthError::UnsupportedGrantType => "Unsupported grant type".to_string(),
+            OAuthError::UnsupportedResponseType => {
+                "Only the code response type is supported".to_string()
+            }
+            OAuthError::InvalidScope => {
+                "The requested scope is not allowed for this client".to_string()
+            }
+            OAuthError::Database(_) => "Internal Server Error".to_string(),
+        }
+    }
+}
+
+// An authorization request that names a registered client and redirect URI
+pub struct ValidatedAuthorization {
+    pub client: OAuthClient,
+    pub redirect_uri: String,
+    pub scopes: Vec<String>,
+    pub state: Option<String>,
+    pub code_challenge: String,
+    pub nonce: Option<String>,
+}
+
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+// Split a space separated scope, dropping duplicates
+pub fn parse_scope(scope: &str) -> Vec<String> {
+    let mut scopes: Vec<String> = vec![];
+    for value in scope.split_whitespace() {
+        if !scopes.iter().any(|existing| existing == value) {
+            scopes.push(value.to_string());
+        }
+    }
+    scopes
+}
+
+fn contains(list: &str, value: &str) -> bool {
+    list.split_whitespace().any(|item| item == value)
+}
+
+// The requested scopes, or every scope of the client when none were requested
+fn requested_scopes(client: &OAuthClient, scope: Option<&str>) -> Result<Vec<String>, OAuthError> {
+    let scopes = match scope {
+        Some(scope) => parse_scope(scope),
+        None => parse_scope(&client.scopes),
+    };
+    if scopes.is_empty() || !scopes.iter().all(|scope| contains(&client.scopes, scope)) {
+        return Err(OAuthError::InvalidScope);
+    }
+    Ok(scopes)
+}
+
+// Append query parameters to a redirect URI
+pub fn redirect_with(redirect_uri: &str, params: &[(&str, &str)]) -> String {
+    let separator = if redirect_uri.contains('?') { '&' } else { '?' };
+    let query = serde_urlencoded::to_string(params).unwrap_or_default();
+    format!("{}{}{}", redirect_uri, separator, query)
+}
+
+// Client id and secret from an `Authorization: Basic` header
+pub fn parse_basic_credentials(header: &str) -> Option<(String, String)> {
+    let encoded = header.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (client_id, client_secret) = decoded.split_once(':')?;
+    Some((client_id.to_string(), client_secret.to_string()))
+}
+
+pub fn validate_client_request(
+    conn: &mut PgConnection,
+    request: &CreateOAuthClientRequest,
+) -> QueryResult<Result<(), String>> {
+    if request.name.trim().is_empty() {
+        return Ok(Err("The client needs a name".to_string()));
+    }
+    if request.grant_types.is_empty() {
+        return Ok(Err("The client needs at least one grant type".to_string()));
+    }
+    if let Some(grant_type) = request
+        .grant_types
+        .iter()
+        .find(|grant_type| !GRANT_TYPES.contains(&grant_type.as_str()))
+    {
+        return Ok(Err(format!("Unsupported grant type {}", grant_type)));
+    }
+    let has_grant = |grant_type: &str| request.grant_types.iter().any(|g| g == grant_type);
+    if has_grant(CLIENT_CREDENTIALS) && request.public {
+        return Ok(Err(
+            "Public clients can't use the client credentials grant".to_string()
+        ));
+    }
+    if has_grant(REFRESH_TOKEN) && !has_grant(AUTHORIZATION_CODE) {
+        return Ok(Err(
+            "The refresh token grant requires the authorization code grant".to_string(),
+        ));
+    }
+    if has_grant(AUTHORIZATION_CODE) && request.redirect_uris.is_empty() {
+        return Ok(Err(
+            "The authorization code grant requires a redirect URI".to_string()
+        ));
+    }
+    // Redirect URIs are compared as is, they need a scheme and can't carry a fragment
+    if let Some(uri) = request
+        .redirect_uris
+        .iter()
+        .find(|uri| !uri.contains(':') || uri.contains('#') || uri.chars().any(char::is_whitespace))
+    {
+        return Ok(Err(format!("Invalid redirect URI {}", uri)));
+    }
+
+    if request.scopes.is_empty() {
+        return Ok(Err("The client needs at least one scope".to_string()));
+    }
+    let permissions = find_all_permissions(conn)?;
+    if let Some(scope) = request.scopes.iter().find(|scope| {
+        !OPENID_SCOPES.contains(&scope.as_str())
+            && !permissions
+                .iter()
+                .any(|permission| &permission.name == *scope)
+    }) {
+        return Ok(Err(format!("Unknown scope {}", scope)));
+    }
+    Ok(Ok(()))
+}
+
+// Register a client, the plain secret of a confidential client is only returned here
+pub fn create_client(
+    conn: &mut PgConnection,
+    request: CreateOAuthClientRequest,
+) -> QueryResult<(OAuthClient, Option<String>)> {
+    let client_secret = (!request.public).then(generate_opaque_token);
+    let client = diesel::insert_into(oauth_clients::table)
+        .values(CreateOAuthClientDb {
+            client_id: Uuid::new_v4().simple().to_string(),
+            client_secret_hash: client_secret.as_deref().map(hash_token),
+            name: request.name,
+            redirect_uris: request.redirect_uris.join(" "),
+            scopes: parse_scope(&request.scopes.join(" ")).join(" "),
+            grant_types: parse_scope(&request.grant_types.join(" ")).join(" "),
+            first_party: request.first_party,
+        })
+        .returning(OAuthClient::as_returning())
+        .get_result(conn)?;
+
+    Ok((client, client_secret))
+}
+
+pub fn find_all_clients(conn: &mut PgConnection) -> QueryResult<Vec<OAuthClient>> {
+    oauth_clients::table
+        .order(oauth_clients::created_at.asc())
+        .select(OAuthClient::as_select())
+        .load(conn)
+}
+
+pub fn find_client(conn: &mut PgConnection, client_id: &str) -> QueryResult<Option<OAuthClient>> {
+    oauth_clients::table
+        .filter(oauth_clients::client_id.eq(client_id))
+        .select(OAuthClient::as_select())
+        .first(conn)
+        .optional()
+}
+
+// Also removes its codes, consents and refresh tokens
+pub fn delete_client(conn: &mut PgConnection, client_id: &str) -> QueryResult<usize> {
+    diesel::delete(oauth_clients::table.filter(oauth_clients::client_id.eq(client_id)))
+        .execute(conn)
+}
+
+// Confidential clients must present their secret, public clients must not have one
+pub fn authenticate_client(
+    conn: &mut PgConnection,
+    client_id: &str,
+    client_secret: Option<&str>,
+) -> Result<OAuthClient, OAuthError> {
+    let client = find_client(conn, client_id)?.ok_or(OAuthError::InvalidClient)?;
+    let authenticated = match (&client.client_secret_hash, client_secret) {
+        (Some(stored), Some(secret)) => {
+            let presented = hash_token(secret);
+            stored.len() == presented.len() && memcmp::eq(stored.as_bytes(), presented.as_bytes())
+        }
+        (None, None) => true,
+        _ => false,
+    };
+    if !authenticated {
+        return Err(OAuthError::InvalidClient);
+    }
+    Ok(client)
+}
+
+pub fn validate_authorization_request(
+    conn: &mut PgConnection,
+    request: AuthorizationRequest,
+) -> Result<ValidatedAuthorization, OAuthError> {
+    let client = find_client(conn, &request.client_id)?
+        .ok_or_else(|| OAuthError::InvalidRequest("Unknown client".to_string()))?;
+
+    let registered: Vec<&str> = client.redirect_uris.split_whitespace().collect();
+    let redirect_uri = match (request.redirect_uri, registered.as_slice()) {
+        (Some(uri), _) if registered.contains(&uri.as_str()) => uri,
+        (None, [only]) => only.to_string(),
+        _ => {
+            return Err(OAuthError::InvalidRequest(
+                "The redirect URI is not registered for this client".to_string(),
+            ))
+        }
+    };
+
+    if request.response_type != "code" {
+        return Err(OAuthError::UnsupportedResponseType);
+    }
+    if !contains(&client.grant_types, AUTHORIZATION_CODE) {
+        return Err(OAuthError::UnauthorizedClient);
+    }
+    let code_challenge = match (
+        request.code_challenge,
+        request.code_challenge_method.as_deref(),
+    ) {
+        (Some(challenge), Some("S256")) if !challenge.is_empty() => challenge,
+        _ => {
+            return Err(OAuthError::InvalidRequest(
+                "A code challenge with the S256 method is required".to_string(),
+            ))
+        }
+    };
+    let scopes = requested_scopes(&client, request.scope.as_deref())?;
+
+    Ok(ValidatedAuthorization {
+        client,
+        redirect_uri,
+        scopes,
+        state: request.state,
+        code_challenge,
+        nonce: request.nonce,
+    })
+}
+
+// First party clients are trusted, others need the user's consent for every scope
+pub fn has_consent(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    client: &OAuthClient,
+    scopes: &[String],
+) -> QueryResult<bool> {
+    if client.first_party {
+        return Ok(true);
+    }
+    let consented = oauth_consents::table
+        .filter(oauth_consents::user_id.eq(owner_id))
+        .filter(oauth_consents::client_id.eq(client.id))
+        .select(oauth_consents::scopes)
+        .first::<String>(conn)
+        .optional()?;
+
+    Ok(consented.is_some_and(|consented| scopes.iter().all(|scope| contains(&consented, scope))))
+}
+
+// Record the consent, adding to the scopes the user already allowed
+pub fn grant_consent(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    client: &OAuthClient,
+    scopes: &[String],
+) -> QueryResult<()> {
+    conn.transaction(|conn| {
+        let consented = oauth_consents::table
+            .filter(oauth_consents::user_id.eq(owner_id))
+            .filter(oauth_consents::client_id.eq(client.id))
+            .select(oauth_consents::scopes)
+            .for_update()
+            .first::<String>(conn)
+            .optional()?
+            .unwrap_or_default();
+        let merged = parse_scope(&format!("{} {}", consented, scopes.join(" "))).join(" ");
+
+        diesel::insert_into(oauth_consents::table)
+            .values(CreateOAuthConsentDb {
+                user_id: owner_id,
+                client_id: client.id,
+                scopes: merged.clone(),
+            })
+            .on_conflict((oauth_consents::user_id, oauth_consents::client_id))
+            .do_update()
+            .set((
+                oauth_consents::scopes.eq(merged),
+                oauth_consents::updated_at.eq(now()),
+            ))
+            .execute(conn)?;
+        Ok(())
+    })
+}
+
+pub fn find_consents(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+) -> QueryResult<Vec<OAuthConsentResponse>> {
+    let consents = oauth_consents::table
+        .inner_join(oauth_clients::table)
+        .filter(oauth_consents::user_id.eq(owner_id))
+        .order(oauth_consents::updated_at.desc())
+        .select((
+            oauth_clients::client_id,
+            oauth_clients::name,
+            oauth_consents::scopes,
+            oauth_consents::updated_at,
+        ))
+        .load::<(String, String, String, NaiveDateTime)>(conn)?;
+
+    Ok(consents
+        .into_iter()
+        .map(
+            |(client_id, client_name, scopes, updated_at)| OAuthConsentResponse {
+                client_id,
+                client_name,
+                scopes: parse_scope(&scopes),
+                updated_at,
+            },
+        )
+        .collect())
+}
+
+// Withdraw a consent along with the refresh tokens the client holds for the user. Access
+// tokens already issued stay valid until they expire.
+pub fn revoke_consent(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    client_id: &str,
+) -> QueryResult<usize> {
+    let client = match find_client(conn, client_id)? {
+        Some(client) => client,
+        None => return Ok(0),
+    };
+    conn.transaction(|conn| {
+        revoke_client_refresh_tokens(conn, owner_id, client.id)?;
+        diesel::delete(
+            oauth_consents::table
+                .filter(oauth_consents::user_id.eq(owner_id))
+                .filter(oauth_consents::client_id.eq(client.id)),
+        )
+        .execute(conn)
+    })
+}
+
+// Store a single use code for the authorization and return the plain code
+pub fn issue_authorization_code(
+    conn: &mut PgConnection,
+    owner_id: Uuid,
+    authorization: &ValidatedAuthorization,
+) -> QueryResult<String> {
+    diesel::delete(
+        oauth_authorization_codes::table.filter(oauth_authorization_codes::expires_at.lt(now())),
+    )
+    .execute(conn)?;
+
+    let code = generate_opaque_token();
+    diesel::insert_into(oauth_authorization_codes::table)
+        .values(CreateAuthorizationCodeDb {
+            code_hash: hash_token(&code),
+            client_id: authorization.client.id,
+            user_id: owner_id,
+            redirect_uri: authorization.redirect_uri.clone(),
+            scope: authorization.scopes.join(" "),
+            code_challenge: authorization.code_challenge.clone(),
+            nonce: authorization.nonce.clone(),
+            expires_at: now() + Duration::seconds(OAUTH_CODE_LIFETIME as i64),
+        })
+        .execute(conn)?;
+
+    Ok(code)
+}
+
+// Permissions carried by an access token for the scopes, limited to those of the role
+fn permissions_for_scopes(
+    conn: &mut PgConnection,
+    role: UserRole,
+    scopes: &[String],
+) -> QueryResult<Vec<String>> {
+    Ok(find_permissions_for_role(conn, role)?
+        .into_iter()
+        .filter(|permission| scopes.contains(permission))
+        .collect())
+}
+
+fn user_token_response(
+    conn: &mut PgConnection,
+    client: &OAuthClient,
+    user: &User,
+    scopes: &[String],
+    refresh_token: Option<String>,
+    id_token: Option<String>,
+) -> QueryResult<OAuthTokenResponse> {
+    let scope = scopes.join(" ");
+    let permissions = permissions_for_scopes(conn, user.role, scopes)?;
+
+    Ok(OAuthTokenResponse {
+        access_token: generate_client_token(
+            &user.id.to_string(),
+            user.role,
+            permissions,
+            &client.client_id,
+            &scope,
+        ),
+        token_type: "Bearer".to_string(),
+        expires_in: JWT_LIFETIME,
+        refresh_token,
+        scope,
+        id_token,
+    })
+}
+
+fn required<'a>(value: &'a Option<String>, name: &str) -> Result<&'a str, OAuthError> {
+    value
+        .as_deref()
+        .ok_or_else(|| OAuthError::InvalidRequest(format!("Missing {}", name)))
+}
+
+// Exchange a code for tokens. Consuming the code and checking it are separate steps, so a
+// code presented with a wrong verifier or redirect URI can't be tried again.
+pub fn authorization_code_grant(
+    conn: &mut PgConnection,
+    client: &OAuthClient,
+    request: &OAuthTokenRequest,
+) -> Result<OAuthTokenResponse, OAuthError> {
+    let code = required(&request.code, "code")?;
+    let code_verifier = required(&request.code_verifier, "code_verifier")?;
+    let invalid_code = || OAuthError::InvalidGrant("Invalid authorization code".to_string());
+
+    let stored = diesel::update(
+        oauth_authorization_codes::table
+            .filter(oauth_authorization_codes::code_hash.eq(hash_token(code)))
+            .filter(oauth_authorization_codes::used_at.is_null()),
+    )
+    .set(oauth_authorization_codes::used_at.eq(now()))
+    .returning(AuthorizationCode::as_returning())
+    .get_result(conn)
+    .optional()?
+    .ok_or_else(invalid_code)?;
+
+    if stored.client_id != client.id
+        || stored.expires_at < now()
+        || request.redirect_uri.as_deref() != Some(stored.redirect_uri.as_str())
+    {
+        return Err(invalid_code());
+    }
+    let challenge = code_challenge(code_verifier);
+    if challenge.len() != stored.code_challenge.len()
+        || !memcmp::eq(challenge.as_bytes(), stored.code_challenge.as_bytes())
+    {
+        return Err(OAuthError::InvalidGrant(
+            "The code verifier doesn't match the code challenge".to_string(),
+        ));
+    }
+
+    let user = find_user_by_id(conn, stored.user_id)?.ok_or_else(invalid_code)?;
+    let scopes = parse_scope(&stored.scope);
+    let refresh_token = if contains(&client.grant_types, REFRESH_TOKEN) {
+        Some(issue_client_refresh_token(conn, user.id, client.id, &stored.scope)?.1)
+    } else {
+        None
+    };
+    let id_token = scopes
+        .iter()
+        .any(|scope| scope == "openid")
+        .then(|| generate_id_token(&user, &client.client_id, stored.nonce, &scopes));
+
+    Ok(user_token_response(
+        conn,
+        client,
+        &user,
+        &scopes,
+        refresh_token,
+        id_token,
+    )?)
+}
+
+// Rotate a refresh token issued to the client. A narrower scope can be requested for the
+// new access token, the refresh token keeps the original one.
+pub fn refresh_token_grant(
+    conn: &mut PgConnection,
+    client: &OAuthClient,
+    request: &OAuthTokenRequest,
+) -> Result<OAuthTokenResponse, OAuthError> {
+    let presented = required(&request.refresh_token, "refresh_token")?;
+
+    let (refresh_token, plain_refresh_token) =
+        match rotate_refresh_token(conn, presented, Some(client.id)) {
+            Ok(rotated) => rotated,
+            Err(RefreshTokenError::Database(e)) => return Err(OAuthError::Database(e)),
+            Err(_) => {
+                return Err(OAuthError::InvalidGrant(
+                    "Invalid refresh token".to_string(),
+                ))
+            }
+        };
+    let granted = refresh_token.scope.unwrap_or_default();
+    let scopes = match &request.scope {
+        Some(scope) => parse_scope(scope),
+        None => parse_scope(&granted),
+    };
+    if !scopes.iter().all(|scope| contains(&granted, scope)) {
+        return Err(OAuthError::InvalidScope);
+    }
+
+    let user = find_user_by_id(conn, refresh_token.user_id)?
+        .ok_or_else(|| OAuthError::InvalidGrant("Invalid refresh token".to_string()))?;
+
+    Ok(user_token_response(
+        conn,
+        client,
+        &user,
+        &scopes,
+        Some(plain_refresh_token),
+        None,
+    )?)
+}
+
+// Token for the client itself, with the permissions among the requested scopes. It acts
+// as a guest, since no user is behind it, but unlike user tokens its permissions aren't
+// limited by that role: guests have none. The scopes are trusted as registered, which
+// only admins can do and only with existing permissions, so a client registered with
+// users:delete can delete any user. Role checks still reject the token.
+pub fn client_credentials_grant(
+    client: &OAuthClient,
+    request: &OAuthTokenRequest,
+) -> Result<OAuthTokenResponse, OAuthError> {
+    if client.client_secret_hash.is_none() {
+        return Err(OAuthError::UnauthorizedClient);
+    }
+    let scopes = requested_scopes(client, request.scope.as_deref())?;
+    if scopes
+        .iter()
+        .any(|scope| OPENID_SCOPES.contains(&scope.as_str()))
+    {
+        return Err(OAuthError::InvalidScope);
+    }
+    let scope = scopes.join(" ");
+
+    Ok(OAuthTokenResponse {
+        access_token: generate_client_token(
+            &client.client_id,
+            UserRole::Guest,
+            scopes,
+            &client.client_id,
+            &scope,
+        ),
+        token_type: "Bearer".to_string(),
+        expires_in: JWT_LIFETIME,
+        refresh_token: None,
+        scope,
+        id_token: None,
+    })
+}
+
+pub fn supports_grant(client: &OAuthClient, grant_type: &str) -> bool {
+    contains(&client.grant_types, grant_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        authorization_code_grant, client_credentials_grant, create_client, delete_client,
+        grant_consent, has_consent, issue_authorization_code, parse_basic_credentials, parse_scope,
+        redirect_with, refresh_token_grant, revoke_consent, validate_authorization_request,
+        OAuthError, ValidatedAuthorization,
+    };
+    use crate::{
+        authentication::{
+            jwt::services::decode_claims, oidc::client::code_challenge,
+            service::authenticate_permission,
+        },
+        database::{
+            model::{
+                db::DbPool,
+                oauth::{
+                    AuthorizationRequest, CreateOAuthClientRequest, OAuthClient, OAuthTokenRequest,
+                    OAuthTokenResponse,
+                },
+                users::{User, UserRole},
+            },
+            tools::testing::{create_test_user, test_pool},
+        },
+        users::service::delete_user,
+    };
+
+    const REDIRECT_URI: &str = "https://app.example.com/cb";
+    const VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+
+    fn create_test_client(pool: &DbPool, redirect_uris: &[&str], first_party: bool) -> OAuthClient {
+        create_client(
+            &mut pool.get().unwrap(),
+            CreateOAuthClientRequest {
+                name: "Test client".to_string(),
+                redirect_uris: redirect_uris.iter().map(|uri| uri.to_string()).collect(),
+                scopes: vec!["openid".to_string(), "users:read".to_string()],
+                grant_types: vec![
+                    "authorization_code".to_string(),
+                    "refresh_token".to_string(),
+                ],
+                public: false,
+                first_party,
+            },
+        )
+        .expect("Failed to create test client.")
+        .0
+    }
+
+    fn authorization_request(
+        client: &OAuthClient,
+        redirect_uri: Option<&str>,
+    ) -> AuthorizationRequest {
+        AuthorizationRequest {
+            response_type: "code".to_string(),
+            client_id: client.client_id.clone(),
+            redirect_uri: redirect_uri.map(str::to_string),
+            scope: Some("openid users:read".to_string()),
+            state: None,
+            code_challenge: Some(code_challenge(VERIFIER)),
+            code_challenge_method: Some("S256".to_string()),
+            nonce: None,
+        }
+    }
+
+    fn authorize(pool: &DbPool, client: &OAuthClient) -> ValidatedAuthorization {
+        validate_authorization_request(
+            &mut pool.get().unwrap(),
+            authorization_request(client, Some(REDIRECT_URI)),
+        )
+        .expect("The authorization request should be valid")
+    }
+
+    fn exchange(
+        pool: &DbPool,
+        client: &OAuthClient,
+        code: &str,
+        redirect_uri: &str,
+        verifier: &str,
+    ) -> Result<OAuthTokenResponse, OAuthError> {
+        authorization_code_grant(
+            &mut pool.get().unwrap(),
+            client,
+            &OAuthTokenRequest {
+                grant_type: "authorization_code".to_string(),
+                code: Some(code.to_string()),
+                redirect_uri: Some(redirect_uri.to_string()),
+                code_verifier: Some(verifier.to_string()),
+                refresh_token: None,
+                scope: None,
+                client_id: None,
+                client_secret: None,
+            },
+        )
+    }
+
+    fn refresh(
+        pool: &DbPool,
+        client: &OAuthClient,
+        refresh_token: &str,
+    ) -> Result<OAuthTokenResponse, OAuthError> {
+        refresh_token_grant(
+            &mut pool.get().unwrap(),
+            client,
+            &OAuthTokenRequest {
+                grant_type: "refresh_token".to_string(),
+                code: None,
+                redirect_uri: None,
+                code_verifier: None,
+                refresh_token: Some(refresh_token.to_string()),
+                scope: None,
+                client_id: None,
+                client_secret: None,
+            },
+        )
+    }
+
+    fn new_code(pool: &DbPool, user: &User, client: &OAuthClient) -> String {
+        issue_authorization_code(&mut pool.get().unwrap(), user.id, &authorize(pool, client))
+            .expect("Failed to issue a code.")
+    }
+
+    fn clean_up(pool: &DbPool, user: &User, clients: &[&OAuthClient]) {
+        let mut conn = pool.get().unwrap();
+        for client in clients {
+            delete_client(&mut conn, &client.client_id).unwrap();
+        }
+        delete_user(&mut conn, user.id).unwrap();
+    }
+
+    #[test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    fn codes_need_the_verifier_and_are_single_use() {
+        let pool = test_pool();
+        let user = create_test_user(&pool, UserRole::User);
+        let client = create_test_client(&pool, &[REDIRECT_URI], false);
+
+        let code = new_code(&pool, &user, &client);
+        let wrong_verifier = exchange(&pool, &client, &code, REDIRECT_URI, "not the verifier");
+        // The failed attempt used up the code
+        let retried = exchange(&pool, &client, &code, REDIRECT_URI, VERIFIER);
+
+        let code = new_code(&pool, &user, &client);
+        let exchanged = exchange(&pool, &client, &code, REDIRECT_URI, VERIFIER);
+        let replayed = exchange(&pool, &client, &code, REDIRECT_URI, VERIFIER);
+        clean_up(&pool, &user, &[&client]);
+
+        assert!(matches!(wrong_verifier, Err(OAuthError::InvalidGrant(_))));
+        assert!(matches!(retried, Err(OAuthError::InvalidGrant(_))));
+        let tokens = exchanged.expect("The code should be exchanged");
+        assert!(tokens.refresh_token.is_some());
+        assert!(tokens.id_token.is_some());
+        assert!(matches!(replayed, Err(OAuthError::InvalidGrant(_))));
+    }
+
+    #[test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    fn redirect_uri_must_match_exactly() {
+        let pool = test_pool();
+        let user = create_test_user(&pool, UserRole::User);
+        let other_uri = "https://app.example.com/other";
+        let client = create_test_client(&pool, &[REDIRECT_URI, other_uri], false);
+        let mut conn = pool.get().unwrap();
+
+        let unregistered = validate_authorization_request(
+            &mut conn,
+            authorization_request(&client, Some("https://app.example.com/cb/../evil")),
+        );
+        // Only a client with a single registered URI may leave it out
+        let missing =
+            validate_authorization_request(&mut conn, authorization_request(&client, None));
+        drop(conn);
+        let code = new_code(&pool, &user, &client);
+        let other_redirect = exchange(&pool, &client, &code, other_uri, VERIFIER);
+        clean_up(&pool, &user, &[&client]);
+
+        assert!(matches!(unregistered, Err(OAuthError::InvalidRequest(_))));
+        assert!(matches!(missing, Err(OAuthError::InvalidRequest(_))));
+        assert!(matches!(other_redirect, Err(OAuthError::InvalidGrant(_))));
+    }
+
+    #[test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    fn consent_covers_the_granted_scopes() {
+        let pool = test_pool();
+        let user = create_test_user(&pool, UserRole::User);
+        let client = create_test_client(&pool, &[REDIRECT_URI], false);
+        let first_party = create_test_client(&pool, &[REDIRECT_URI], true);
+        let mut conn = pool.get().unwrap();
+        let scopes = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let before = has_consent(&mut conn, user.id, &client, &scopes(&["users:read"])).unwrap();
+        grant_consent(&mut conn, user.id, &client, &scopes(&["users:read"])).unwrap();
+        let granted = has_consent(&mut conn, user.id, &client, &scopes(&["users:read"])).unwrap();
+        let wider = has_consent(
+            &mut conn,
+            user.id,
+            &client,
+            &scopes(&["openid", "users:read"]),
+        )
+        .unwrap();
+        grant_consent(&mut conn, user.id, &client, &scopes(&["openid"])).unwrap();
+        let merged = has_consent(
+            &mut conn,
+            user.id,
+            &client,
+            &scopes(&["openid", "users:read"]),
+        )
+        .unwrap();
+        let revoked = revoke_consent(&mut conn, user.id, &client.client_id).unwrap();
+        let after = has_consent(&mut conn, user.id, &client, &scopes(&["users:read"])).unwrap();
+        let trusted =
+            has_consent(&mut conn, user.id, &first_party, &scopes(&["users:read"])).unwrap();
+        drop(conn);
+        clean_up(&pool, &user, &[&client, &first_party]);
+
+        assert!(!before);
+        assert!(granted);
+        assert!(!wider);
+        assert!(merged);
+        assert_eq!(revoked, 1);
+        assert!(!after);
+        assert!(trusted);
+    }
+
+    #[test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    fn refresh_tokens_are_bound_to_their_client() {
+        let pool = test_pool();
+        let user = create_test_user(&pool, UserRole::User);
+        let client = create_test_client(&pool, &[REDIRECT_URI], false);
+        let other = create_test_client(&pool, &[REDIRECT_URI], false);
+
+        let code = new_code(&pool, &user, &client);
+        let refresh_token = exchange(&pool, &client, &code, REDIRECT_URI, VERIFIER)
+            .unwrap()
+            .refresh_token
+            .unwrap();
+        let by_other_client = refresh(&pool, &other, &refresh_token);
+        let by_own_client = refresh(&pool, &client, &refresh_token);
+        clean_up(&pool, &user, &[&client, &other]);
+
+        assert!(matches!(by_other_client, Err(OAuthError::InvalidGrant(_))));
+        assert!(by_own_client
+            .expect("The client should refresh its token")
+            .refresh_token
+            .is_some());
+    }
+
+    #[test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    fn client_tokens_carry_the_registered_scopes() {
+        let pool = test_pool();
+        let (client, _) = create_client(
+            &mut pool.get().unwrap(),
+            CreateOAuthClientRequest {
+                name: "Test service".to_string(),
+                redirect_uris: vec![],
+                scopes: vec!["users:read".to_string(), "users:delete".to_string()],
+                grant_types: vec!["client_credentials".to_string()],
+                public: false,
+                first_party: false,
+            },
+        )
+        .expect("Failed to create test client.");
+        let request = |scope: Option<&str>| OAuthTokenRequest {
+            grant_type: "client_credentials".to_string(),
+            code: None,
+            redirect_uri: None,
+            code_verifier: None,
+            refresh_token: None,
+            scope: scope.map(str::to_string),
+            client_id: None,
+            client_secret: None,
+        };
+
+        let all = client_credentials_grant(&client, &request(None)).unwrap();
+        let read = client_credentials_grant(&client, &request(Some("users:read"))).unwrap();
+        let unregistered = client_credentials_grant(&client, &request(Some("users:write")));
+        delete_client(&mut pool.get().unwrap(), &client.client_id).unwrap();
+
+        let all = decode_claims(&all.access_token).unwrap();
+        assert_eq!(all.role, UserRole::Guest);
+        assert_eq!(all.client_id.as_deref(), Some(client.client_id.as_str()));
+        assert!(authenticate_permission(&all, "users:delete").is_ok());
+        let read = decode_claims(&read.access_token).unwrap();
+        assert_eq!(read.permissions, vec!["users:read".to_string()]);
+        assert!(authenticate_permission(&read, "users:delete").is_err());
+        assert!(matches!(unregistered, Err(OAuthError::InvalidScope)));
+    }
+
+    #[test]
+    fn parses_scope_without_duplicates() {
+        assert_eq!(
+            parse_scope(" openid  users:read openid email "),
+            vec!["openid", "users:read", "email"]
+        );
+        assert!(parse_scope("").is_empty());
+    }
+
+    #[test]
+    fn appends_parameters_to_redirect_uri() {
+        assert_eq!(
+            redirect_with(
+                "https://app.example.com/cb",
+                &[("code", "abc"), ("state", "x y")]
+            ),
+            "https://app.example.com/cb?code=abc&state=x+y"
+        );
+        assert_eq!(
+            redirect_with("https://app.example.com/cb?tenant=1", &[("code", "abc")]),
+            "https://app.example.com/cb?tenant=1&code=abc"
+        );
+    }
+
+    #[test]
+    fn parses_basic_credentials() {
+        // client:s3cr:et, the secret may contain colons
+        assert_eq!(
+            parse_basic_credentials("Basic Y2xpZW50OnMzY3I6ZXQ="),
+            Some(("client".to_string(), "s3cr:et".to_string()))
+        );
+        assert_eq!(parse_basic_credentials("Bearer Y2xpZW50OnNlY3JldA=="), None);
+        assert_eq!(parse_basic_credentials("Basic not base64"), None);
+    }
+}
@@ -0,0 +1,233 @@
+use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+use crate::authentication::guard::{roles, RequireRole};
+use crate::authentication::middleware::AuthenticationCheck;
+use crate::common::model::AppError;
+use crate::database::model::permissions::{CreatePermissionRequest, UpdatePermissionRequest};
+use crate::database::model::users::UserRole;
+use crate::database::{model::db::DbPool, tools::get_connection};
+use crate::permissions::service::{
+    create_permission, delete_permission, find_all_permissions, find_all_role_permissions,
+    grant_permission, revoke_permission, update_permission,
+};
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(find_all_permissions_handler);
+    cfg.service(create_permission_handler);
+    cfg.service(update_permission_handler);
+    cfg.service(delete_permission_handler);
+
+    cfg.service(find_all_role_permissions_handler);
+    cfg.service(grant_permission_handler);
+    cfg.service(revoke_permission_handler);
+}
+
+// Find all permissions handler
+#[utoipa::path(
+    path = "/admin/permissions",
+    responses(
+        (status = 200, description = "Successful response", body = Vec<Permission>),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Requires the admin role"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "findAllPermissions"
+)]
+#[get("/permissions", wrap = "AuthenticationCheck")]
+async fn find_all_permissions_handler(
+    pool: web::Data<DbPool>,
+    _claims: RequireRole<roles::Admin>,
+) -> Result<impl Responder, AppError> {
+    let mut conn = get_connection(pool);
+
+    match find_all_permissions(&mut conn) {
+        Ok(permissions) => Ok(HttpResponse::Ok().json(permissions)),
+        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+}
+
+// Create permission handler
+#[utoipa::path(
+    path = "/admin/permissions",
+    request_body = CreatePermissionRequest,
+    responses(
+        (status = 200, description = "Permission created successfully", body = Permission),
+        (status = 400, description = "Permission already exists"),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Requires the admin role"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "createPermission"
+)]
+#[post("/permissions", wrap = "AuthenticationCheck")]
+async fn create_permission_handler(
+    pool: web::Data<DbPool>,
+    req_body: web::Json<CreatePermissionRequest>,
+    _claims: RequireRole<roles::Admin>,
+) -> Result<impl Responder, AppError> {
+    let mut conn = get_connection(pool);
+
+    match create_permission(&mut conn, req_body.into_inner()) {
+        Ok(permission) => Ok(HttpResponse::Ok().json(permission)),
+        Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Err(
+            AppError::ValidationError("Permission already exists".to_string()),
+        ),
+        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+}
+
+// Update permission handler
+#[utoipa::path(
+    path = "/admin/permissions/{name}",
+    request_body = UpdatePermissionRequest,
+    responses(
+        (status = 200, description = "Permission updated successfully", body = Permission),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Requires the admin role"),
+        (status = 404, description = "Permission not found"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "updatePermission"
+)]
+#[put("/permissions/{name}", wrap = "AuthenticationCheck")]
+async fn update_permission_handler(
+    pool: web::Data<DbPool>,
+    name: web::Path<String>,
+    req_body: web::Json<UpdatePermissionRequest>,
+    _claims: RequireRole<roles::Admin>,
+) -> Result<impl Responder, AppError> {
+    let mut conn = get_connection(pool);
+
+    match update_permission(&mut conn, &name, req_body.into_inner()) {
+        Ok(Some(permission)) => Ok(HttpResponse::Ok().json(permission)),
+        Ok(None) => Err(AppError::NotFoundError("Permission not found".to_string())),
+        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+}
+
+// Delete permission handler, also removes it from every role
+#[utoipa::path(
+    path = "/admin/permissions/{name}",
+    responses(
+        (status = 200, description = "Permission deleted successfully"),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Requires the admin role"),
+        (status = 404, description = "Permission not found"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "deletePermission"
+)]
+#[delete("/permissions/{name}", wrap = "AuthenticationCheck")]
+async fn delete_permission_handler(
+    pool: web::Data<DbPool>,
+    name: web::Path<String>,
+    _claims: RequireRole<roles::Admin>,
+) -> Result<impl Responder, AppError> {
+    let mut conn = get_connection(pool);
+
+    match delete_permission(&mut conn, &name) {
+        Ok(0) => Err(AppError::NotFoundError("Permission not found".to_string())),
+        Ok(_) => Ok(HttpResponse::Ok().finish()),
+        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+}
+
+// Find the permissions granted to each role
+#[utoipa::path(
+    path = "/admin/roles",
+    responses(
+        (status = 200, description = "Successful response", body = Vec<RolePermissionsResponse>),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Requires the admin role"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "findAllRolePermissions"
+)]
+#[get("/roles", wrap = "AuthenticationCheck")]
+async fn find_all_role_permissions_handler(
+    pool: web::Data<DbPool>,
+    _claims: RequireRole<roles::Admin>,
+) -> Result<impl Responder, AppError> {
+    let mut conn = get_connection(pool);
+
+    match find_all_role_permissions(&mut conn) {
+        Ok(role_permissions) => Ok(HttpResponse::Ok().json(role_permissions)),
+        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+}
+
+// Grant a permission to a role, tokens issued from now on carry it
+#[utoipa::path(
+    path = "/admin/roles/{role}/permissions/{name}",
+    params(
+        ("role" = UserRole, Path, description = "Role name"),
+        ("name" = String, Path, description = "Permission name")
+    ),
+    responses(
+        (status = 200, description = "Permission granted"),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Requires the admin role"),
+        (status = 404, description = "Permission not found"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "grantPermission"
+)]
+#[put("/roles/{role}/permissions/{name}", wrap = "AuthenticationCheck")]
+async fn grant_permission_handler(
+    pool: web::Data<DbPool>,
+    path: web::Path<(UserRole, String)>,
+    _claims: RequireRole<roles::Admin>,
+) -> Result<impl Responder, AppError> {
+    let (role, name) = path.into_inner();
+    let mut conn = get_connection(pool);
+
+    match grant_permission(&mut conn, role, &name) {
+        Ok(_) => Ok(HttpResponse::Ok().finish()),
+        Err(DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => {
+            Err(AppError::NotFoundError("Permission not found".to_string()))
+        }
+        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+}
+
+// Revoke a permission from a role, tokens already issued keep it until they expire
+#[utoipa::path(
+    path = "/admin/roles/{role}/permissions/{name}",
+    params(
+        ("role" = UserRole, Path, description = "Role name"),
+        ("name" = String, Path, description = "Permission name")
+    ),
+    responses(
+        (status = 200, description = "Permission revoked"),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Requires the admin role"),
+        (status = 404, description = "The role doesn't have the permission"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "revokePermission"
+)]
+#[delete("/roles/{role}/permissions/{name}", wrap = "AuthenticationCheck")]
+async fn revoke_permission_handler(
+    pool: web::Data<DbPool>,
+    path: web::Path<(UserRole, String)>,
+    _claims: RequireRole<roles::Admin>,
+) -> Result<impl Responder, AppError> {
+    let (role, name) = path.into_inner();
+    let mut conn = get_connection(pool);
+
+    match revoke_permission(&mut conn, role, &name) {
+        Ok(0) => Err(AppError::NotFoundError(
+            "The role doesn't have the permission".to_string(),
+        )),
+        Ok(_) => Ok(HttpResponse::Ok().finish()),
+        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    }
+}
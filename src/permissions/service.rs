@@ -0,0 +1,91 @@
+use diesel::{pg::PgConnection, prelude::*, result::QueryResult};
+
+use crate::database::model::{
+    permissions::{
+        CreatePermissionRequest, CreateRolePermissionDb, Permission, RolePermissionsResponse,
+        UpdatePermissionRequest,
+    },
+    users::UserRole,
+};
+use crate::schema::{permissions, role_permissions};
+
+pub fn find_permissions_for_role(
+    conn: &mut PgConnection,
+    role: UserRole,
+) -> QueryResult<Vec<String>> {
+    role_permissions::table
+        .filter(role_permissions::role_id.eq(role as i32))
+        .select(role_permissions::permission)
+        .order(role_permissions::permission)
+        .load(conn)
+}
+
+pub fn find_all_permissions(conn: &mut PgConnection) -> QueryResult<Vec<Permission>> {
+    permissions::table
+        .order(permissions::name)
+        .select(Permission::as_select())
+        .load(conn)
+}
+
+pub fn create_permission(
+    conn: &mut PgConnection,
+    permission_data: CreatePermissionRequest,
+) -> QueryResult<Permission> {
+    diesel::insert_into(permissions::table)
+        .values(permission_data)
+        .returning(Permission::as_returning())
+        .get_result(conn)
+}
+
+pub fn update_permission(
+    conn: &mut PgConnection,
+    permission_name: &str,
+    permission_data: UpdatePermissionRequest,
+) -> QueryResult<Option<Permission>> {
+    diesel::update(permissions::table.find(permission_name))
+        .set(permission_data)
+        .returning(Permission::as_returning())
+        .get_result(conn)
+        .optional()
+}
+
+// Also removes the permission from every role.
+pub fn delete_permission(conn: &mut PgConnection, permission_name: &str) -> QueryResult<usize> {
+    diesel::delete(permissions::table.find(permission_name)).execute(conn)
+}
+
+pub fn find_all_role_permissions(
+    conn: &mut PgConnection,
+) -> QueryResult<Vec<RolePermissionsResponse>> {
+    [UserRole::Guest, UserRole::User, UserRole::Admin]
+        .into_iter()
+        .map(|role| {
+            Ok(RolePermissionsResponse {
+                role,
+                permissions: find_permissions_for_role(conn, role)?,
+            })
+        })
+        .collect()
+}
+
+pub fn grant_permission(
+    conn: &mut PgConnection,
+    role: UserRole,
+    permission_name: &str,
+) -> QueryResult<usize> {
+    diesel::insert_into(role_permissions::table)
+        .values(CreateRolePermissionDb {
+            role_id: role as i32,
+            permission: permission_name.to_string(),
+        })
+        .on_conflict_do_nothing()
+        .execute(conn)
+}
+
+pub fn revoke_permission(
+    conn: &mut PgConnection,
+    role: UserRole,
+    permission_name: &str,
+) -> QueryResult<usize> {
+    diesel::delete(role_permissions::table.find((role as i32, permission_name))).execute(conn)
+}
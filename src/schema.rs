@@ -1,5 +1,48 @@
 // @generated automatically by Diesel CLI.
 
+pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "tsvector", schema = "pg_catalog"))]
+    pub struct Tsvector;
+}
+
+diesel::table! {
+    api_keys (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        name -> Varchar,
+        prefix -> Varchar,
+        key_hash -> Varchar,
+        scopes -> Varchar,
+        expires_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    email_verification_tokens (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        token_hash -> Varchar,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    impersonations (id) {
+        id -> Uuid,
+        actor_id -> Uuid,
+        user_id -> Uuid,
+        reason -> Varchar,
+        token_id -> Uuid,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     lists (id) {
         id -> Uuid,
@@ -13,6 +56,152 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    mfa_recovery_codes (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        code_hash -> Varchar,
+        used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    oauth_authorization_codes (id) {
+        id -> Uuid,
+        code_hash -> Varchar,
+        client_id -> Uuid,
+        user_id -> Uuid,
+        redirect_uri -> Varchar,
+        scope -> Varchar,
+        code_challenge -> Varchar,
+        nonce -> Nullable<Varchar>,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    oauth_clients (id) {
+        id -> Uuid,
+        client_id -> Varchar,
+        client_secret_hash -> Nullable<Varchar>,
+        name -> Varchar,
+        redirect_uris -> Varchar,
+        scopes -> Varchar,
+        grant_types -> Varchar,
+        first_party -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    oauth_consents (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        client_id -> Uuid,
+        scopes -> Varchar,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    oidc_login_states (id) {
+        id -> Uuid,
+        state_hash -> Varchar,
+        provider -> Varchar,
+        nonce -> Varchar,
+        code_verifier -> Varchar,
+        expires_at -> Timestamp,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    password_reset_tokens (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        token_hash -> Varchar,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    permissions (name) {
+        name -> Varchar,
+        description -> Nullable<Text>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    refresh_tokens (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        family_id -> Uuid,
+        token_hash -> Varchar,
+        expires_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+        replaced_by -> Nullable<Uuid>,
+        created_at -> Timestamp,
+        client_id -> Nullable<Uuid>,
+        scope -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    revoked_tokens (jti) {
+        jti -> Uuid,
+        user_id -> Uuid,
+        expires_at -> Timestamp,
+        revoked_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    role_permissions (role_id, permission) {
+        role_id -> Int4,
+        permission -> Varchar,
+    }
+}
+
+diesel::table! {
+    roles (id) {
+        id -> Int4,
+        name -> Varchar,
+    }
+}
+
+diesel::table! {
+    sessions (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        device -> Nullable<Varchar>,
+        user_agent -> Nullable<Varchar>,
+        ip_address -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        last_seen_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    signing_keys (kid) {
+        kid -> Varchar,
+        algorithm -> Varchar,
+        private_key -> Text,
+        public_key -> Nullable<Text>,
+        status -> Varchar,
+        created_at -> Timestamp,
+        activated_at -> Nullable<Timestamp>,
+        retired_at -> Nullable<Timestamp>,
+    }
+}
+
 diesel::table! {
     subtask_mapping (task_id, dependent_id) {
         task_id -> Uuid,
@@ -46,6 +235,38 @@ diesel::table! {
 }
 
 diesel::table! {
+    totp_credentials (user_id) {
+        user_id -> Uuid,
+        secret -> Varchar,
+        confirmed_at -> Nullable<Timestamp>,
+        last_used_step -> Nullable<Int8>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    user_identities (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        provider -> Varchar,
+        subject -> Varchar,
+        email -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        last_login_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    user_token_revocations (user_id) {
+        user_id -> Uuid,
+        revoked_before -> Timestamp,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::Tsvector;
+
     users (id) {
         id -> Uuid,
         username -> Varchar,
@@ -55,18 +276,89 @@ diesel::table! {
         role -> Int4,
         updated_at -> Timestamp,
         created_at -> Timestamp,
+        email_verified_at -> Nullable<Timestamp>,
+        failed_login_attempts -> Int4,
+        locked_until -> Nullable<Timestamp>,
+        search_vector -> Tsvector,
+        last_failed_login_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    webauthn_challenges (id) {
+        id -> Uuid,
+        user_id -> Nullable<Uuid>,
+        challenge_hash -> Varchar,
+        ceremony -> Varchar,
+        expires_at -> Timestamp,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    webauthn_credentials (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        credential_id -> Varchar,
+        public_key -> Bytea,
+        algorithm -> Int4,
+        sign_count -> Int8,
+        name -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        last_used_at -> Nullable<Timestamp>,
     }
 }
 
+diesel::joinable!(api_keys -> users (user_id));
+diesel::joinable!(email_verification_tokens -> users (user_id));
 diesel::joinable!(lists -> users (user_id));
+diesel::joinable!(mfa_recovery_codes -> users (user_id));
+diesel::joinable!(oauth_authorization_codes -> oauth_clients (client_id));
+diesel::joinable!(oauth_authorization_codes -> users (user_id));
+diesel::joinable!(oauth_consents -> oauth_clients (client_id));
+diesel::joinable!(oauth_consents -> users (user_id));
+diesel::joinable!(password_reset_tokens -> users (user_id));
+diesel::joinable!(refresh_tokens -> oauth_clients (client_id));
+diesel::joinable!(refresh_tokens -> users (user_id));
+diesel::joinable!(revoked_tokens -> users (user_id));
+diesel::joinable!(role_permissions -> permissions (permission));
+diesel::joinable!(role_permissions -> roles (role_id));
+diesel::joinable!(sessions -> users (user_id));
 diesel::joinable!(task_list_mapping -> lists (list_id));
 diesel::joinable!(task_list_mapping -> tasks (task_id));
 diesel::joinable!(tasks -> users (user_id));
+diesel::joinable!(totp_credentials -> users (user_id));
+diesel::joinable!(user_identities -> users (user_id));
+diesel::joinable!(user_token_revocations -> users (user_id));
+diesel::joinable!(users -> roles (role));
+diesel::joinable!(webauthn_challenges -> users (user_id));
+diesel::joinable!(webauthn_credentials -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    api_keys,
+    email_verification_tokens,
+    impersonations,
     lists,
+    mfa_recovery_codes,
+    oauth_authorization_codes,
+    oauth_clients,
+    oauth_consents,
+    oidc_login_states,
+    password_reset_tokens,
+    permissions,
+    refresh_tokens,
+    revoked_tokens,
+    role_permissions,
+    roles,
+    sessions,
+    signing_keys,
     subtask_mapping,
     task_list_mapping,
     tasks,
+    totp_credentials,
+    user_identities,
+    user_token_revocations,
     users,
+    webauthn_challenges,
+    webauthn_credentials,
 );
@@ -0,0 +1,90 @@
+use actix_web::{delete, get, web, HttpResponse, Responder};
+use log::error;
+use uuid::Uuid;
+
+use crate::authentication::guard::{roles, AllowImpersonation, RequireRole};
+use crate::authentication::revocation::services::RevocationStore;
+use crate::common::model::AppError;
+use crate::database::model::sessions::SessionResponse;
+use crate::database::{model::db::DbPool, tools::get_connection};
+use crate::sessions::service::{end_session, find_active_sessions};
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(find_sessions_handler);
+    cfg.service(delete_session_handler);
+}
+
+fn user_id(sub: &str) -> Result<Uuid, AppError> {
+    Uuid::parse_str(sub).map_err(|_| AppError::UnauthorizedError("Invalid token".to_string()))
+}
+
+// Find the active sessions of the current user
+#[utoipa::path(
+    path = "/api/sessions",
+    responses(
+        (status = 200, description = "Sessions that can still be refreshed, most recently used first", body = Vec<SessionResponse>),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Not available to scoped tokens and API keys"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "findSessions"
+)]
+#[get("/sessions")]
+async fn find_sessions_handler(
+    pool: web::Data<DbPool>,
+    claims: AllowImpersonation<roles::Guest>,
+) -> Result<impl Responder, AppError> {
+    let owner_id = user_id(&claims.sub)?;
+    let mut conn = get_connection(pool);
+
+    match find_active_sessions(&mut conn, owner_id) {
+        Ok(sessions) => Ok(HttpResponse::Ok().json(
+            sessions
+                .into_iter()
+                .map(|session| SessionResponse::new(session, claims.sid))
+                .collect::<Vec<_>>(),
+        )),
+        Err(e) => {
+            error!("{:?}", e);
+            Err(AppError::DatabaseError("Internal Server Error".to_string()))
+        }
+    }
+}
+
+// Log out of a session of the current user, its access and refresh tokens stop working
+#[utoipa::path(
+    path = "/api/sessions/{id}",
+    responses(
+        (status = 200, description = "Session ended"),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Not available to scoped tokens and API keys"),
+        (status = 404, description = "Session not found"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[])),
+    operation_id = "deleteSession"
+)]
+#[delete("/sessions/{id}")]
+async fn delete_session_handler(
+    pool: web::Data<DbPool>,
+    revocation_store: web::Data<RevocationStore>,
+    session_id: web::Path<Uuid>,
+    claims: RequireRole<roles::Guest>,
+) -> Result<impl Responder, AppError> {
+    let owner_id = user_id(&claims.sub)?;
+    let session_id = session_id.into_inner();
+    let mut conn = get_connection(pool);
+
+    match end_session(&mut conn, owner_id, session_id) {
+        Ok(true) => {
+            revocation_store.revoke_session(session_id);
+            Ok(HttpResponse::Ok().finish())
+        }
+        Ok(false) => Err(AppError::NotFoundError("Session not found".to_string())),
+        Err(e) => {
+            error!("{:?}", e);
+            Err(AppError::DatabaseError("Internal Server Error".to_string()))
+        }
+    }
+}
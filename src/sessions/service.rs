@@ -0,0 +1,229 @@
+use std::net::IpAddr;
+
+use chrono::{NaiveDateTime, Utc};
+use diesel::{dsl::exists, pg::PgConnection, prelude::*, result::QueryResult};
+use uuid::Uuid;
+
+use crate::{
+    authentication::refresh::services::revoke_refresh_token_family,
+    database::model::sessions::{CreateSessionDb, Session},
+    schema::{refresh_tokens, sessions},
+};
+
+// Checked in order, Edge and Opera also mention Chrome and Chrome mentions Safari
+const BROWSERS: [(&str, &str); 7] = [
+    ("Edg/", "Edge"),
+    ("OPR/", "Opera"),
+    ("Firefox/", "Firefox"),
+    ("Chrome/", "Chrome"),
+    ("Safari/", "Safari"),
+    ("curl/", "curl"),
+    ("PostmanRuntime/", "Postman"),
+];
+
+// Android and iOS user agents also mention Linux and Mac OS X
+const OPERATING_SYSTEMS: [(&str, &str); 6] = [
+    ("Android", "Android"),
+    ("iPhone", "iOS"),
+    ("iPad", "iPadOS"),
+    ("Windows", "Windows"),
+    ("Mac OS X", "macOS"),
+    ("Linux", "Linux"),
+];
+
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+// A readable name for the device, e.g. "Firefox on Linux", good enough to tell sessions apart
+pub fn describe_device(user_agent: &str) -> Option<String> {
+    let find = |candidates: &[(&str, &'static str)]| {
+        candidates
+            .iter()
+            .find(|(marker, _)| user_agent.contains(marker))
+            .map(|(_, name)| *name)
+    };
+
+    match (find(&BROWSERS), find(&OPERATING_SYSTEMS)) {
+        (Some(browser), Some(os)) => Some(format!("{} on {}", browser, os)),
+        (Some(name), None) | (None, Some(name)) => Some(name.to_string()),
+        (None, None) => None,
+    }
+}
+
+pub fn start_session(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    user_agent: Option<&str>,
+    ip_address: Option<IpAddr>,
+) -> QueryResult<Session> {
+    let new_session = CreateSessionDb {
+        user_id,
+        device: user_agent.and_then(describe_device),
+        user_agent: user_agent.map(str::to_string),
+        ip_address: ip_address.map(|ip| ip.to_string()),
+    };
+
+    diesel::insert_into(sessions::table)
+        .values(new_session)
+        .returning(Session::as_returning())
+        .get_result(conn)
+}
+
+// Called whenever the session's tokens are refreshed
+pub fn touch_session(conn: &mut PgConnection, session_id: Uuid) -> QueryResult<usize> {
+    diesel::update(sessions::table.find(session_id))
+        .set(sessions::last_seen_at.eq(now()))
+        .execute(conn)
+}
+
+// Sessions that can still be refreshed. Logging out of every session or resetting the
+// password revokes the refresh tokens, which ends the sessions as well.
+pub fn find_active_sessions(conn: &mut PgConnection, user_id: Uuid) -> QueryResult<Vec<Session>> {
+    sessions::table
+        .filter(sessions::user_id.eq(user_id))
+        .filter(sessions::revoked_at.is_null())
+        .filter(exists(
+            refresh_tokens::table
+                .filter(refresh_tokens::family_id.eq(sessions::id))
+                .filter(refresh_tokens::revoked_at.is_null())
+                .filter(refresh_tokens::expires_at.gt(now())),
+        ))
+        .order(sessions::last_seen_at.desc())
+        .select(Session::as_select())
+        .load(conn)
+}
+
+// End a session of the user along with its refresh tokens. Its access tokens are
+// rejected once the revocation store knows about it.
+pub fn end_session(conn: &mut PgConnection, user_id: Uuid, session_id: Uuid) -> QueryResult<bool> {
+    conn.transaction(|conn| {
+        let ended = diesel::update(
+            sessions::table
+                .filter(sessions::id.eq(session_id))
+                .filter(sessions::user_id.eq(user_id))
+                .filter(sessions::revoked_at.is_null()),
+        )
+        .set(sessions::revoked_at.eq(now()))
+        .execute(conn)?;
+        if ended == 0 {
+            return Ok(false);
+        }
+
+        revoke_refresh_token_family(conn, session_id)?;
+        Ok(true)
+    })
+}
+
+// End every open session of the user and return their ids. The caller revokes the
+// refresh tokens.
+pub fn end_all_sessions(conn: &mut PgConnection, user_id: Uuid) -> QueryResult<Vec<Uuid>> {
+    diesel::update(
+        sessions::table
+            .filter(sessions::user_id.eq(user_id))
+            .filter(sessions::revoked_at.is_null()),
+    )
+    .set(sessions::revoked_at.eq(now()))
+    .returning(sessions::id)
+    .get_results(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        authentication::refresh::services::issue_refresh_token,
+        database::{
+            model::users::UserRole,
+            tools::testing::{create_test_user, test_pool},
+        },
+        users::service::delete_user,
+    };
+
+    #[test]
+    fn describes_common_user_agents() {
+        let firefox =
+            "Mozilla/5.0 (X11; Ubuntu; Linux x86_64; rv:121.0) Gecko/20100101 Firefox/121.0";
+        let edge = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36 Edg/120.0.0.0";
+        let iphone = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_2 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.2 Mobile/15E148 Safari/604.1";
+        let android = "Mozilla/5.0 (Linux; Android 14) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Mobile Safari/537.36";
+
+        assert_eq!(
+            describe_device(firefox).as_deref(),
+            Some("Firefox on Linux")
+        );
+        assert_eq!(describe_device(edge).as_deref(), Some("Edge on Windows"));
+        assert_eq!(describe_device(iphone).as_deref(), Some("Safari on iOS"));
+        assert_eq!(
+            describe_device(android).as_deref(),
+            Some("Chrome on Android")
+        );
+        assert_eq!(describe_device("curl/8.5.0").as_deref(), Some("curl"));
+        assert_eq!(describe_device("my-script"), None);
+    }
+
+    fn start_test_session(conn: &mut PgConnection, user_id: Uuid) -> Session {
+        let session = start_session(conn, user_id, Some("curl/8.5.0"), None).unwrap();
+        issue_refresh_token(conn, user_id, Some(session.id)).unwrap();
+        session
+    }
+
+    #[test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    fn ended_sessions_are_no_longer_active() {
+        let pool = test_pool();
+        let user = create_test_user(&pool, UserRole::User);
+        let other = create_test_user(&pool, UserRole::User);
+        let mut conn = pool.get().unwrap();
+
+        let ended = start_test_session(&mut conn, user.id);
+        let kept = start_test_session(&mut conn, user.id);
+        // Without a refresh token the session can't be continued
+        start_session(&mut conn, user.id, None, None).unwrap();
+        let active_before = find_active_sessions(&mut conn, user.id).unwrap();
+
+        let by_other = end_session(&mut conn, other.id, ended.id).unwrap();
+        let by_owner = end_session(&mut conn, user.id, ended.id).unwrap();
+        let again = end_session(&mut conn, user.id, ended.id).unwrap();
+        let active_after = find_active_sessions(&mut conn, user.id).unwrap();
+        let live_refresh_tokens = refresh_tokens::table
+            .filter(refresh_tokens::family_id.eq(ended.id))
+            .filter(refresh_tokens::revoked_at.is_null())
+            .count()
+            .get_result::<i64>(&mut conn)
+            .unwrap();
+        delete_user(&mut conn, user.id).unwrap();
+        delete_user(&mut conn, other.id).unwrap();
+
+        assert_eq!(active_before.len(), 2);
+        assert!(!by_other);
+        assert!(by_owner);
+        assert!(!again);
+        assert_eq!(
+            active_after
+                .iter()
+                .map(|session| session.id)
+                .collect::<Vec<_>>(),
+            vec![kept.id]
+        );
+        assert_eq!(live_refresh_tokens, 0);
+    }
+
+    #[test]
+    #[ignore = "requires TEST_DATABASE_URL"]
+    fn ending_all_sessions_returns_the_open_ones() {
+        let pool = test_pool();
+        let user = create_test_user(&pool, UserRole::User);
+        let mut conn = pool.get().unwrap();
+
+        let first = start_test_session(&mut conn, user.id);
+        let second = start_test_session(&mut conn, user.id);
+        end_session(&mut conn, user.id, first.id).unwrap();
+        let ended = end_all_sessions(&mut conn, user.id).unwrap();
+        let active = find_active_sessions(&mut conn, user.id).unwrap();
+        delete_user(&mut conn, user.id).unwrap();
+
+        assert_eq!(ended, vec![second.id]);
+        assert!(active.is_empty());
+    }
+}
@@ -2,22 +2,34 @@ use std::collections::HashMap;
 
 use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
 use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::Connection;
+use log::error;
 use uuid::Uuid;
 
+use crate::authentication::guard::{permissions, RequirePermission};
 use crate::authentication::model::Claims;
-use crate::authentication::service::authenticate_user_role;
+use crate::authentication::registration::services::{send_verification_email, RegistrationError};
+use crate::authentication::service::{
+    authorize_email_lookup, authorize_user_create, authorize_user_delete, authorize_user_list,
+    authorize_user_update, can_look_up_emails, user_view,
+};
+use crate::common::mailer::Mailer;
 use crate::common::model::AppError;
-use crate::database::model::users::{CreateUserRequest, UpdateUserRequest};
+use crate::common::password_policy::validate_password;
+use crate::database::model::users::{
+    CreateUserRequest, UpdateUserRequest, UserListQuery, UserSearchQuery,
+};
 use crate::database::{model::db::DbPool, tools::get_connection};
 use crate::users::service::update_user;
 use crate::users::service::{
-    create_user, delete_user, find_all_users, find_user_by_email, find_user_by_id,
-    find_user_by_username,
+    create_user, delete_user, find_user_by_email, find_user_by_id, find_user_by_username,
+    find_users, search_users, UserListError, UserPage,
 };
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(find_user_handler);
     cfg.service(find_all_users_handler);
+    cfg.service(search_users_handler);
 
     cfg.service(create_user_handler);
     cfg.service(update_user_handler);
@@ -29,44 +41,46 @@ pub fn config(cfg: &mut web::ServiceConfig) {
     path = "/api/user",
     params(
         ("id" = Option<Uuid>, Query, description = "User ID"),
-        ("email" = Option<String>, Query, description = "User email"),
+        ("email" = Option<String>, Query, description = "User email, admins only"),
         ("username" = Option<String>, Query, description = "User username")
     ),
     responses(
-        (status = 200, description = "Successful response", body = User),
+        (status = 200, description = "The account, with the fields the caller may see: admins get AdminUserResponse, the owner UserResponse and anyone else PublicUserResponse", body = UserView),
         (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Missing permission, or a lookup by email by a non-admin"),
         (status = 500, description = "Internal Server Error")
     ),
-    security(("token_jwt"=[])),
+    security(("token_jwt"=[]), ("api_key"=[])),
     operation_id = "findUser",
 )]
 #[get("/user")]
 async fn find_user_handler(
     pool: web::Data<DbPool>,
     params: web::Query<HashMap<String, String>>,
-    claims: web::ReqData<Claims>,
+    claims: RequirePermission<permissions::UsersRead>,
 ) -> Result<impl Responder, AppError> {
-    authenticate_user_role(&claims)?;
     let mut conn = get_connection(pool);
 
     if let Some(user_id) = params.get("id") {
         match Uuid::parse_str(user_id) {
             Ok(id) => match find_user_by_id(&mut conn, id) {
-                Ok(Some(user)) => Ok(HttpResponse::Ok().json(user)),
+                Ok(Some(user)) => Ok(HttpResponse::Ok().json(user_view(&claims, user))),
                 Ok(None) => Err(AppError::NotFoundError("User not found".to_string())),
                 Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
             },
             Err(_) => Err(AppError::ValidationError("Invalid user id".to_string())),
         }
     } else if let Some(email) = params.get("email") {
+        // Otherwise a 404 would tell whether the address is registered
+        authorize_email_lookup(&claims)?;
         match find_user_by_email(&mut conn, email) {
-            Ok(Some(user)) => Ok(HttpResponse::Ok().json(user)),
+            Ok(Some(user)) => Ok(HttpResponse::Ok().json(user_view(&claims, user))),
             Ok(None) => Err(AppError::NotFoundError("User not found".to_string())),
             Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
         }
     } else if let Some(username) = params.get("username") {
         match find_user_by_username(&mut conn, username) {
-            Ok(Some(user)) => Ok(HttpResponse::Ok().json(user)),
+            Ok(Some(user)) => Ok(HttpResponse::Ok().json(user_view(&claims, user))),
             Ok(None) => Err(AppError::NotFoundError("User not found".to_string())),
             Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
         }
@@ -80,26 +94,95 @@ async fn find_user_handler(
 // Find all users handler
 #[utoipa::path(
     path = "/api/users",
+    params(UserListQuery),
     responses(
-        (status = 200, description = "Successful response", body = Vec<User>),
+        (
+            status = 200,
+            description = "A page of accounts, with the fields the caller may see of each",
+            body = Vec<UserView>,
+            headers(
+                ("X-Total-Count" = i64, description = "Number of accounts matching the filters across all pages"),
+                ("X-Next-Cursor" = String, description = "Cursor of the next page, missing on the last page")
+            )
+        ),
+        (status = 400, description = "Invalid limit, offset, cursor or filter"),
         (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Missing permission, or filtering or sorting by email without the admin role"),
         (status = 500, description = "Internal Server Error")
     ),
-    security(("token_jwt"=[])),
+    security(("token_jwt"=[]), ("api_key"=[])),
     operation_id = "findAllUsers"
 )]
 #[get("/users")]
 async fn find_all_users_handler(
     pool: web::Data<DbPool>,
-    claims: web::ReqData<Claims>,
+    params: web::Query<UserListQuery>,
+    claims: RequirePermission<permissions::UsersRead>,
 ) -> Result<impl Responder, AppError> {
-    authenticate_user_role(&claims)?; // Perform authentication check
+    authorize_user_list(&claims, &params)?;
+    let mut conn = get_connection(pool);
 
+    user_page_response(&claims, find_users(&mut conn, &params))
+}
+
+// Search users handler
+#[utoipa::path(
+    path = "/api/users/search",
+    params(UserSearchQuery),
+    responses(
+        (
+            status = 200,
+            description = "A page of matching accounts, best matches first, with the fields the caller may see of each. Only admins' searches match email addresses.",
+            body = Vec<UserView>,
+            headers(
+                ("X-Total-Count" = i64, description = "Number of matching accounts across all pages")
+            )
+        ),
+        (status = 400, description = "Invalid limit or offset, or a search without letters or digits"),
+        (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Missing permission"),
+        (status = 500, description = "Internal Server Error")
+    ),
+    security(("token_jwt"=[]), ("api_key"=[])),
+    operation_id = "searchUsers"
+)]
+#[get("/users/search")]
+async fn search_users_handler(
+    pool: web::Data<DbPool>,
+    params: web::Query<UserSearchQuery>,
+    claims: RequirePermission<permissions::UsersRead>,
+) -> Result<impl Responder, AppError> {
     let mut conn = get_connection(pool);
 
-    match find_all_users(&mut conn) {
-        Ok(users) => Ok(HttpResponse::Ok().json(users)),
-        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    let with_emails = can_look_up_emails(&claims);
+    user_page_response(&claims, search_users(&mut conn, &params, with_emails))
+}
+
+fn user_page_response(
+    claims: &Claims,
+    result: Result<UserPage, UserListError>,
+) -> Result<HttpResponse, AppError> {
+    match result {
+        Ok(page) => {
+            let mut response = HttpResponse::Ok();
+            response.insert_header(("X-Total-Count", page.total.to_string()));
+            if let Some(next_cursor) = page.next_cursor {
+                response.insert_header(("X-Next-Cursor", next_cursor));
+            }
+            Ok(response.json(
+                page.users
+                    .into_iter()
+                    .map(|user| user_view(claims, user))
+                    .collect::<Vec<_>>(),
+            ))
+        }
+        Err(UserListError::Validation(message)) => {
+            Err(AppError::ValidationError(message.to_string()))
+        }
+        Err(UserListError::Database(e)) => {
+            error!("{:?}", e);
+            Err(AppError::DatabaseError("Internal Server Error".to_string()))
+        }
     }
 }
 
@@ -108,25 +191,28 @@ async fn find_all_users_handler(
     path = "/api/user",
     request_body = CreateUserRequest,
     responses(
-        (status = 200, description = "User created successfully", body = User),
-        (status = 400, description = "User already exists"),
+        (status = 200, description = "User created successfully", body = UserView),
+        (status = 400, description = "User already exists or the password doesn't meet the password policy", body = PasswordPolicyErrorResponse),
         (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Missing permission or role above your own"),
         (status = 500, description = "Internal Server Error")
     ),
-    security(("token_jwt"=[])),
+    security(("token_jwt"=[]), ("api_key"=[])),
     operation_id = "createUser")
 ]
 #[post("/user")]
 async fn create_user_handler(
     pool: web::Data<DbPool>,
     req_body: web::Json<CreateUserRequest>,
-    claims: web::ReqData<Claims>,
+    claims: RequirePermission<permissions::UsersWrite>,
 ) -> Result<impl Responder, AppError> {
-    authenticate_user_role(&claims)?;
+    authorize_user_create(&claims, &req_body)?;
+    validate_password(&req_body.password, &[&req_body.username, &req_body.email])
+        .map_err(AppError::PasswordPolicyError)?;
     let mut conn = get_connection(pool);
 
     match create_user(&mut conn, req_body.into_inner()) {
-        Ok(user) => Ok(HttpResponse::Ok().json(user)),
+        Ok(user) => Ok(HttpResponse::Ok().json(user_view(&claims, user))),
         Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
             Err(AppError::ValidationError("User already exists".to_string()))
         }
@@ -142,27 +228,53 @@ async fn create_user_handler(
     path = "/api/user/{user_id}",
     request_body = UpdateUserRequest,
     responses(
-        (status = 200, description = "User updated successfully", body = User),
+        (status = 200, description = "User updated successfully. A new email address has to be verified again, a verification token is mailed to it.", body = UserView),
+        (status = 400, description = "The password doesn't meet the password policy", body = PasswordPolicyErrorResponse),
         (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Missing permission, another user's account or a role change by a non-admin"),
+        (status = 404, description = "User not found"),
         (status = 500, description = "Internal Server Error")
     ),
-    security(("token_jwt"=[])),
+    security(("token_jwt"=[]), ("api_key"=[])),
     operation_id = "updateUserDetails"
 )]
 #[put("/user/{user_id}")]
 async fn update_user_handler(
     pool: web::Data<DbPool>,
+    mailer: web::Data<dyn Mailer>,
     user_id: web::Path<Uuid>,
     req_body: web::Json<UpdateUserRequest>,
-    claims: web::ReqData<Claims>,
+    claims: RequirePermission<permissions::UsersWrite>,
 ) -> Result<impl Responder, AppError> {
-    authenticate_user_role(&claims)?;
-
+    authorize_user_update(&claims, *user_id, &req_body)?;
     let mut conn = get_connection(pool);
 
-    match update_user(&mut conn, *user_id, req_body.into_inner()) {
-        Ok(user) => Ok(HttpResponse::Ok().json(user)),
-        Err(_) => Err(AppError::DatabaseError("Internal Server Error".to_string())),
+    if let Some(password) = &req_body.password {
+        let user = match find_user_by_id(&mut conn, *user_id) {
+            Ok(Some(user)) => user,
+            Ok(None) => return Err(AppError::NotFoundError("User not found".to_string())),
+            Err(_) => return Err(AppError::DatabaseError("Internal Server Error".to_string())),
+        };
+        let username = req_body.username.as_deref().unwrap_or(&user.username);
+        let email = req_body.email.as_deref().unwrap_or(&user.email);
+        validate_password(password, &[username, email]).map_err(AppError::PasswordPolicyError)?;
+    }
+
+    let changes_email = req_body.email.is_some();
+    let result = conn.transaction(|conn| {
+        let user = update_user(conn, *user_id, req_body.into_inner())?;
+        if changes_email && user.email_verified_at.is_none() {
+            send_verification_email(conn, mailer.get_ref(), &user)?;
+        }
+        Ok::<_, RegistrationError>(user)
+    });
+
+    match result {
+        Ok(user) => Ok(HttpResponse::Ok().json(user_view(&claims, user))),
+        Err(e) => {
+            error!("{:?}", e);
+            Err(AppError::DatabaseError("Internal Server Error".to_string()))
+        }
     }
 }
 
@@ -172,19 +284,19 @@ async fn update_user_handler(
     responses(
         (status = 200, description = "User deleted successfully"),
         (status = 401, description = "Missing or invalid authentication"),
+        (status = 403, description = "Missing permission or another user's account"),
         (status = 500, description = "Internal Server Error")
     ),
-    security(("token_jwt"=[])),
+    security(("token_jwt"=[]), ("api_key"=[])),
     operation_id = "deleteUserById"
 )]
 #[delete("/user/{user_id}")]
 async fn delete_user_handler(
     pool: web::Data<DbPool>,
     user_id: web::Path<Uuid>,
-    claims: web::ReqData<Claims>,
+    claims: RequirePermission<permissions::UsersDelete>,
 ) -> Result<impl Responder, AppError> {
-    authenticate_user_role(&claims)?;
-
+    authorize_user_delete(&claims, *user_id)?;
     let mut conn = get_connection(pool);
 
     match delete_user(&mut conn, *user_id) {
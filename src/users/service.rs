@@ -1,17 +1,29 @@
+use crate::common::password::hash_password;
 use crate::database::model::users::{
-    CreateUserDb, CreateUserRequest, UpdateUserDb, UpdateUserRequest, User,
+    CreateUserDb, CreateUserRequest, SortOrder, UpdateUserDb, UpdateUserRequest, User,
+    UserListQuery, UserSearchQuery, UserSort,
 };
 use crate::schema::users as users_schema;
 use crate::schema::users::{self, dsl::*};
 
-use bcrypt::{hash, DEFAULT_COST};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::NaiveDateTime;
+use diesel::pg::Pg;
 use diesel::result::Error;
-use diesel::ExpressionMethods;
+use diesel::sql_types::{BigInt, Text};
 use diesel::{pg::PgConnection, result::QueryResult, OptionalExtension, QueryDsl, RunQueryDsl};
+use diesel::{BoolExpressionMethods, ExpressionMethods, PgTextExpressionMethods};
+use diesel::{Connection, QueryableByName, SelectableHelper};
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+// Microseconds, the precision Postgres stores timestamps with
+const CURSOR_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.6f";
+
 pub fn create_user(conn: &mut PgConnection, user_data: CreateUserRequest) -> QueryResult<User> {
-    let hash = match hash(user_data.password, DEFAULT_COST) {
+    let hash = match hash_password(&user_data.password) {
         Ok(hashed) => hashed,
         Err(_) => return Err(Error::QueryBuilderError("Password hashing failed".into())),
     };
@@ -24,17 +36,23 @@ pub fn create_user(conn: &mut PgConnection, user_data: CreateUserRequest) -> Que
     };
     diesel::insert_into(users::table)
         .values(new_user)
+        .returning(User::as_returning())
         .get_result(conn)
 }
 
 pub fn find_user_by_id(conn: &mut PgConnection, user_id: Uuid) -> QueryResult<Option<User>> {
-    users.find(user_id).first(conn).optional()
+    users
+        .find(user_id)
+        .select(User::as_select())
+        .first(conn)
+        .optional()
 }
 
 pub fn find_user_by_email(conn: &mut PgConnection, user_email: &str) -> QueryResult<Option<User>> {
     users
         .filter(users_schema::email.eq(user_email))
-        .first::<User>(conn)
+        .select(User::as_select())
+        .first(conn)
         .optional()
 }
 
@@ -44,12 +62,192 @@ pub fn find_user_by_username(
 ) -> QueryResult<Option<User>> {
     users
         .filter(users_schema::username.eq(user_username))
-        .first::<User>(conn)
+        .select(User::as_select())
+        .first(conn)
         .optional()
 }
 
-pub fn find_all_users(conn: &mut PgConnection) -> QueryResult<Vec<User>> {
-    users.load::<User>(conn)
+#[derive(Debug)]
+pub enum UserListError {
+    Validation(&'static str),
+    Database(diesel::result::Error),
+}
+
+impl From<diesel::result::Error> for UserListError {
+    fn from(e: diesel::result::Error) -> Self {
+        UserListError::Database(e)
+    }
+}
+
+pub struct UserPage {
+    pub users: Vec<User>,
+    // Number of users matching the filters across all pages
+    pub total: i64,
+    // Set when there are more users after this page
+    pub next_cursor: Option<String>,
+}
+
+// The sort key and id of the last user on a page, opaque to clients
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Cursor {
+    sort: UserSort,
+    value: String,
+    id: Uuid,
+}
+
+fn encode_cursor(cursor: &Cursor) -> String {
+    URL_SAFE_NO_PAD.encode(serde_json::to_vec(cursor).unwrap_or_default())
+}
+
+fn decode_cursor(cursor: &str, sort: UserSort) -> Result<Cursor, UserListError> {
+    URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|json| serde_json::from_slice::<Cursor>(&json).ok())
+        .filter(|cursor| cursor.sort == sort)
+        .ok_or(UserListError::Validation("Invalid cursor"))
+}
+
+fn sort_value(user: &User, sort: UserSort) -> String {
+    match sort {
+        UserSort::CreatedAt => user.created_at.format(CURSOR_TIME_FORMAT).to_string(),
+        UserSort::Username => user.username.clone(),
+        UserSort::Email => user.email.clone(),
+    }
+}
+
+// Matches the text anywhere, with LIKE wildcards in it taken literally
+fn contains_pattern(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+fn filtered_users(params: &UserListQuery) -> users::BoxedQuery<'static, Pg> {
+    let mut query = users::table.into_boxed();
+    if let Some(filter_role) = params.role {
+        query = query.filter(users_schema::role.eq(filter_role as i32));
+    }
+    if let Some(filter_timezone) = &params.timezone {
+        query = query.filter(users_schema::timezone.eq(filter_timezone.clone()));
+    }
+    if let Some(created_after) = params.created_after {
+        query = query.filter(users_schema::created_at.ge(created_after));
+    }
+    if let Some(created_before) = params.created_before {
+        query = query.filter(users_schema::created_at.lt(created_before));
+    }
+    if let Some(filter_username) = &params.username {
+        query = query.filter(users_schema::username.ilike(contains_pattern(filter_username)));
+    }
+    if let Some(filter_email) = &params.email {
+        query = query.filter(users_schema::email.ilike(contains_pattern(filter_email)));
+    }
+    query
+}
+
+fn page_size(limit: Option<i64>, offset: Option<i64>) -> Result<i64, UserListError> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(UserListError::Validation("limit must be between 1 and 100"));
+    }
+    if offset.is_some_and(|offset| offset < 0) {
+        return Err(UserListError::Validation("offset can't be negative"));
+    }
+    Ok(limit)
+}
+
+// Keyset pagination on the sort column, with the id breaking ties between equal values
+macro_rules! sorted_page {
+    ($query:expr, $column:expr, $after:expr, $order:expr) => {{
+        let mut query = $query;
+        if let Some((value, last_id)) = $after {
+            query = match $order {
+                SortOrder::Asc => query.filter(
+                    $column
+                        .gt(value.clone())
+                        .or($column.eq(value).and(users_schema::id.gt(last_id))),
+                ),
+                SortOrder::Desc => query.filter(
+                    $column
+                        .lt(value.clone())
+                        .or($column.eq(value).and(users_schema::id.lt(last_id))),
+                ),
+            };
+        }
+        match $order {
+            SortOrder::Asc => query.order(($column.asc(), users_schema::id.asc())),
+            SortOrder::Desc => query.order(($column.desc(), users_schema::id.desc())),
+        }
+    }};
+}
+
+pub fn find_users(
+    conn: &mut PgConnection,
+    params: &UserListQuery,
+) -> Result<UserPage, UserListError> {
+    let limit = page_size(params.limit, params.offset)?;
+    if params.offset.is_some() && params.cursor.is_some() {
+        return Err(UserListError::Validation(
+            "Use either offset or cursor, not both",
+        ));
+    }
+    let after = match &params.cursor {
+        Some(cursor) => Some(decode_cursor(cursor, params.sort)?),
+        None => None,
+    };
+
+    let total = filtered_users(params).count().get_result::<i64>(conn)?;
+
+    let query = filtered_users(params);
+    let query = match params.sort {
+        UserSort::CreatedAt => {
+            let after = match after {
+                Some(cursor) => Some((
+                    NaiveDateTime::parse_from_str(&cursor.value, CURSOR_TIME_FORMAT)
+                        .map_err(|_| UserListError::Validation("Invalid cursor"))?,
+                    cursor.id,
+                )),
+                None => None,
+            };
+            sorted_page!(query, users_schema::created_at, after, params.order)
+        }
+        UserSort::Username => {
+            let after = after.map(|cursor| (cursor.value, cursor.id));
+            sorted_page!(query, users_schema::username, after, params.order)
+        }
+        UserSort::Email => {
+            let after = after.map(|cursor| (cursor.value, cursor.id));
+            sorted_page!(query, users_schema::email, after, params.order)
+        }
+    };
+
+    // One extra row tells whether there is a next page
+    let mut page = query
+        .offset(params.offset.unwrap_or(0))
+        .limit(limit + 1)
+        .select(User::as_select())
+        .load(conn)?;
+    let next_cursor = if page.len() as i64 > limit {
+        page.truncate(limit as usize);
+        page.last().map(|last| {
+            encode_cursor(&Cursor {
+                sort: params.sort,
+                value: sort_value(last, params.sort),
+                id: last.id,
+            })
+        })
+    } else {
+        None
+    };
+
+    Ok(UserPage {
+        users: page,
+        total,
+        next_cursor,
+    })
 }
 
 pub fn update_user(
@@ -60,7 +258,7 @@ pub fn update_user(
     let user_update = UpdateUserDb {
         username: user_data.username.clone(),
         hashed_password: match &user_data.password {
-            Some(password) => match hash(password, DEFAULT_COST) {
+            Some(password) => match hash_password(password) {
                 Ok(hashed) => Some(hashed),
                 Err(_) => return Err(Error::QueryBuilderError("Password hashing failed".into())),
             },
@@ -68,17 +266,161 @@ pub fn update_user(
         },
         email: user_data.email.clone(),
         timezone: user_data.timezone.clone(),
-        role: match &user_data.role {
-            Some(new_role) => Some(*new_role as i32),
-            None => None,
-        },
+        role: user_data.role.map(|new_role| new_role as i32),
     };
 
-    diesel::update(users.find(user_id))
-        .set(&user_update)
-        .get_result(conn)
+    conn.transaction(|conn| {
+        let previous_email = users
+            .find(user_id)
+            .select(users_schema::email)
+            .for_update()
+            .first::<String>(conn)?;
+        let user = diesel::update(users.find(user_id))
+            .set(&user_update)
+            .returning(User::as_returning())
+            .get_result(conn)?;
+        if user.email == previous_email {
+            return Ok(user);
+        }
+
+        // A new address has to be verified again, otherwise it would count as verified for
+        // OpenID Connect logins of whoever really owns it
+        diesel::update(users.find(user_id))
+            .set(users_schema::email_verified_at.eq(None::<NaiveDateTime>))
+            .returning(User::as_returning())
+            .get_result(conn)
+    })
 }
 
 pub fn delete_user(conn: &mut PgConnection, user_id: Uuid) -> QueryResult<usize> {
     diesel::delete(users.find(user_id)).execute(conn)
 }
+
+// Every word of the search as a prefix, so "jan doe" finds jane.doe@example.com. Anything
+// but letters and digits separates words, which also keeps tsquery operators out.
+fn prefix_tsquery(search: &str) -> Option<String> {
+    let words: Vec<String> = search
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("{}:*", word.to_lowercase()))
+        .collect();
+    if words.is_empty() {
+        None
+    } else {
+        Some(words.join(" & "))
+    }
+}
+
+// Where a search looks and how its matches are ranked. Full text matches are ranked by
+// ts_rank and typos (pg_trgm's % operator) by their trigram similarity.
+struct SearchFields {
+    condition: &'static str,
+    rank: &'static str,
+}
+
+// Usernames and email addresses, for callers that can see the addresses
+const USER_SEARCH: SearchFields = SearchFields {
+    condition: "search_vector @@ to_tsquery('simple', $1) OR username % $2 OR email % $2",
+    rank: "ts_rank(search_vector, to_tsquery('simple', $1)) \
+        + greatest(similarity(username, $2), similarity(email, $2))",
+};
+
+// Usernames only, otherwise the matches would reveal the email addresses
+const USERNAME_SEARCH: SearchFields = SearchFields {
+    condition: "to_tsvector('simple', username) @@ to_tsquery('simple', $1) OR username % $2",
+    rank: "ts_rank(to_tsvector('simple', username), to_tsquery('simple', $1)) \
+        + similarity(username, $2)",
+};
+
+#[derive(QueryableByName)]
+struct SearchCount {
+    #[diesel(sql_type = BigInt)]
+    total: i64,
+}
+
+// Users matching the search, best matches first. Email addresses are only searched when
+// with_emails is set.
+pub fn search_users(
+    conn: &mut PgConnection,
+    params: &UserSearchQuery,
+    with_emails: bool,
+) -> Result<UserPage, UserListError> {
+    let limit = page_size(params.limit, params.offset)?;
+    let search = params.q.trim();
+    let tsquery = prefix_tsquery(search).ok_or(UserListError::Validation(
+        "q must contain letters or digits",
+    ))?;
+
+    let fields = if with_emails {
+        &USER_SEARCH
+    } else {
+        &USERNAME_SEARCH
+    };
+
+    let total = diesel::sql_query(format!(
+        "SELECT count(*) AS total FROM users WHERE {}",
+        fields.condition
+    ))
+    .bind::<Text, _>(&tsquery)
+    .bind::<Text, _>(search)
+    .get_result::<SearchCount>(conn)?
+    .total;
+
+    let found = diesel::sql_query(format!(
+        "SELECT id, username, email, hashed_password, timezone, role, updated_at, created_at, \
+            email_verified_at, failed_login_attempts, locked_until \
+        FROM users WHERE {} \
+        ORDER BY {} DESC, id \
+        LIMIT $3 OFFSET $4",
+        fields.condition, fields.rank
+    ))
+    .bind::<Text, _>(&tsquery)
+    .bind::<Text, _>(search)
+    .bind::<BigInt, _>(limit)
+    .bind::<BigInt, _>(params.offset.unwrap_or(0))
+    .load::<User>(conn)?;
+
+    Ok(UserPage {
+        users: found,
+        total,
+        next_cursor: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_like_wildcards() {
+        assert_eq!(contains_pattern("jane"), "%jane%");
+        assert_eq!(contains_pattern("50%_off\\"), "%50\\%\\_off\\\\%");
+    }
+
+    #[test]
+    fn searches_every_word_as_a_prefix() {
+        assert_eq!(
+            prefix_tsquery("Jan  doe@Exam").as_deref(),
+            Some("jan:* & doe:* & exam:*")
+        );
+        assert_eq!(
+            prefix_tsquery("a & !b | c:*").as_deref(),
+            Some("a:* & b:* & c:*")
+        );
+        assert_eq!(prefix_tsquery(" @!& "), None);
+    }
+
+    #[test]
+    fn cursors_only_continue_the_same_sort() {
+        let cursor = Cursor {
+            sort: UserSort::Username,
+            value: "jane".to_string(),
+            id: Uuid::new_v4(),
+        };
+        let encoded = encode_cursor(&cursor);
+
+        assert_eq!(decode_cursor(&encoded, UserSort::Username).unwrap(), cursor);
+        assert!(decode_cursor(&encoded, UserSort::Email).is_err());
+        assert!(decode_cursor("not a cursor", UserSort::Username).is_err());
+    }
+}